@@ -0,0 +1,340 @@
+//! Playing related tracks when a guild's queue runs dry.
+//!
+//! [`Autoplay`] is a listener, so add it to the manager's [`ListenerChain`].
+//! As it needs the [`NodeManager`], set the player manager's `listener` to
+//! the chain after creating both and before creating any players.
+//! When a track finishes with nothing queued in a guild that has autoplay
+//! enabled, its [`TrackRecommender`] is asked for identifiers based on the
+//! finished track, which are resolved through the node's `/loadtracks` and
+//! checked against the guild's recently played tracks. Recommendations are
+//! made one at a time on a worker thread, so the node's events aren't held
+//! up by the requests.
+//!
+//! Autoplay only holds a weak handle to the manager, so it doesn't keep the
+//! manager from being closed, and its worker stops once the manager is gone.
+//!
+//! [`Autoplay`]: struct.Autoplay.html
+//! [`ListenerChain`]: ../struct.ListenerChain.html
+//! [`NodeManager`]: ../nodes/struct.NodeManager.html
+//! [`TrackRecommender`]: trait.TrackRecommender.html
+
+use decoder;
+use nodes::{Node, NodeManager, WeakNodeManager};
+use parking_lot::{Mutex, RwLock};
+use player::AudioPlayer;
+use rest::{LoadedTrack, TrackInfo};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::Builder as ThreadBuilder;
+use ::listener::AudioPlayerListener;
+use ::prelude::*;
+
+/// The default number of recently played tracks per guild that
+/// recommendations are de-duplicated against.
+pub const DEFAULT_RECENT_CAPACITY: usize = 50;
+
+/// Recommends tracks to play after a track.
+///
+/// Closures taking the last played track's info implement this trait.
+pub trait TrackRecommender: Send + Sync {
+    /// Returns identifiers to resolve through `/loadtracks`, such as URLs or
+    /// searches like `ytsearch:query`, in order of preference.
+    fn recommend(&self, last: &TrackInfo) -> Vec<String>;
+}
+
+impl<F> TrackRecommender for F where F: Fn(&TrackInfo) -> Vec<String> + Send + Sync {
+    fn recommend(&self, last: &TrackInfo) -> Vec<String> {
+        self(last)
+    }
+}
+
+/// Recommends the YouTube mix playlist of a YouTube track.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct YouTubeMix;
+
+impl TrackRecommender for YouTubeMix {
+    fn recommend(&self, last: &TrackInfo) -> Vec<String> {
+        let is_youtube = last.uri.as_ref().map_or(false, |uri| uri.contains("youtube.com/"));
+
+        if !is_youtube {
+            return Vec::new();
+        }
+
+        vec![format!(
+            "https://www.youtube.com/watch?v={0}&list=RD{0}",
+            last.identifier,
+        )]
+    }
+}
+
+/// Recommends a YouTube search for other tracks by the same author.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SameAuthor;
+
+impl TrackRecommender for SameAuthor {
+    fn recommend(&self, last: &TrackInfo) -> Vec<String> {
+        if last.author.is_empty() {
+            return Vec::new();
+        }
+
+        vec![format!("ytsearch:{}", last.author)]
+    }
+}
+
+/// Queues and plays recommended tracks in guilds whose queue ran dry.
+///
+/// Autoplay is off for every guild until enabled with [`set_enabled`].
+///
+/// [`set_enabled`]: #method.set_enabled
+#[derive(Clone)]
+pub struct Autoplay {
+    node_manager: WeakNodeManager,
+    recommender: Arc<TrackRecommender>,
+    enabled: Arc<RwLock<HashSet<u64>>>,
+    /// The identifiers of each guild's recently played tracks, oldest first.
+    recent: Arc<Mutex<HashMap<u64, VecDeque<String>>>>,
+    recent_capacity: usize,
+    /// Sends the guild and last track of every autoplay to the worker
+    /// thread, once it is started.
+    worker: Arc<Mutex<Option<Sender<(u64, String)>>>>,
+}
+
+impl Autoplay {
+    pub fn new<R: TrackRecommender + 'static>(node_manager: &NodeManager, recommender: R) -> Self {
+        Self {
+            node_manager: node_manager.downgrade(),
+            recommender: Arc::new(recommender),
+            enabled: Arc::new(RwLock::new(HashSet::new())),
+            recent: Arc::new(Mutex::new(HashMap::new())),
+            recent_capacity: DEFAULT_RECENT_CAPACITY,
+            worker: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets the number of recently played tracks per guild that
+    /// recommendations are de-duplicated against.
+    pub fn set_recent_capacity(&mut self, capacity: usize) {
+        self.recent_capacity = capacity;
+    }
+
+    /// Turns autoplay on or off for a guild.
+    pub fn set_enabled(&self, guild_id: u64, enabled: bool) {
+        let mut guilds = self.enabled.write();
+
+        if enabled {
+            guilds.insert(guild_id);
+        } else {
+            guilds.remove(&guild_id);
+        }
+    }
+
+    pub fn is_enabled(&self, guild_id: u64) -> bool {
+        self.enabled.read().contains(&guild_id)
+    }
+
+    /// Records a track as recently played in a guild, so it isn't
+    /// recommended again.
+    pub fn record_played(&self, guild_id: u64, identifier: &str) {
+        let mut recent = self.recent.lock();
+        let tracks = recent.entry(guild_id).or_insert_with(VecDeque::new);
+
+        tracks.retain(|recent| recent != identifier);
+        tracks.push_back(identifier.to_owned());
+
+        while tracks.len() > self.recent_capacity {
+            tracks.pop_front();
+        }
+    }
+
+    /// Forgets the recently played tracks of a guild.
+    pub fn clear_recent(&self, guild_id: u64) {
+        self.recent.lock().remove(&guild_id);
+    }
+
+    /// Resolves the first recommended track after the given one that wasn't
+    /// recently played in the guild.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoNodesAvailable`] if no node is available to resolve
+    /// the recommendations on, or if the node manager was closed.
+    ///
+    /// [`Error::NoNodesAvailable`]: ../enum.Error.html#variant.NoNodesAvailable
+    pub fn recommend(&self, guild_id: u64, last: &TrackInfo) -> Result<Option<LoadedTrack>> {
+        let node_manager = self.node_manager.upgrade().ok_or(Error::NoNodesAvailable)?;
+
+        self.recommend_with(&node_manager, guild_id, last)
+    }
+
+    fn recommend_with(
+        &self,
+        node_manager: &NodeManager,
+        guild_id: u64,
+        last: &TrackInfo,
+    ) -> Result<Option<LoadedTrack>> {
+        let node = Self::node(node_manager, guild_id)?;
+
+        for identifier in self.recommender.recommend(last) {
+            let loaded = match node.load_tracks(&identifier) {
+                Ok(loaded) => loaded,
+                Err(why) => {
+                    warn!("Err loading autoplay recommendation {}: {:?}", identifier, why);
+
+                    continue;
+                },
+            };
+
+            let recent_tracks = self.recent.lock();
+            let recent = recent_tracks.get(&guild_id);
+
+            let track = loaded.tracks.into_iter().find(|track| {
+                track.info.identifier != last.identifier
+                    && recent.map_or(true, |recent| !recent.contains(&track.info.identifier))
+            });
+
+            if track.is_some() {
+                return Ok(track);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The guild's node if it is available, or else the least loaded one.
+    fn node(node_manager: &NodeManager, guild_id: u64) -> Result<Arc<Node>> {
+        node_manager
+            .node_for_guild(guild_id)
+            .and_then(|node| if node.is_available() { Some(node) } else { None })
+            .or_else(|| node_manager.determine_best_node())
+            .ok_or(Error::NoNodesAvailable)
+    }
+
+    /// Decodes the last track, recommends a track after it and plays it,
+    /// unless the guild's player started something else meanwhile.
+    ///
+    /// Returns whether the node manager is still alive.
+    fn autoplay(&self, guild_id: u64, last: &str) -> bool {
+        let node_manager = match self.node_manager.upgrade() {
+            Some(node_manager) => node_manager,
+            None => return false,
+        };
+
+        // the node knows more about the track than its encoded form holds
+        let decoded = Self::node(&node_manager, guild_id).and_then(|node| node.decode_track(last));
+
+        let last = match decoded {
+            Ok(last) => last,
+            Err(why) => {
+                warn!("Err decoding the last track for autoplay: {:?}", why);
+
+                return true;
+            },
+        };
+
+        let track = match self.recommend_with(&node_manager, guild_id, &last) {
+            Ok(Some(track)) => track,
+            Ok(None) => {
+                debug!("No autoplay recommendation for guild {}", guild_id);
+
+                return true;
+            },
+            Err(why) => {
+                warn!("Err recommending a track for guild {}: {:?}", guild_id, why);
+
+                return true;
+            },
+        };
+
+        let player = match node_manager.player_manager.read().get_player(&guild_id) {
+            Some(player) => player,
+            None => return true,
+        };
+
+        let mut player = player.lock();
+
+        if player.track.is_some() || !player.queue.is_empty() {
+            return true;
+        }
+
+        if let Err(why) = player.play(&track.track, None, None) {
+            warn!("Err autoplaying a track for guild {}: {:?}", guild_id, why);
+        }
+
+        true
+    }
+
+    /// Hands an autoplay to the worker thread, starting it if needed.
+    fn queue(&self, guild_id: u64, last: &str) {
+        let mut worker = self.worker.lock();
+
+        if worker.is_none() {
+            let (tx, rx) = mpsc::channel::<(u64, String)>();
+
+            // without the sender, so the worker stops once every clone of
+            // this autoplay is dropped, or once the node manager is closed
+            let autoplay = Self {
+                worker: Arc::new(Mutex::new(None)),
+                ..self.clone()
+            };
+            let builder = ThreadBuilder::new().name("autoplay".into());
+
+            let spawned = builder.spawn(move || {
+                for (guild_id, last) in rx {
+                    if !autoplay.autoplay(guild_id, &last) {
+                        debug!("Node manager closed, stopping autoplay");
+
+                        break;
+                    }
+                }
+            });
+
+            match spawned {
+                Ok(_) => *worker = Some(tx),
+                Err(why) => {
+                    warn!("Err spawning autoplay thread: {:?}", why);
+
+                    return;
+                },
+            }
+        }
+
+        if let Some(ref tx) = *worker {
+            if tx.send((guild_id, last.to_owned())).is_err() {
+                warn!("Err sending autoplay for guild {} to the autoplay thread", guild_id);
+            }
+        }
+    }
+}
+
+impl AudioPlayerListener for Autoplay {
+    fn track_start(&self, player: &mut AudioPlayer, track: &str) {
+        if let Ok(info) = decoder::decode_track(track) {
+            self.record_played(player.guild_id, &info.identifier);
+        }
+    }
+
+    fn track_end(&self, player: &mut AudioPlayer, track: &str, reason: &str) {
+        // only recommend when the track ran out on its own
+        if reason != "FINISHED" && reason != "LOAD_FAILED" {
+            return;
+        }
+
+        if !self.is_enabled(player.guild_id) || player.track.is_some() || !player.queue.is_empty() {
+            return;
+        }
+
+        // resolving makes REST requests, which shouldn't block the node
+        self.queue(player.guild_id, track);
+    }
+}
+
+impl Debug for Autoplay {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("Autoplay")
+            .field("enabled", &self.enabled)
+            .field("recent_capacity", &self.recent_capacity)
+            .finish()
+    }
+}
@@ -0,0 +1,445 @@
+//! Caching `/loadtracks` results.
+//!
+//! A [`TrackCache`] set on the [`NodeManager`] is shared by the REST clients
+//! of every node, so an identifier resolved on one node is not resolved again
+//! on another. Entries expire after a time to live and the least recently
+//! used ones are evicted once the cache is full.
+//!
+//! Results are cached per protocol, as tracks encoded by v4 nodes can't
+//! always be played by v3 nodes and the other way around.
+//!
+//! Tracks that were found and searches with no matches are cached with their
+//! own time to live and counted separately. Failed loads are never cached.
+//!
+//! [`NodeManager`]: ../nodes/struct.NodeManager.html
+//! [`TrackCache`]: struct.TrackCache.html
+
+use nodes::Protocol;
+use parking_lot::Mutex;
+use rest::{LoadType, LoadedTracks};
+use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::thread::{self, Builder as ThreadBuilder};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ::prelude::*;
+
+/// How many entries a cache keeps and for how long.
+#[derive(Clone, Debug)]
+pub struct TrackCacheConfig {
+    /// The most entries kept before the least recently used are evicted.
+    pub capacity: usize,
+    /// How long results with tracks are kept.
+    pub ttl: Duration,
+    /// How long results with no matches are kept.
+    pub negative_ttl: Duration,
+    /// The file the cache is loaded from and saved to, if any.
+    pub path: Option<PathBuf>,
+    /// How often the cache is saved to its path, besides when it is dropped.
+    pub save_interval: Option<Duration>,
+}
+
+impl Default for TrackCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            ttl: Duration::from_secs(60 * 60),
+            negative_ttl: Duration::from_secs(5 * 60),
+            path: None,
+            save_interval: Some(Duration::from_secs(5 * 60)),
+        }
+    }
+}
+
+/// Counters of how a cache was used.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct TrackCacheStats {
+    /// Lookups answered with cached tracks.
+    pub positive_hits: u64,
+    /// Lookups answered with a cached result with no matches.
+    pub negative_hits: u64,
+    /// Lookups not found in the cache or expired.
+    pub misses: u64,
+    /// Entries evicted to make room for others.
+    pub evictions: u64,
+    /// The entries currently cached.
+    pub entries: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Entry {
+    /// The protocol and identifier the entry is keyed by.
+    identifier: String,
+    loaded: LoadedTracks,
+    /// When the entry expires, in seconds since the Unix epoch.
+    expires_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<String, (Entry, u64)>,
+    /// The identifier of each entry by when it was last used.
+    recency: BTreeMap<u64, String>,
+    /// Incremented on every use, ordering entries by recency.
+    clock: u64,
+    stats: TrackCacheStats,
+}
+
+impl CacheState {
+    /// Marks an entry as the most recently used.
+    fn touch(&mut self, identifier: &str) {
+        self.clock += 1;
+        let clock = self.clock;
+
+        if let Some(&mut (_, ref mut used)) = self.entries.get_mut(identifier) {
+            self.recency.remove(&*used);
+            *used = clock;
+            self.recency.insert(clock, identifier.to_owned());
+        }
+    }
+
+    fn remove(&mut self, identifier: &str) -> Option<Entry> {
+        let (entry, used) = self.entries.remove(identifier)?;
+        self.recency.remove(&used);
+
+        Some(entry)
+    }
+
+    fn insert(&mut self, entry: Entry, capacity: usize) {
+        let identifier = entry.identifier.clone();
+
+        self.remove(&identifier);
+
+        while !self.entries.is_empty() && self.entries.len() >= capacity {
+            let oldest = self.recency.values().next().cloned();
+
+            match oldest {
+                Some(oldest) => {
+                    self.remove(&oldest);
+                    self.stats.evictions += 1;
+                },
+                None => break,
+            }
+        }
+
+        if capacity == 0 {
+            return;
+        }
+
+        self.entries.insert(identifier.clone(), (entry, 0));
+        self.touch(&identifier);
+    }
+}
+
+/// A least recently used cache of `/loadtracks` results with a time to live,
+/// keyed by protocol and identifier.
+///
+/// Caches are cheap to clone and share their entries. A cache with a path is
+/// saved every save interval and when its last clone is dropped.
+#[derive(Clone)]
+pub struct TrackCache {
+    inner: Arc<CacheInner>,
+}
+
+impl TrackCache {
+    /// Creates a cache, loading the entries saved at the config's path if
+    /// there are any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the saved entries could not be read or parsed, or
+    /// if the thread saving the cache periodically could not be spawned.
+    pub fn new(config: TrackCacheConfig) -> Result<Self> {
+        let inner = Arc::new(CacheInner {
+            config,
+            state: Mutex::new(CacheState::default()),
+        });
+
+        inner.load()?;
+
+        if let (&Some(_), Some(interval)) = (&inner.config.path, inner.config.save_interval) {
+            spawn_saver(Arc::downgrade(&inner), interval)?;
+        }
+
+        Ok(Self {
+            inner,
+        })
+    }
+
+    #[inline]
+    pub fn config(&self) -> &TrackCacheConfig {
+        &self.inner.config
+    }
+
+    /// The cached result for an identifier resolved with a protocol, if it
+    /// has not expired.
+    pub fn get(&self, protocol: Protocol, identifier: &str) -> Option<LoadedTracks> {
+        let key = cache_key(protocol, identifier);
+        let mut state = self.inner.state.lock();
+        let now = unix_secs(SystemTime::now());
+
+        let cached = state.entries
+            .get(&key)
+            .map(|&(ref entry, _)| (entry.expires_at > now, entry.loaded.clone()));
+
+        match cached {
+            Some((true, loaded)) => {
+                state.touch(&key);
+
+                if loaded.load_type == LoadType::NoMatches {
+                    state.stats.negative_hits += 1;
+                } else {
+                    state.stats.positive_hits += 1;
+                }
+
+                Some(loaded)
+            },
+            Some((false, _)) => {
+                state.remove(&key);
+                state.stats.misses += 1;
+
+                None
+            },
+            None => {
+                state.stats.misses += 1;
+
+                None
+            },
+        }
+    }
+
+    /// Caches the result for an identifier resolved with a protocol, unless
+    /// the load failed.
+    pub fn insert(&self, protocol: Protocol, identifier: &str, loaded: &LoadedTracks) {
+        let config = &self.inner.config;
+
+        let ttl = match loaded.load_type {
+            LoadType::LoadFailed => return,
+            LoadType::NoMatches => config.negative_ttl,
+            _ => config.ttl,
+        };
+
+        let entry = Entry {
+            identifier: cache_key(protocol, identifier),
+            loaded: loaded.clone(),
+            expires_at: unix_secs(SystemTime::now() + ttl),
+        };
+
+        self.inner.state.lock().insert(entry, config.capacity);
+    }
+
+    /// Removes the cached result for an identifier resolved with a protocol,
+    /// returning whether there was one.
+    pub fn remove(&self, protocol: Protocol, identifier: &str) -> bool {
+        self.inner.state.lock().remove(&cache_key(protocol, identifier)).is_some()
+    }
+
+    /// Removes every cached result.
+    pub fn clear(&self) {
+        let mut state = self.inner.state.lock();
+
+        state.entries.clear();
+        state.recency.clear();
+    }
+
+    /// The counters of how the cache was used.
+    pub fn stats(&self) -> TrackCacheStats {
+        let state = self.inner.state.lock();
+
+        TrackCacheStats {
+            entries: state.entries.len(),
+            ..state.stats
+        }
+    }
+
+    /// Saves the entries that have not expired to the config's path, if it
+    /// has one.
+    ///
+    /// This is done every save interval and when the last clone of the cache
+    /// is dropped, but can be done sooner, such as before shutting down.
+    pub fn save(&self) -> Result<()> {
+        self.inner.save()
+    }
+}
+
+struct CacheInner {
+    config: TrackCacheConfig,
+    state: Mutex<CacheState>,
+}
+
+impl CacheInner {
+    fn save(&self) -> Result<()> {
+        let path = match self.config.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let now = unix_secs(SystemTime::now());
+
+        // copied out so the file is written without holding the lock
+        let entries = {
+            let state = self.state.lock();
+
+            // saved from least to most recently used, so loading keeps the
+            // order
+            let entries = state.recency
+                .values()
+                .filter_map(|identifier| state.entries.get(identifier))
+                .map(|&(ref entry, _)| entry)
+                .filter(|entry| entry.expires_at > now)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            entries
+        };
+
+        // write a temporary file first so a failed save keeps the old one
+        let temp = path.with_extension("tmp");
+
+        serde_json::to_writer(BufWriter::new(File::create(&temp)?), &entries)?;
+        fs::rename(&temp, path)?;
+
+        debug!("saved {} cached track results to {:?}", entries.len(), path);
+
+        Ok(())
+    }
+
+    fn load(&self) -> Result<()> {
+        let path = match self.config.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref why) if why.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(why) => return Err(From::from(why)),
+        };
+
+        let entries: Vec<Entry> = serde_json::from_reader(BufReader::new(file))?;
+        let now = unix_secs(SystemTime::now());
+        let mut state = self.state.lock();
+
+        for entry in entries {
+            if entry.expires_at > now {
+                state.insert(entry, self.config.capacity);
+            }
+        }
+
+        debug!("loaded {} cached track results from {:?}", state.entries.len(), path);
+
+        Ok(())
+    }
+}
+
+impl Drop for CacheInner {
+    fn drop(&mut self) {
+        if let Err(why) = self.save() {
+            warn!("Err saving the track cache: {:?}", why);
+        }
+    }
+}
+
+/// Spawns a thread saving a cache every interval until it is dropped.
+fn spawn_saver(cache: Weak<CacheInner>, interval: Duration) -> Result<()> {
+    let builder = ThreadBuilder::new().name("track cache".into());
+
+    builder.spawn(move || loop {
+        thread::sleep(interval);
+
+        let cache = match cache.upgrade() {
+            Some(cache) => cache,
+            None => break,
+        };
+
+        if let Err(why) = cache.save() {
+            warn!("Err saving the track cache: {:?}", why);
+        }
+    })?;
+
+    Ok(())
+}
+
+impl Debug for TrackCache {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("TrackCache")
+            .field("config", &self.inner.config)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// The key of an identifier resolved with a protocol.
+fn cache_key(protocol: Protocol, identifier: &str) -> String {
+    format!("v{}:{}", protocol.version(), identifier)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use nodes::Protocol;
+    use rest::{LoadType, LoadedTracks, PlaylistInfo};
+    use super::{TrackCache, TrackCacheConfig};
+
+    fn loaded(load_type: LoadType) -> LoadedTracks {
+        LoadedTracks {
+            load_type,
+            playlist_info: PlaylistInfo::default(),
+            tracks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn keeps_protocols_apart() {
+        let cache = TrackCache::new(TrackCacheConfig::default()).unwrap();
+
+        cache.insert(Protocol::V3, "ytsearch:never gonna", &loaded(LoadType::SearchResult));
+
+        assert!(cache.get(Protocol::V4, "ytsearch:never gonna").is_none());
+        assert_eq!(
+            cache.get(Protocol::V3, "ytsearch:never gonna").map(|loaded| loaded.load_type),
+            Some(LoadType::SearchResult),
+        );
+
+        cache.insert(Protocol::V4, "ytsearch:never gonna", &loaded(LoadType::NoMatches));
+
+        let stats = cache.stats();
+
+        assert_eq!(stats.entries, 2);
+        assert_eq!((stats.positive_hits, stats.misses), (1, 1));
+
+        assert!(cache.remove(Protocol::V4, "ytsearch:never gonna"));
+        assert!(!cache.remove(Protocol::V4, "ytsearch:never gonna"));
+        assert!(cache.get(Protocol::V3, "ytsearch:never gonna").is_some());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_and_skips_failed_loads() {
+        let cache = TrackCache::new(TrackCacheConfig {
+            capacity: 2,
+            ..TrackCacheConfig::default()
+        }).unwrap();
+
+        cache.insert(Protocol::V3, "first", &loaded(LoadType::TrackLoaded));
+        cache.insert(Protocol::V3, "second", &loaded(LoadType::TrackLoaded));
+        assert!(cache.get(Protocol::V3, "first").is_some());
+
+        cache.insert(Protocol::V3, "third", &loaded(LoadType::TrackLoaded));
+        cache.insert(Protocol::V3, "failed", &loaded(LoadType::LoadFailed));
+
+        assert!(cache.get(Protocol::V3, "second").is_none());
+        assert!(cache.get(Protocol::V3, "first").is_some());
+        assert!(cache.get(Protocol::V3, "third").is_some());
+        assert!(cache.get(Protocol::V3, "failed").is_none());
+        assert_eq!(cache.stats().evictions, 1);
+    }
+}
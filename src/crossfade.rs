@@ -0,0 +1,445 @@
+//! Crossfading and gapless transitions between queued tracks.
+//!
+//! [`Crossfade`] checks the players of enabled guilds every tick. Near the end
+//! of a track with another one queued it resolves the next track through the
+//! guild's node ahead of time and fades the volume out. The player starts the
+//! next track as soon as the node reports the current one finished, and the
+//! volume is then faded back in. For that, the `auto_advance` of enabled
+//! guilds' players is turned on, and turned back off once a guild is
+//! disabled.
+//!
+//! Queued tracks may be identifiers to load, such as URLs or searches like
+//! `ytsearch:query`. They are loaded through the node's `/loadtracks` while
+//! preloading and replaced in the queue by the encoded track, so the node can
+//! start them right away.
+//!
+//! The end of a track is known from its length or its end time, and its
+//! position is extrapolated from the node's player updates, so streams are
+//! never faded.
+//!
+//! Crossfades only hold a weak handle to the manager, so they don't keep the
+//! manager from being closed, and their thread stops once the manager is
+//! gone.
+//!
+//! [`Crossfade`]: struct.Crossfade.html
+
+use decoder;
+use nodes::{Node, NodeManager, WeakNodeManager};
+use parking_lot::{Mutex, RwLock};
+use player::AudioPlayer;
+use rest::{LoadedTrack, TrackInfo};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, Builder as ThreadBuilder, JoinHandle};
+use std::time::Duration;
+use ::prelude::*;
+
+/// How tracks transition into each other.
+#[derive(Clone, Debug)]
+pub struct CrossfadeConfig {
+    /// How long to fade out the end of a track and fade in the next one.
+    ///
+    /// With `None`, tracks are only started back to back.
+    pub duration: Option<Duration>,
+    /// How long before the end of a track the next queued track is
+    /// resolved or loaded.
+    pub preload: Duration,
+    /// How often players are checked.
+    pub tick: Duration,
+}
+
+impl Default for CrossfadeConfig {
+    fn default() -> Self {
+        Self {
+            duration: Some(Duration::from_secs(5)),
+            preload: Duration::from_secs(10),
+            tick: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Transition {
+    /// The track the transition is for.
+    track: String,
+    /// The length of the track, or `None` for streams.
+    length: Option<u64>,
+    /// The volume before fading out, which the next track fades in to.
+    target_volume: Option<i32>,
+    /// The next queued track once it was resolved, or is being resolved.
+    preloading: Option<String>,
+    /// The next queued track as encoded once it was resolved, with its info.
+    next: Option<(String, TrackInfo)>,
+}
+
+impl Transition {
+    /// Starts the transition of a track, using its resolved info if it was
+    /// preloaded.
+    fn new(track: &str, info: Option<TrackInfo>) -> Self {
+        let length = info
+            .or_else(|| decoder::decode_track(track).ok())
+            .and_then(|info| if info.is_stream { None } else { Some(info.length) });
+
+        Self {
+            track: track.to_owned(),
+            length,
+            ..Self::default()
+        }
+    }
+}
+
+/// What a tick did to a player's transition.
+enum Step {
+    /// The player has nothing left to transition.
+    Done,
+    /// The transition goes on.
+    Continue,
+    /// The next queued track should be resolved or loaded.
+    Preload(String),
+}
+
+/// Crossfades and gaplessly transitions between the queued tracks of enabled
+/// guilds.
+///
+/// Crossfades are cheap to clone and share their state.
+#[derive(Clone)]
+pub struct Crossfade {
+    node_manager: WeakNodeManager,
+    config: CrossfadeConfig,
+    enabled: Arc<RwLock<HashSet<u64>>>,
+    /// The guilds whose player's `auto_advance` was turned on by the
+    /// crossfade, to turn back off once disabled.
+    advancing: Arc<Mutex<HashSet<u64>>>,
+    transitions: Arc<Mutex<HashMap<u64, Transition>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl Crossfade {
+    pub fn new(node_manager: &NodeManager, config: CrossfadeConfig) -> Self {
+        Self {
+            node_manager: node_manager.downgrade(),
+            config,
+            enabled: Arc::new(RwLock::new(HashSet::new())),
+            advancing: Arc::new(Mutex::new(HashSet::new())),
+            transitions: Arc::new(Mutex::new(HashMap::new())),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &CrossfadeConfig {
+        &self.config
+    }
+
+    /// Turns transitions on or off for a guild.
+    ///
+    /// Disabling a guild turns its player's `auto_advance` back off if the
+    /// crossfade turned it on.
+    pub fn set_enabled(&self, guild_id: u64, enabled: bool) {
+        if enabled {
+            self.enabled.write().insert(guild_id);
+
+            return;
+        }
+
+        self.enabled.write().remove(&guild_id);
+        self.transitions.lock().remove(&guild_id);
+
+        if !self.advancing.lock().remove(&guild_id) {
+            return;
+        }
+
+        let player = self.node_manager
+            .upgrade()
+            .and_then(|node_manager| node_manager.player_manager.read().get_player(&guild_id));
+
+        if let Some(player) = player {
+            player.lock().auto_advance = false;
+        }
+    }
+
+    pub fn is_enabled(&self, guild_id: u64) -> bool {
+        self.enabled.read().contains(&guild_id)
+    }
+
+    /// The resolved info of the track queued after the guild's current one,
+    /// once it was preloaded or loaded.
+    pub fn preloaded(&self, guild_id: u64) -> Option<TrackInfo> {
+        let transitions = self.transitions.lock();
+
+        transitions.get(&guild_id)?.next.as_ref().map(|&(_, ref info)| info.clone())
+    }
+
+    /// Spawns a thread checking players every tick until [`stop`] is called.
+    ///
+    /// [`stop`]: #method.stop
+    pub fn start(&self) -> Result<JoinHandle<()>> {
+        self.running.store(true, Ordering::SeqCst);
+
+        let crossfade = self.clone();
+        let builder = ThreadBuilder::new().name("crossfade".into());
+
+        builder.spawn(move || {
+            while crossfade.running.load(Ordering::SeqCst) && crossfade.tick_players() {
+                thread::sleep(crossfade.config.tick);
+            }
+        }).map_err(From::from)
+    }
+
+    /// Stops the thread spawned by [`start`] after its current tick.
+    ///
+    /// [`start`]: #method.start
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Checks the players of enabled guilds once, starting fades and
+    /// resolving next tracks.
+    pub fn tick(&self) {
+        self.tick_players();
+    }
+
+    /// Checks the players of enabled guilds once, returning whether the node
+    /// manager is still alive.
+    fn tick_players(&self) -> bool {
+        let node_manager = match self.node_manager.upgrade() {
+            Some(node_manager) => node_manager,
+            None => return false,
+        };
+
+        let guild_ids = self.enabled.read().iter().cloned().collect::<Vec<_>>();
+
+        for guild_id in guild_ids {
+            let player = match node_manager.player_manager.read().get_player(&guild_id) {
+                Some(player) => player,
+                None => {
+                    self.transitions.lock().remove(&guild_id);
+
+                    continue;
+                },
+            };
+
+            let step = {
+                let mut player = player.lock();
+
+                // the player starts the next track once the current one ends
+                if !player.auto_advance {
+                    player.auto_advance = true;
+                    self.advancing.lock().insert(guild_id);
+                }
+
+                let mut transitions = self.transitions.lock();
+
+                let step = {
+                    let transition = transitions.entry(guild_id).or_insert_with(Transition::default);
+
+                    self.update(&mut player, transition)
+                };
+
+                if let Step::Done = step {
+                    transitions.remove(&guild_id);
+                }
+
+                step
+            };
+
+            // resolved without the locks, as it makes a REST request
+            if let Step::Preload(next) = step {
+                self.preload(&node_manager, guild_id, &next);
+            }
+        }
+
+        true
+    }
+
+    /// Steps the transition of a player.
+    fn update(&self, player: &mut AudioPlayer, transition: &mut Transition) -> Step {
+        let track = match player.track.clone() {
+            Some(track) => track,
+            None => {
+                restore_volume(player, transition);
+
+                return Step::Done;
+            },
+        };
+
+        let fade = self.config.duration.map_or(0, millis);
+
+        if transition.track != track {
+            // the next track started, either as the faded out one finished
+            // or by being skipped to
+            let target = transition.target_volume.take();
+            let info = match transition.next.take() {
+                Some((next, info)) => if next == track { Some(info) } else { None },
+                None => None,
+            };
+
+            *transition = Transition::new(&track, info);
+
+            if let Some(target) = target {
+                let duration = Duration::from_millis(fade as u64);
+
+                if let Err(why) = player.fade_volume(target, duration) {
+                    warn!("Err fading in volume for guild {}: {:?}", player.guild_id, why);
+                }
+            }
+        }
+
+        if player.paused {
+            restore_volume(player, transition);
+
+            return Step::Continue;
+        }
+
+        let next = player.queue.front().map(|next| next.track.clone());
+
+        let next = match next {
+            Some(next) => next,
+            None => {
+                restore_volume(player, transition);
+
+                return Step::Continue;
+            },
+        };
+
+        let end = match player.end_time().or(transition.length) {
+            Some(end) => end as i64,
+            None => return Step::Continue,
+        };
+        let remaining = end - player.estimated_position();
+
+        if fade > 0 && remaining <= fade && transition.target_volume.is_none() {
+            transition.target_volume = Some(player.volume);
+
+            let duration = Duration::from_millis(remaining.max(0) as u64);
+
+            if let Err(why) = player.fade_volume(0, duration) {
+                warn!("Err fading out volume for guild {}: {:?}", player.guild_id, why);
+            }
+        }
+
+        let is_preloaded = transition.preloading.as_ref() == Some(&next);
+
+        if remaining > millis(self.config.preload) || is_preloaded {
+            return Step::Continue;
+        }
+
+        transition.preloading = Some(next.clone());
+
+        Step::Preload(next)
+    }
+
+    /// Resolves the next queued track of a guild through its node ahead of
+    /// time.
+    ///
+    /// Encoded tracks are decoded, falling back to decoding them locally.
+    /// Anything else is loaded through `/loadtracks`, and replaced in the
+    /// queue by the loaded track if it is still next.
+    fn preload(&self, node_manager: &NodeManager, guild_id: u64, next: &str) {
+        let node = node_manager.node_for_guild(guild_id);
+
+        let loaded = if decoder::decode_track(next).is_ok() {
+            let info = match node {
+                Some(node) => node.decode_track(next),
+                None => decoder::decode_track(next),
+            };
+
+            info.map(|info| Some(LoadedTrack {
+                track: next.to_owned(),
+                info,
+            }))
+        } else {
+            match node {
+                Some(node) => load(&node, next),
+                None => Err(Error::NoNodesAvailable),
+            }
+        };
+
+        let loaded = match loaded {
+            Ok(Some(loaded)) => loaded,
+            Ok(None) => {
+                warn!("Nothing found preloading next track for guild {}: {}", guild_id, next);
+
+                return;
+            },
+            Err(why) => {
+                warn!("Err preloading next track for guild {}: {:?}", guild_id, why);
+
+                return;
+            },
+        };
+
+        debug!("Preloaded next track for guild {}: {}", guild_id, loaded.info.title);
+
+        let player = match node_manager.player_manager.read().get_player(&guild_id) {
+            Some(player) => player,
+            None => return,
+        };
+
+        let mut player = player.lock();
+        let mut transitions = self.transitions.lock();
+
+        let transition = match transitions.get_mut(&guild_id) {
+            Some(transition) => transition,
+            None => return,
+        };
+
+        // the queue may have changed while the track was resolved
+        let is_preloading = transition
+            .preloading
+            .as_ref()
+            .map_or(false, |preloading| preloading == next);
+        let is_next = player.queue.front().map_or(false, |queued| queued.track == next);
+
+        if !is_preloading || !is_next {
+            return;
+        }
+
+        if let Some(queued) = player.queue.front_mut() {
+            queued.track = loaded.track.clone();
+        }
+
+        transition.preloading = Some(loaded.track.clone());
+        transition.next = Some((loaded.track, loaded.info));
+    }
+}
+
+impl Debug for Crossfade {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("Crossfade")
+            .field("config", &self.config)
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+/// Returns the player to the volume it had before fading, ending any fade.
+fn restore_volume(player: &mut AudioPlayer, transition: &mut Transition) {
+    let target = match transition.target_volume.take() {
+        Some(target) => target,
+        None => return,
+    };
+
+    if let Err(why) = player.volume(target) {
+        warn!("Err restoring volume for guild {}: {:?}", player.guild_id, why);
+    }
+}
+
+/// Loads an identifier, returning the playlist's selected track or else the
+/// first track found.
+fn load(node: &Node, identifier: &str) -> Result<Option<LoadedTrack>> {
+    let loaded = node.load_tracks(identifier)?;
+
+    let selected = match loaded.playlist_info.selected_track {
+        Some(selected) if selected >= 0 => selected as usize,
+        _ => 0,
+    };
+
+    Ok(loaded.tracks.into_iter().nth(selected))
+}
+
+fn millis(duration: Duration) -> i64 {
+    duration.as_secs() as i64 * 1000 + i64::from(duration.subsec_nanos() / 1_000_000)
+}
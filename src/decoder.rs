@@ -0,0 +1,278 @@
+//! Decoding of base64-encoded tracks without asking a node.
+//!
+//! Tracks are encoded by Lavaplayer as a message with a header holding the
+//! message size and flags, followed by the track info. Source-specific data
+//! comes after the info, and the track's position is always the last field.
+
+use base64;
+use rest::TrackInfo;
+use ::prelude::*;
+
+/// The header flag set when the message starts with a version byte.
+const TRACK_INFO_VERSIONED: u32 = 1;
+
+/// Decodes the info of a base64-encoded track.
+///
+/// Whether the track is seekable is not part of the message, so as in
+/// Lavaplayer, where only streams can't be seeked, it is taken to be
+/// `!is_stream`. Use [`Node::decode_track`] for the node's own answer.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidTrack`] if the track is not valid base64 or is not
+/// a track message.
+///
+/// [`Error::InvalidTrack`]: ../enum.Error.html#variant.InvalidTrack
+/// [`Node::decode_track`]: ../nodes/struct.Node.html#method.decode_track
+pub fn decode_track(track: &str) -> Result<TrackInfo> {
+    let bytes = base64::decode(track)
+        .map_err(|why| Error::InvalidTrack(format!("Invalid base64: {}", why)))?;
+
+    let mut reader = MessageReader::new(&bytes);
+
+    let header = reader.read_u32()?;
+    let size = (header & 0x3FFF_FFFF) as usize;
+    let flags = header >> 30;
+
+    if size < 8 {
+        return Err(invalid("message is too short"));
+    }
+
+    // the size excludes the header itself
+    let message = bytes.get(4..4 + size).ok_or_else(|| invalid("message size exceeds the track"))?;
+
+    let mut reader = MessageReader::new(&message[..size - 8]);
+
+    let version = if flags & TRACK_INFO_VERSIONED != 0 {
+        reader.read_u8()?
+    } else {
+        1
+    };
+
+    let title = reader.read_utf()?;
+    let author = reader.read_utf()?;
+    let length = reader.read_u64()?;
+    let identifier = reader.read_utf()?;
+    let is_stream = reader.read_bool()?;
+    let uri = if version >= 2 {
+        reader.read_nullable_utf()?
+    } else {
+        None
+    };
+
+    let position = MessageReader::new(&message[size - 8..]).read_u64()?;
+
+    Ok(TrackInfo {
+        identifier,
+        is_seekable: !is_stream,
+        author,
+        length,
+        is_stream,
+        position,
+        title,
+        uri,
+    })
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidTrack(reason.to_owned())
+}
+
+/// Reads the fields of a message written by a Java `DataOutput`.
+struct MessageReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> MessageReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("unexpected end of the track"));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+
+        Ok(bytes.iter().fold(0, |value, byte| value << 8 | u32::from(*byte)))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let bytes = self.take(8)?;
+
+        Ok(bytes.iter().fold(0, |value, byte| value << 8 | u64::from(*byte)))
+    }
+
+    /// Reads a string prefixed by its length in bytes.
+    ///
+    /// Java writes modified UTF-8, which only differs from UTF-8 for null
+    /// and supplementary characters, so those are decoded lossily.
+    fn read_utf(&mut self) -> Result<String> {
+        let len = self.read_u16()? as usize;
+        let bytes = self.take(len)?;
+
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn read_nullable_utf(&mut self) -> Result<Option<String>> {
+        if self.read_bool()? {
+            self.read_utf().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64;
+    use rest::TrackInfo;
+    use super::decode_track;
+    use ::prelude::*;
+
+    fn info(uri: Option<&str>) -> TrackInfo {
+        TrackInfo {
+            identifier: "dQw4w9WgXcQ".to_owned(),
+            is_seekable: true,
+            author: "RickAstleyVEVO".to_owned(),
+            length: 212_000,
+            is_stream: false,
+            position: 0,
+            title: "Rick Astley - Never Gonna Give You Up".to_owned(),
+            uri: uri.map(ToOwned::to_owned),
+        }
+    }
+
+    /// Encodes a track the way Lavaplayer does for a message version.
+    fn encode(version: u8, info: &TrackInfo) -> String {
+        let mut message = Vec::new();
+
+        if version > 1 {
+            message.push(version);
+        }
+
+        write_utf(&mut message, &info.title);
+        write_utf(&mut message, &info.author);
+        write_u64(&mut message, info.length);
+        write_utf(&mut message, &info.identifier);
+        message.push(info.is_stream as u8);
+
+        if version >= 2 {
+            write_nullable_utf(&mut message, info.uri.as_ref().map(|uri| &uri[..]));
+        }
+
+        if version >= 3 {
+            // the artwork URL and ISRC
+            write_nullable_utf(&mut message, Some("https://i.ytimg.com/vi/dQw4w9WgXcQ/mqdefault.jpg"));
+            write_nullable_utf(&mut message, None);
+        }
+
+        // the source name and source-specific data
+        write_utf(&mut message, "youtube");
+        write_u64(&mut message, info.position);
+
+        let flags = if version > 1 { 1 << 30 } else { 0 };
+        let header = message.len() as u32 | flags;
+
+        let mut bytes = (0..4).rev().map(|i| (header >> (i * 8)) as u8).collect::<Vec<_>>();
+        bytes.extend(message);
+
+        base64::encode(&bytes)
+    }
+
+    fn write_u64(message: &mut Vec<u8>, value: u64) {
+        message.extend((0..8).rev().map(|i| (value >> (i * 8)) as u8));
+    }
+
+    fn write_utf(message: &mut Vec<u8>, value: &str) {
+        let len = value.len() as u16;
+
+        message.push((len >> 8) as u8);
+        message.push(len as u8);
+        message.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_nullable_utf(message: &mut Vec<u8>, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                message.push(1);
+                write_utf(message, value);
+            },
+            None => message.push(0),
+        }
+    }
+
+    #[test]
+    fn round_trips_unversioned_tracks() {
+        let info = info(None);
+
+        assert_eq!(decode_track(&encode(1, &info)).unwrap(), info);
+    }
+
+    #[test]
+    fn round_trips_versioned_tracks() {
+        let info = info(Some("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+
+        assert_eq!(decode_track(&encode(2, &info)).unwrap(), info);
+        assert_eq!(decode_track(&encode(3, &info)).unwrap(), info);
+    }
+
+    #[test]
+    fn round_trips_streams_and_positions() {
+        let mut info = info(None);
+        info.is_stream = true;
+        info.is_seekable = false;
+        // Lavaplayer gives streams a length of Long.MAX_VALUE
+        info.length = 0x7FFF_FFFF_FFFF_FFFF;
+        info.position = 42_000;
+
+        assert_eq!(decode_track(&encode(2, &info)).unwrap(), info);
+    }
+
+    #[test]
+    fn round_trips_multibyte_text() {
+        let mut info = info(Some("https://soundcloud.com/アーティスト/曲"));
+        info.title = "曲のタイトル".to_owned();
+        info.author = "Ñandú".to_owned();
+
+        assert_eq!(decode_track(&encode(2, &info)).unwrap(), info);
+    }
+
+    #[test]
+    fn rejects_invalid_tracks() {
+        let encoded = encode(2, &info(None));
+        let bytes = base64::decode(&encoded).unwrap();
+        let truncated = base64::encode(&bytes[..bytes.len() - 10]);
+
+        for track in &["not base64!", "AAAA", &truncated[..]] {
+            match decode_track(track) {
+                Err(Error::InvalidTrack(_)) => {},
+                other => panic!("expected an invalid track for {}, got {:?}", track, other),
+            }
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use hyper::status::StatusCode;
 use hyper::Error as HyperError;
+use native_tls::{Error as TlsError, HandshakeError};
 use serde_json::Error as JsonError;
 use std::error::Error as StdError;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::io::Error as IoError;
+use std::io::{Error as IoError, ErrorKind};
 use std::result::Result as StdResult;
 use std::sync::mpsc::SendError;
 use websocket::client::ParseError;
@@ -13,11 +15,34 @@ pub type Result<T> = StdResult<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     Hyper(HyperError),
+    /// A node config failed validation, with the reason why.
+    InvalidConfig(String),
+    /// An encoded track could not be decoded, with the reason why.
+    InvalidTrack(String),
+    /// A vote skip threshold was not above 0 and at most 1.
+    InvalidThreshold(f64),
+    /// A volume was outside of the range a node accepts.
+    InvalidVolume(i32),
     Io(IoError),
     Json(JsonError),
+    /// The node was closed or gave up reconnecting.
+    NodeClosed,
+    /// The node lost its connection and is reconnecting, so an op could not
+    /// be sent.
+    NodeReconnecting,
+    /// A player was scheduled to leave its voice channel without a voice
+    /// leaver set.
+    NoVoiceLeaver,
+    /// No node was available to create a player on.
+    NoNodesAvailable,
+    /// A v4 node has not opened a session to control players in yet.
+    NoSession,
     PlayerAlreadyExists,
     Send(String),
     StatsNotPresent,
+    Tls(TlsError),
+    /// A REST request to a node returned a non-success status code.
+    UnsuccessfulRequest(StatusCode),
     UriParse(ParseError),
     WebSocket(WebSocketError),
 }
@@ -32,11 +57,22 @@ impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
             Error::Hyper(ref inner) => inner.description(),
+            Error::InvalidConfig(ref inner) => inner,
+            Error::InvalidTrack(ref inner) => inner,
+            Error::InvalidThreshold(_) => "The vote skip threshold must be above 0 and at most 1",
+            Error::InvalidVolume(_) => "The volume must be between 0 and 1000",
             Error::Io(ref inner) => inner.description(),
             Error::Json(ref inner) => inner.description(),
+            Error::NodeClosed => "The node is closed",
+            Error::NodeReconnecting => "The node is reconnecting",
+            Error::NoNodesAvailable => "No node is available",
+            Error::NoSession => "The node has not opened a session yet",
+            Error::NoVoiceLeaver => "No voice leaver is set",
             Error::PlayerAlreadyExists => "Player already exists for the guild",
             Error::Send(ref inner) => inner,
             Error::StatsNotPresent => "No stats are present",
+            Error::Tls(ref inner) => inner.description(),
+            Error::UnsuccessfulRequest(_) => "The node returned an unsuccessful status code",
             Error::UriParse(ref inner) => inner.description(),
             Error::WebSocket(ref inner) => inner.description(),
         }
@@ -73,6 +109,24 @@ impl<T> From<SendError<T>> for Error {
     }
 }
 
+impl From<TlsError> for Error {
+    fn from(err: TlsError) -> Self {
+        Error::Tls(err)
+    }
+}
+
+impl<S> From<HandshakeError<S>> for Error {
+    fn from(err: HandshakeError<S>) -> Self {
+        match err {
+            HandshakeError::Failure(err) => Error::Tls(err),
+            HandshakeError::WouldBlock(_) => Error::Io(IoError::new(
+                ErrorKind::WouldBlock,
+                "TLS handshake would block",
+            )),
+        }
+    }
+}
+
 impl From<WebSocketError> for Error {
     fn from(err: WebSocketError) -> Self {
         Error::WebSocket(err)
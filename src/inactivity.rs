@@ -0,0 +1,326 @@
+//! Leaving voice channels that are empty or idle.
+//!
+//! The [`InactivityManager`] knows who is in the bot's voice channel from a
+//! [`VoiceTracker`] and watches each player for being stopped or paused.
+//! Once a player has been inactive for the configured timeout it is paused,
+//! and if it stays inactive it is destroyed and its voice channel is left,
+//! calling [`AudioPlayerListener::player_inactive`] just before.
+//!
+//! The manager only holds a weak handle to the [`NodeManager`], so the node
+//! manager can still be closed, which also stops the checking thread.
+//!
+//! [`AudioPlayerListener::player_inactive`]:
+//! ../trait.AudioPlayerListener.html#method.player_inactive
+//! [`NodeManager`]: ../nodes/struct.NodeManager.html
+//! [`InactivityManager`]: struct.InactivityManager.html
+//! [`VoiceTracker`]: ../voice/struct.VoiceTracker.html
+
+use nodes::{NodeManager, WeakNodeManager};
+use parking_lot::Mutex;
+use player::AudioPlayer;
+use serenity::model::guild::Guild;
+use serenity::model::voice::VoiceState;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, Builder as ThreadBuilder, JoinHandle};
+use std::time::{Duration, Instant};
+use voice::VoiceTracker;
+use ::listener;
+use ::prelude::*;
+
+/// Leaves a guild's voice channel.
+///
+/// Closures taking the guild ID implement this trait, so leaving can be done
+/// through Serenity's voice manager or by sending a voice state update on the
+/// guild's shard.
+pub trait VoiceLeaver: Send + Sync {
+    fn leave(&self, guild_id: u64);
+}
+
+impl<F> VoiceLeaver for F where F: Fn(u64) + Send + Sync {
+    fn leave(&self, guild_id: u64) {
+        self(guild_id)
+    }
+}
+
+/// Why a player was considered inactive.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum InactivityReason {
+    /// No one but the bot is in the voice channel.
+    EmptyChannel,
+    /// The player is not playing a track.
+    Stopped,
+    /// The player is paused.
+    Paused,
+}
+
+/// When players are considered inactive and what happens to them.
+#[derive(Clone, Debug)]
+pub struct InactivityConfig {
+    /// How long a player must be inactive before it is paused.
+    pub timeout: Duration,
+    /// How long a player stays paused for inactivity before it is destroyed
+    /// and its voice channel left.
+    pub leave_after: Duration,
+    /// How often players are checked.
+    pub check_interval: Duration,
+    /// Whether an empty voice channel makes a player inactive.
+    pub leave_when_empty: bool,
+    /// Whether a stopped or paused player is inactive.
+    pub leave_when_idle: bool,
+}
+
+impl Default for InactivityConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(300),
+            leave_after: Duration::from_secs(60),
+            check_interval: Duration::from_secs(5),
+            leave_when_empty: true,
+            leave_when_idle: true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GuildActivity {
+    inactive_since: Option<Instant>,
+    /// Whether the player was paused for being inactive, so it can be resumed
+    /// when someone comes back.
+    paused_by_us: bool,
+}
+
+/// Pauses, then destroys and leaves, players that are inactive.
+///
+/// Managers are cheap to clone and share their state.
+#[derive(Clone)]
+pub struct InactivityManager {
+    node_manager: WeakNodeManager,
+    tracker: VoiceTracker,
+    config: InactivityConfig,
+    leaver: Arc<VoiceLeaver>,
+    guilds: Arc<Mutex<HashMap<u64, GuildActivity>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl InactivityManager {
+    /// Creates a manager for the players of a node manager, knowing who is
+    /// in the bot's voice channel from the tracker.
+    ///
+    /// The tracker can be shared with other helpers such as [`VoteSkip`],
+    /// in which case voice states only need to be recorded once.
+    ///
+    /// [`VoteSkip`]: ../vote_skip/struct.VoteSkip.html
+    pub fn new<L: VoiceLeaver + 'static>(
+        node_manager: &NodeManager,
+        tracker: VoiceTracker,
+        config: InactivityConfig,
+        leaver: L,
+    ) -> Self {
+        Self {
+            node_manager: node_manager.downgrade(),
+            tracker,
+            config,
+            leaver: Arc::new(leaver),
+            guilds: Arc::new(Mutex::new(HashMap::new())),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &InactivityConfig {
+        &self.config
+    }
+
+    /// The tracker of who is in which voice channel, which can be shared
+    /// with other helpers such as [`VoteSkip`].
+    ///
+    /// [`VoteSkip`]: ../vote_skip/struct.VoteSkip.html
+    #[inline]
+    pub fn voice_tracker(&self) -> &VoiceTracker {
+        &self.tracker
+    }
+
+    /// Records a voice state update received from Discord.
+    ///
+    /// Call this from `EventHandler::voice_state_update`.
+    pub fn update_voice_state(&self, guild_id: u64, state: &VoiceState) {
+        self.tracker.update(guild_id, state);
+    }
+
+    /// Records the members and voice states of a guild.
+    ///
+    /// Call this from `EventHandler::guild_create`.
+    pub fn update_guild(&self, guild: &Guild) {
+        self.tracker.update_guild(guild);
+    }
+
+    /// Forgets everything tracked about a guild, such as when the bot is
+    /// removed from it.
+    pub fn remove_guild(&self, guild_id: u64) {
+        self.tracker.remove_guild(guild_id);
+        self.guilds.lock().remove(&guild_id);
+    }
+
+    /// The number of users other than bots in the bot's voice channel, if
+    /// the bot is known to be in one.
+    pub fn listeners(&self, guild_id: u64) -> Option<usize> {
+        self.tracker.listeners(guild_id).map(|listeners| listeners.len())
+    }
+
+    /// Spawns a thread checking players every check interval until
+    /// [`stop`] is called or the node manager is closed.
+    ///
+    /// [`stop`]: #method.stop
+    pub fn start(&self) -> Result<JoinHandle<()>> {
+        self.running.store(true, Ordering::SeqCst);
+
+        let manager = self.clone();
+        let builder = ThreadBuilder::new().name("inactivity manager".into());
+
+        builder.spawn(move || {
+            while manager.running.load(Ordering::SeqCst) && manager.check_players() {
+                thread::sleep(manager.config.check_interval);
+            }
+        }).map_err(From::from)
+    }
+
+    /// Stops the thread spawned by [`start`] after its current check.
+    ///
+    /// [`start`]: #method.start
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// Checks every player once, pausing or leaving those that have been
+    /// inactive for long enough.
+    pub fn check(&self) {
+        self.check_players();
+    }
+
+    /// Checks every player once, returning whether the node manager is still
+    /// alive.
+    fn check_players(&self) -> bool {
+        let node_manager = match self.node_manager.upgrade() {
+            Some(node_manager) => node_manager,
+            None => return false,
+        };
+
+        let guild_ids = node_manager.player_manager.read().guild_ids();
+
+        for guild_id in guild_ids {
+            self.check_player(&node_manager, guild_id);
+        }
+
+        // forget inactivity of players destroyed elsewhere
+        let player_manager = node_manager.player_manager.read();
+
+        for (guild_id, guild) in self.guilds.lock().iter_mut() {
+            if !player_manager.has_player(guild_id) {
+                guild.inactive_since = None;
+                guild.paused_by_us = false;
+            }
+        }
+
+        true
+    }
+
+    fn check_player(&self, node_manager: &NodeManager, guild_id: u64) {
+        let player = match node_manager.player_manager.read().get_player(&guild_id) {
+            Some(player) => player,
+            None => return,
+        };
+
+        let mut player = player.lock();
+
+        let reason = {
+            let mut guilds = self.guilds.lock();
+            let guild = guilds.entry(guild_id).or_insert_with(GuildActivity::default);
+
+            match self.update(&mut player, guild) {
+                Some(reason) => reason,
+                None => return,
+            }
+        };
+
+        info!("Leaving inactive player for guild {}: {:?}", guild_id, reason);
+
+        let listener = player.listener();
+
+        listener::dispatch("player_inactive", || listener.player_inactive(&mut player, reason));
+
+        drop(player);
+
+        self.leaver.leave(guild_id);
+        node_manager.destroy_player(guild_id);
+    }
+
+    /// Updates the inactivity of a guild's player, pausing it once the
+    /// timeout passes.
+    ///
+    /// Returns the reason to leave if the player should be left.
+    fn update(
+        &self,
+        player: &mut AudioPlayer,
+        guild: &mut GuildActivity,
+    ) -> Option<InactivityReason> {
+        let guild_id = player.guild_id;
+
+        let reason = if self.config.leave_when_empty && self.tracker.is_empty(guild_id) {
+            InactivityReason::EmptyChannel
+        } else if guild.paused_by_us {
+            // someone came back, so pick up where the player left off
+            if let Err(why) = player.pause(false) {
+                warn!("Err resuming player for guild {}: {:?}", guild_id, why);
+            }
+
+            guild.paused_by_us = false;
+            guild.inactive_since = None;
+
+            return None;
+        } else if self.config.leave_when_idle && player.track.is_none() {
+            InactivityReason::Stopped
+        } else if self.config.leave_when_idle && player.paused {
+            InactivityReason::Paused
+        } else {
+            guild.inactive_since = None;
+
+            return None;
+        };
+
+        let inactive_for = guild.inactive_since.get_or_insert_with(Instant::now).elapsed();
+
+        if inactive_for < self.config.timeout {
+            return None;
+        }
+
+        if player.track.is_some() && !player.paused {
+            debug!("Pausing inactive player for guild {}: {:?}", guild_id, reason);
+
+            match player.pause(true) {
+                Ok(()) => guild.paused_by_us = true,
+                Err(why) => warn!("Err pausing inactive player for guild {}: {:?}", guild_id, why),
+            }
+        }
+
+        if inactive_for < self.config.timeout + self.config.leave_after {
+            return None;
+        }
+
+        guild.inactive_since = None;
+        guild.paused_by_us = false;
+
+        Some(reason)
+    }
+}
+
+impl Debug for InactivityManager {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("InactivityManager")
+            .field("user_id", &self.tracker.user_id())
+            .field("config", &self.config)
+            .finish()
+    }
+}
@@ -1,21 +1,46 @@
 #[macro_use] extern crate log;
+#[cfg(feature = "metrics")]
+#[macro_use] extern crate lazy_static;
 
 pub extern crate lavalink;
 
+extern crate base64;
 extern crate evzht9h3nznqzwl as websocket;
 extern crate hyper;
+extern crate hyper_native_tls;
+extern crate native_tls;
 extern crate parking_lot;
 extern crate percent_encoding;
 extern crate serde;
-extern crate serde_json;
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate serde_json;
 extern crate serenity;
+#[cfg(feature = "toml")]
+extern crate toml;
 
+pub mod autoplay;
+pub mod cache;
+pub mod crossfade;
+pub mod decoder;
+pub mod inactivity;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod nodes;
 pub mod player;
+pub mod recovery;
+pub mod rest;
+pub mod schedule;
+pub mod search;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod voice;
+pub mod vote_skip;
 
 mod error;
 mod listener;
 mod prelude;
+mod scheduler;
+mod tls;
 
 pub use error::{Error, Result};
-pub use listener::AudioPlayerListener;
\ No newline at end of file
+pub use listener::{AudioPlayerListener, ListenerChain};
\ No newline at end of file
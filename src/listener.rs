@@ -1,10 +1,255 @@
-use ::player::AudioPlayer;
+use lavalink::stats::RemoteStats;
+use ::inactivity::InactivityReason;
+use ::nodes::NodeHandle;
+use ::player::{AudioPlayer, TrackData};
+use ::Error;
+use std::sync::Arc;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
 
+/// Receives player and node events.
+///
+/// Every hook has a default no-op implementation, so implementors only need
+/// to write the hooks they care about.
 pub trait AudioPlayerListener: Send + Sync {
-    fn player_pause(&self, player: &mut AudioPlayer);
-    fn player_resume(&self, player: &mut AudioPlayer);
-    fn track_start(&self, player: &mut AudioPlayer, track: &str);
-    fn track_end(&self, player: &mut AudioPlayer, track: &str, reason: &str);
-    fn track_exception(&self, player: &mut AudioPlayer, track: &str, exception: &str);
-    fn track_stuck(&self, player: &mut AudioPlayer, track: &str, threshold: i64);
+    fn player_pause(&self, _player: &mut AudioPlayer) {}
+    fn player_resume(&self, _player: &mut AudioPlayer) {}
+    fn track_start(&self, _player: &mut AudioPlayer, _track: &str) {}
+    fn track_end(&self, _player: &mut AudioPlayer, _track: &str, _reason: &str) {}
+    fn track_exception(&self, _player: &mut AudioPlayer, _track: &str, _exception: &str) {}
+    fn track_stuck(&self, _player: &mut AudioPlayer, _track: &str, _threshold: i64) {}
+
+    /// Called when a track starts, along with its data.
+    ///
+    /// Defaults to calling [`track_start`].
+    ///
+    /// [`track_start`]: #method.track_start
+    fn track_start_with_data(&self, player: &mut AudioPlayer, track: &str, _data: &TrackData) {
+        self.track_start(player, track);
+    }
+
+    /// Called when a track ends, along with its data.
+    ///
+    /// The data is empty if the track was no longer the player's current
+    /// track. Defaults to calling [`track_end`].
+    ///
+    /// [`track_end`]: #method.track_end
+    fn track_end_with_data(
+        &self,
+        player: &mut AudioPlayer,
+        track: &str,
+        reason: &str,
+        _data: &TrackData,
+    ) {
+        self.track_end(player, track, reason);
+    }
+
+    /// Called after the [`NodeManager`] creates a player, once the player
+    /// manager is unlocked.
+    ///
+    /// [`NodeManager`]: nodes/struct.NodeManager.html
+    fn player_created(&self, _player: &mut AudioPlayer) {}
+
+    /// Called after the [`NodeManager`] destroys a player and removes it
+    /// from the player manager, once the player manager is unlocked.
+    ///
+    /// [`NodeManager`]: nodes/struct.NodeManager.html
+    fn player_destroyed(&self, _player: &mut AudioPlayer) {}
+
+    /// Called by the [`InactivityManager`] just before it leaves the
+    /// player's voice channel and destroys the player.
+    ///
+    /// [`InactivityManager`]: inactivity/struct.InactivityManager.html
+    fn player_inactive(&self, _player: &mut AudioPlayer, _reason: InactivityReason) {}
+
+    /// Called when a node connects, including after reconnecting.
+    fn node_connected(&self, _node: &NodeHandle) {}
+
+    /// Called when the connection to a node is lost or closed.
+    fn node_disconnected(&self, _node: &NodeHandle) {}
+
+    /// Called when a node sends its stats.
+    fn stats_received(&self, _node: &NodeHandle, _stats: &RemoteStats) {}
+
+    /// Called when the voice websocket between a node and Discord is closed.
+    fn voice_websocket_closed(
+        &self,
+        _player: &mut AudioPlayer,
+        _code: i64,
+        _reason: &str,
+        _by_remote: bool,
+    ) {}
+
+    /// Called when an error occurs that can't be returned to a caller, such
+    /// as a failure in a node's receive loop.
+    fn error(&self, _error: &Error) {}
+}
+
+/// A listener that forwards every event to several listeners, in the order
+/// they were added.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let listener = ListenerChain::new()
+///     .with(Arc::new(LoggingListener))
+///     .with(Arc::new(QueueListener::default()));
+///
+/// let manager = NodeManager::new(Arc::new(listener));
+/// ```
+#[derive(Clone, Default)]
+pub struct ListenerChain {
+    listeners: Vec<Arc<AudioPlayerListener>>,
+}
+
+impl ListenerChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a listener to the end of the chain.
+    pub fn with(mut self, listener: Arc<AudioPlayerListener>) -> Self {
+        self.listeners.push(listener);
+
+        self
+    }
+
+    /// Adds a listener to the end of the chain.
+    pub fn push(&mut self, listener: Arc<AudioPlayerListener>) {
+        self.listeners.push(listener);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
+
+impl AudioPlayerListener for ListenerChain {
+    fn player_pause(&self, player: &mut AudioPlayer) {
+        for listener in &self.listeners {
+            listener.player_pause(player);
+        }
+    }
+
+    fn player_resume(&self, player: &mut AudioPlayer) {
+        for listener in &self.listeners {
+            listener.player_resume(player);
+        }
+    }
+
+    fn track_start(&self, player: &mut AudioPlayer, track: &str) {
+        for listener in &self.listeners {
+            listener.track_start(player, track);
+        }
+    }
+
+    fn track_end(&self, player: &mut AudioPlayer, track: &str, reason: &str) {
+        for listener in &self.listeners {
+            listener.track_end(player, track, reason);
+        }
+    }
+
+    fn track_exception(&self, player: &mut AudioPlayer, track: &str, exception: &str) {
+        for listener in &self.listeners {
+            listener.track_exception(player, track, exception);
+        }
+    }
+
+    fn track_stuck(&self, player: &mut AudioPlayer, track: &str, threshold: i64) {
+        for listener in &self.listeners {
+            listener.track_stuck(player, track, threshold);
+        }
+    }
+
+    fn track_start_with_data(&self, player: &mut AudioPlayer, track: &str, data: &TrackData) {
+        for listener in &self.listeners {
+            listener.track_start_with_data(player, track, data);
+        }
+    }
+
+    fn track_end_with_data(
+        &self,
+        player: &mut AudioPlayer,
+        track: &str,
+        reason: &str,
+        data: &TrackData,
+    ) {
+        for listener in &self.listeners {
+            listener.track_end_with_data(player, track, reason, data);
+        }
+    }
+
+    fn player_created(&self, player: &mut AudioPlayer) {
+        for listener in &self.listeners {
+            listener.player_created(player);
+        }
+    }
+
+    fn player_destroyed(&self, player: &mut AudioPlayer) {
+        for listener in &self.listeners {
+            listener.player_destroyed(player);
+        }
+    }
+
+    fn player_inactive(&self, player: &mut AudioPlayer, reason: InactivityReason) {
+        for listener in &self.listeners {
+            listener.player_inactive(player, reason);
+        }
+    }
+
+    fn node_connected(&self, node: &NodeHandle) {
+        for listener in &self.listeners {
+            listener.node_connected(node);
+        }
+    }
+
+    fn node_disconnected(&self, node: &NodeHandle) {
+        for listener in &self.listeners {
+            listener.node_disconnected(node);
+        }
+    }
+
+    fn stats_received(&self, node: &NodeHandle, stats: &RemoteStats) {
+        for listener in &self.listeners {
+            listener.stats_received(node, stats);
+        }
+    }
+
+    fn voice_websocket_closed(
+        &self,
+        player: &mut AudioPlayer,
+        code: i64,
+        reason: &str,
+        by_remote: bool,
+    ) {
+        for listener in &self.listeners {
+            listener.voice_websocket_closed(player, code, reason, by_remote);
+        }
+    }
+
+    fn error(&self, error: &Error) {
+        for listener in &self.listeners {
+            listener.error(error);
+        }
+    }
+}
+
+/// Calls a listener hook, recording how long it took when metrics are enabled.
+#[inline]
+pub(crate) fn dispatch<F: FnOnce()>(hook: &'static str, f: F) {
+    #[cfg(feature = "metrics")]
+    let started = Instant::now();
+
+    f();
+
+    #[cfg(feature = "metrics")]
+    ::metrics::record_listener_latency(hook, started.elapsed());
+    #[cfg(not(feature = "metrics"))]
+    let _ = hook;
 }
@@ -0,0 +1,327 @@
+//! Prometheus text-format metrics for nodes and players.
+//!
+//! Counters are collected globally as frames flow through the nodes and
+//! listener callbacks fire, while node gauges are read from the
+//! [`NodeManager`] at render time.
+//!
+//! Use [`render`] to produce the exposition text yourself, or [`serve`] to
+//! expose it over a tiny HTTP endpoint at `/metrics`. The server doesn't keep
+//! the manager alive, so the manager can be closed while it runs.
+//!
+//! [`NodeManager`]: ../nodes/struct.NodeManager.html
+//! [`render`]: fn.render.html
+//! [`serve`]: fn.serve.html
+
+use hyper::server::{Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use nodes::NodeManager;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use ::prelude::*;
+
+/// The upper bounds, in seconds, of the listener latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    buckets: [u64; 10],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (idx, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[idx] += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    connects: BTreeMap<String, u64>,
+    frames_sent: BTreeMap<(String, String), u64>,
+    frames_received: BTreeMap<(String, String), u64>,
+    listener_latency: BTreeMap<&'static str, Histogram>,
+    track_exceptions: BTreeMap<String, u64>,
+}
+
+pub(crate) fn record_connect(node: &str) {
+    *REGISTRY.lock().connects.entry(node.to_owned()).or_insert(0) += 1;
+}
+
+pub(crate) fn record_frame_sent(node: &str, op: &str) {
+    let key = (node.to_owned(), op.to_owned());
+
+    *REGISTRY.lock().frames_sent.entry(key).or_insert(0) += 1;
+}
+
+pub(crate) fn record_frame_received(node: &str, op: &str) {
+    let key = (node.to_owned(), op.to_owned());
+
+    *REGISTRY.lock().frames_received.entry(key).or_insert(0) += 1;
+}
+
+pub(crate) fn record_listener_latency(hook: &'static str, elapsed: Duration) {
+    let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+    REGISTRY.lock()
+        .listener_latency
+        .entry(hook)
+        .or_insert_with(Histogram::default)
+        .observe(seconds);
+}
+
+pub(crate) fn record_track_exception(severity: &str) {
+    *REGISTRY.lock().track_exceptions.entry(severity.to_owned()).or_insert(0) += 1;
+}
+
+/// Renders all metrics in the Prometheus text exposition format.
+pub fn render(manager: &NodeManager) -> String {
+    let mut out = String::new();
+
+    render_nodes(&mut out, manager);
+    render_registry(&mut out, &REGISTRY.lock());
+
+    out
+}
+
+/// Serves the rendered metrics over HTTP at `/metrics` on the given address.
+///
+/// The server runs on background threads until the returned handle is
+/// closed or dropped. Once the manager is closed, requests are answered with
+/// `503 Service Unavailable`.
+pub fn serve<A: ToSocketAddrs>(addr: A, manager: &NodeManager) -> Result<MetricsServer> {
+    let server = Server::http(addr)?;
+    let manager = manager.downgrade();
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let handler_closed = Arc::clone(&closed);
+    let listening = server.handle(move |req: Request, mut res: Response| {
+        let is_metrics = match req.uri {
+            RequestUri::AbsolutePath(ref path) => path == "/metrics" || path.starts_with("/metrics?"),
+            _ => false,
+        };
+
+        if !is_metrics {
+            *res.status_mut() = StatusCode::NotFound;
+            let _ = res.send(b"not found");
+
+            return;
+        }
+
+        let manager = if handler_closed.load(Ordering::SeqCst) {
+            None
+        } else {
+            manager.upgrade()
+        };
+
+        let manager = match manager {
+            Some(manager) => manager,
+            None => {
+                *res.status_mut() = StatusCode::ServiceUnavailable;
+                let _ = res.send(b"closed");
+
+                return;
+            },
+        };
+
+        // rendered before responding so the manager isn't kept alive while
+        // the response is sent
+        let rendered = render(&manager);
+        drop(manager);
+
+        res.headers_mut().set_raw(
+            "Content-Type",
+            vec![b"text/plain; version=0.0.4".to_vec()],
+        );
+
+        if let Err(why) = res.send(rendered.as_bytes()) {
+            warn!("Err sending metrics response: {:?}", why);
+        }
+    })?;
+
+    Ok(MetricsServer {
+        addr: listening.socket,
+        listening: Some(listening),
+        closed,
+    })
+}
+
+/// A metrics server started with [`serve`], shut down when closed or
+/// dropped.
+///
+/// [`serve`]: fn.serve.html
+pub struct MetricsServer {
+    addr: SocketAddr,
+    listening: Option<Listening>,
+    closed: Arc<AtomicBool>,
+}
+
+impl MetricsServer {
+    /// The address the server is listening on.
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the server, answering any requests still being handled with
+    /// `503 Service Unavailable`.
+    pub fn close(&mut self) -> Result<()> {
+        self.closed.store(true, Ordering::SeqCst);
+
+        match self.listening.take() {
+            Some(mut listening) => listening.close().map_err(From::from),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        if let Err(why) = self.close() {
+            warn!("Err closing the metrics server: {:?}", why);
+        }
+    }
+}
+
+fn render_nodes(out: &mut String, manager: &NodeManager) {
+    let nodes = manager.nodes.read();
+
+    let mut gauges: BTreeMap<&'static str, (&'static str, Vec<(String, f64)>)> = BTreeMap::new();
+    let mut reconnects = Vec::new();
+
+    {
+        let mut gauge = |name: &'static str, help: &'static str, node: &str, value: f64| {
+            gauges.entry(name)
+                .or_insert_with(|| (help, Vec::new()))
+                .1
+                .push((node.to_owned(), value));
+        };
+
+        for node in nodes.iter() {
+            let host = &node.websocket_host;
+
+            reconnects.push((host.clone(), node.state.read().reconnects));
+
+            if let Ok(penalty) = manager.get_penalty(node) {
+                gauge("lavalink_node_penalty", "Node selection penalty score", host, f64::from(penalty));
+            }
+
+            if let Some(latency) = node.latency() {
+                let seconds = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+
+                gauge("lavalink_node_latency_seconds", "Smoothed websocket round trip time", host, seconds);
+            }
+
+            let stats = match node.stats() {
+                Some(stats) => stats,
+                None => continue,
+            };
+
+            gauge("lavalink_node_players", "Players on the node", host, f64::from(stats.players));
+            gauge("lavalink_node_playing_players", "Players on the node that are playing", host, f64::from(stats.playing_players));
+            gauge("lavalink_node_uptime_seconds", "Node uptime", host, stats.uptime as f64 / 1000f64);
+            gauge("lavalink_node_memory_free_bytes", "Free node memory", host, stats.memory.free as f64);
+            gauge("lavalink_node_memory_used_bytes", "Used node memory", host, stats.memory.used as f64);
+            gauge("lavalink_node_memory_allocated_bytes", "Allocated node memory", host, stats.memory.allocated as f64);
+            gauge("lavalink_node_memory_reservable_bytes", "Reservable node memory", host, stats.memory.reservable as f64);
+            gauge("lavalink_node_cpu_cores", "CPU cores available to the node", host, f64::from(stats.cpu.cores));
+            gauge("lavalink_node_system_load", "System CPU load of the node", host, stats.cpu.system_load);
+            gauge("lavalink_node_lavalink_load", "Lavalink CPU load of the node", host, stats.cpu.lavalink_load);
+
+            if let Some(ref frames) = stats.frame_stats {
+                gauge("lavalink_node_frames_sent", "Average frames sent per minute", host, f64::from(frames.sent));
+                gauge("lavalink_node_frames_nulled", "Average frames nulled per minute", host, f64::from(frames.nulled));
+                gauge("lavalink_node_frames_deficit", "Average frame deficit per minute", host, f64::from(frames.deficit));
+            }
+        }
+    }
+
+    for (name, &(help, ref values)) in &gauges {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+
+        for &(ref node, value) in values {
+            let _ = writeln!(out, "{}{{node=\"{}\"}} {}", name, escape(node), value);
+        }
+    }
+
+    let _ = writeln!(out, "# HELP lavalink_websocket_reconnects_total Websocket reconnections made to the node");
+    let _ = writeln!(out, "# TYPE lavalink_websocket_reconnects_total counter");
+
+    for (node, count) in reconnects {
+        let _ = writeln!(out, "lavalink_websocket_reconnects_total{{node=\"{}\"}} {}", escape(&node), count);
+    }
+}
+
+fn render_registry(out: &mut String, registry: &Registry) {
+    let _ = writeln!(out, "# HELP lavalink_websocket_connections_total Websocket connections made to the node");
+    let _ = writeln!(out, "# TYPE lavalink_websocket_connections_total counter");
+
+    for (node, count) in &registry.connects {
+        let _ = writeln!(out, "lavalink_websocket_connections_total{{node=\"{}\"}} {}", escape(node), count);
+    }
+
+    render_frames(out, "lavalink_frames_sent_total", "Websocket frames sent per op", &registry.frames_sent);
+    render_frames(out, "lavalink_frames_received_total", "Websocket frames received per op", &registry.frames_received);
+
+    let _ = writeln!(out, "# HELP lavalink_listener_callback_seconds Listener callback latency");
+    let _ = writeln!(out, "# TYPE lavalink_listener_callback_seconds histogram");
+
+    for (hook, histogram) in &registry.listener_latency {
+        for (idx, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "lavalink_listener_callback_seconds_bucket{{hook=\"{}\",le=\"{}\"}} {}",
+                hook,
+                bound,
+                histogram.buckets[idx],
+            );
+        }
+
+        let _ = writeln!(out, "lavalink_listener_callback_seconds_bucket{{hook=\"{}\",le=\"+Inf\"}} {}", hook, histogram.count);
+        let _ = writeln!(out, "lavalink_listener_callback_seconds_sum{{hook=\"{}\"}} {}", hook, histogram.sum);
+        let _ = writeln!(out, "lavalink_listener_callback_seconds_count{{hook=\"{}\"}} {}", hook, histogram.count);
+    }
+
+    let _ = writeln!(out, "# HELP lavalink_track_exceptions_total Track exceptions per severity");
+    let _ = writeln!(out, "# TYPE lavalink_track_exceptions_total counter");
+
+    for (severity, count) in &registry.track_exceptions {
+        let _ = writeln!(out, "lavalink_track_exceptions_total{{severity=\"{}\"}} {}", escape(severity), count);
+    }
+}
+
+fn render_frames(out: &mut String, name: &str, help: &str, frames: &BTreeMap<(String, String), u64>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+
+    for (&(ref node, ref op), count) in frames {
+        let _ = writeln!(out, "{}{{node=\"{}\",op=\"{}\"}} {}", name, escape(node), escape(op), count);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,812 @@
+use hyper::Url;
+use serde_json;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use ::prelude::*;
+
+#[cfg(feature = "toml")]
+use toml;
+
+/// The default interval between pings sent to a node.
+pub const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 30;
+
+/// The default time a node keeps a session to resume after the connection is
+/// lost.
+pub const DEFAULT_RESUME_TIMEOUT_SECS: u64 = 60;
+
+/// The version of the Lavalink protocol a node speaks.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Protocol {
+    /// Players are controlled by ops sent over the websocket.
+    V3,
+    /// Players are controlled through the REST API of the session the node
+    /// opens with its `ready` op.
+    V4,
+}
+
+impl Protocol {
+    /// The protocol with the given major Lavalink version, if supported.
+    pub fn from_version(version: u64) -> Option<Self> {
+        match version {
+            3 => Some(Protocol::V3),
+            4 => Some(Protocol::V4),
+            _ => None,
+        }
+    }
+
+    /// The major Lavalink version speaking the protocol.
+    pub fn version(&self) -> u64 {
+        match *self {
+            Protocol::V3 => 3,
+            Protocol::V4 => 4,
+        }
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::V3
+    }
+}
+
+/// The configuration used to connect to a Lavalink node.
+///
+/// Configs can be written as struct literals, or created through
+/// [`NodeConfig::builder`] or one of the loaders, which fill in defaults and
+/// validate the config up front. Configs are validated again when
+/// connecting.
+///
+/// [`NodeConfig::builder`]: #method.builder
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    pub http_host: String,
+    pub websocket_host: String,
+    pub user_id: String,
+    pub password: String,
+    pub num_shards: u64,
+    /// A human-readable name for the node, used in logs and metrics.
+    pub name: Option<String>,
+    /// The region the node is located in.
+    pub region: Option<String>,
+    /// The key used to resume the node's session after a reconnect or a
+    /// restart of the bot, if resuming is wanted.
+    ///
+    /// v3 nodes are sent the key with `configureResuming` and the
+    /// `Resume-Key` header. v4 nodes resume by session ID instead, so any key
+    /// enables resuming there.
+    pub resume_key: Option<String>,
+    /// How long the node keeps the session to resume after the connection is
+    /// lost.
+    pub resume_timeout: Duration,
+    /// How long to wait for the connection to be established.
+    pub connect_timeout: Option<Duration>,
+    /// How long to wait for data from the node before considering it dead.
+    ///
+    /// Defaults to three heartbeat intervals.
+    pub read_timeout: Option<Duration>,
+    /// How often to ping the node.
+    pub heartbeat_interval: Duration,
+    pub reconnect: ReconnectPolicy,
+    pub tls: TlsConfig,
+    /// The version of the protocol the node speaks, which defaults to v3.
+    pub protocol: Protocol,
+}
+
+impl NodeConfig {
+    pub fn builder() -> NodeConfigBuilder {
+        NodeConfigBuilder::default()
+    }
+
+    /// How long the node may go without sending stats or answering a ping
+    /// before it is considered dead.
+    pub fn dead_after(&self) -> Duration {
+        self.read_timeout.unwrap_or(self.heartbeat_interval * 3)
+    }
+
+    /// The name of the node, falling back to its websocket host.
+    pub fn display_name(&self) -> &str {
+        self.name.as_ref().unwrap_or(&self.websocket_host)
+    }
+
+    /// Checks that the config is usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfig`] if a host is not a valid URL with a
+    /// supported scheme, the password is empty, the user id is not numeric, or
+    /// the number of shards or the heartbeat interval is zero.
+    ///
+    /// [`Error::InvalidConfig`]: ../enum.Error.html#variant.InvalidConfig
+    pub fn validate(&self) -> Result<()> {
+        validate_url("http_host", &self.http_host, &["http", "https"])?;
+        validate_url("websocket_host", &self.websocket_host, &["ws", "wss"])?;
+
+        if self.password.is_empty() {
+            return Err(invalid("password must not be empty"));
+        }
+
+        if self.user_id.parse::<u64>().is_err() {
+            return Err(invalid(format!("user_id must be numeric, got {:?}", self.user_id)));
+        }
+
+        if self.num_shards == 0 {
+            return Err(invalid("num_shards must be at least 1"));
+        }
+
+        if self.heartbeat_interval == Duration::from_secs(0) {
+            return Err(invalid("heartbeat_interval must not be zero"));
+        }
+
+        Ok(())
+    }
+
+    /// Loads and validates a single node config from a JSON object.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str::<RawNodeConfig>(json)?.build()
+    }
+
+    /// Loads and validates a list of node configs from a JSON array.
+    pub fn list_from_json(json: &str) -> Result<Vec<Self>> {
+        serde_json::from_str::<Vec<RawNodeConfig>>(json)?
+            .into_iter()
+            .map(RawNodeConfig::build)
+            .collect()
+    }
+
+    /// Loads and validates a single node config from a TOML table.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(input: &str) -> Result<Self> {
+        toml::from_str::<RawNodeConfig>(input)
+            .map_err(|why| invalid(format!("invalid TOML: {}", why)))?
+            .build()
+    }
+
+    /// Loads and validates a list of node configs from the `[[nodes]]` array
+    /// of tables in a TOML document.
+    #[cfg(feature = "toml")]
+    pub fn list_from_toml(input: &str) -> Result<Vec<Self>> {
+        #[derive(Deserialize)]
+        struct Nodes {
+            nodes: Vec<RawNodeConfig>,
+        }
+
+        toml::from_str::<Nodes>(input)
+            .map_err(|why| invalid(format!("invalid TOML: {}", why)))?
+            .nodes
+            .into_iter()
+            .map(RawNodeConfig::build)
+            .collect()
+    }
+
+    /// Loads and validates a node config from environment variables.
+    ///
+    /// Variable names are the upper-cased field names with the given prefix,
+    /// for example `LAVALINK_HTTP_HOST` for the prefix `LAVALINK`. The
+    /// following variables are read:
+    ///
+    /// - `_HTTP_HOST`, `_WEBSOCKET_HOST`, `_USER_ID`, `_PASSWORD`, and
+    ///   `_NUM_SHARDS`, which are required;
+    /// - `_NAME`, `_REGION`, `_RESUME_KEY`, and `_RESUME_TIMEOUT_SECS`;
+    /// - `_CONNECT_TIMEOUT_MS`, `_READ_TIMEOUT_MS`, and
+    ///   `_HEARTBEAT_INTERVAL_MS`;
+    /// - `_RECONNECT`, `_RECONNECT_MAX_ATTEMPTS`,
+    ///   `_RECONNECT_INITIAL_DELAY_MS`, and `_RECONNECT_MAX_DELAY_MS`;
+    /// - `_TLS_CA_CERTIFICATES`, a list of paths separated like `PATH`, and
+    ///   `_TLS_ACCEPT_INVALID_CERTS`;
+    /// - `_PROTOCOL_VERSION`, either 3 or 4.
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let var = |name: &str| env::var(format!("{}_{}", prefix, name)).ok();
+        let parsed = |name: &str| -> Result<Option<u64>> {
+            match var(name) {
+                Some(value) => value.parse::<u64>()
+                    .map(Some)
+                    .map_err(|_| invalid(format!("{}_{} must be numeric", prefix, name))),
+                None => Ok(None),
+            }
+        };
+        let flag = |name: &str| -> Result<Option<bool>> {
+            match var(name) {
+                Some(value) => value.parse::<bool>()
+                    .map(Some)
+                    .map_err(|_| invalid(format!("{}_{} must be true or false", prefix, name))),
+                None => Ok(None),
+            }
+        };
+
+        let reconnect = RawReconnectPolicy {
+            enabled: flag("RECONNECT")?,
+            max_attempts: parsed("RECONNECT_MAX_ATTEMPTS")?.map(|attempts| attempts as u32),
+            initial_delay_ms: parsed("RECONNECT_INITIAL_DELAY_MS")?,
+            max_delay_ms: parsed("RECONNECT_MAX_DELAY_MS")?,
+        };
+        let tls = RawTlsConfig {
+            ca_certificates: var("TLS_CA_CERTIFICATES")
+                .map(|paths| env::split_paths(&paths).collect())
+                .unwrap_or_default(),
+            accept_invalid_certs: flag("TLS_ACCEPT_INVALID_CERTS")?.unwrap_or(false),
+        };
+
+        RawNodeConfig {
+            http_host: var("HTTP_HOST"),
+            websocket_host: var("WEBSOCKET_HOST"),
+            user_id: var("USER_ID").map(RawUserId::Str),
+            password: var("PASSWORD"),
+            num_shards: parsed("NUM_SHARDS")?,
+            name: var("NAME"),
+            region: var("REGION"),
+            resume_key: var("RESUME_KEY"),
+            resume_timeout_secs: parsed("RESUME_TIMEOUT_SECS")?,
+            connect_timeout_ms: parsed("CONNECT_TIMEOUT_MS")?,
+            read_timeout_ms: parsed("READ_TIMEOUT_MS")?,
+            heartbeat_interval_ms: parsed("HEARTBEAT_INTERVAL_MS")?,
+            reconnect: Some(reconnect),
+            tls: Some(tls),
+            protocol_version: parsed("PROTOCOL_VERSION")?,
+        }.build()
+    }
+}
+
+/// A builder for a validated [`NodeConfig`].
+///
+/// [`NodeConfig`]: struct.NodeConfig.html
+#[derive(Clone, Debug)]
+pub struct NodeConfigBuilder {
+    http_host: Option<String>,
+    websocket_host: Option<String>,
+    user_id: Option<String>,
+    password: Option<String>,
+    num_shards: Option<u64>,
+    name: Option<String>,
+    region: Option<String>,
+    resume_key: Option<String>,
+    resume_timeout: Duration,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    heartbeat_interval: Duration,
+    reconnect: ReconnectPolicy,
+    tls: TlsConfig,
+    protocol: Protocol,
+}
+
+impl Default for NodeConfigBuilder {
+    fn default() -> Self {
+        Self {
+            http_host: None,
+            websocket_host: None,
+            user_id: None,
+            password: None,
+            num_shards: None,
+            name: None,
+            region: None,
+            resume_key: None,
+            resume_timeout: Duration::from_secs(DEFAULT_RESUME_TIMEOUT_SECS),
+            connect_timeout: None,
+            read_timeout: None,
+            heartbeat_interval: Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL_SECS),
+            reconnect: ReconnectPolicy::default(),
+            tls: TlsConfig::default(),
+            protocol: Protocol::default(),
+        }
+    }
+}
+
+impl NodeConfigBuilder {
+    pub fn http_host<S: Into<String>>(mut self, http_host: S) -> Self {
+        self.http_host = Some(http_host.into());
+
+        self
+    }
+
+    pub fn websocket_host<S: Into<String>>(mut self, websocket_host: S) -> Self {
+        self.websocket_host = Some(websocket_host.into());
+
+        self
+    }
+
+    pub fn user_id<S: ToString>(mut self, user_id: S) -> Self {
+        self.user_id = Some(user_id.to_string());
+
+        self
+    }
+
+    pub fn password<S: Into<String>>(mut self, password: S) -> Self {
+        self.password = Some(password.into());
+
+        self
+    }
+
+    pub fn num_shards(mut self, num_shards: u64) -> Self {
+        self.num_shards = Some(num_shards);
+
+        self
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+
+        self
+    }
+
+    pub fn region<S: Into<String>>(mut self, region: S) -> Self {
+        self.region = Some(region.into());
+
+        self
+    }
+
+    pub fn resume_key<S: Into<String>>(mut self, resume_key: S) -> Self {
+        self.resume_key = Some(resume_key.into());
+
+        self
+    }
+
+    pub fn resume_timeout(mut self, timeout: Duration) -> Self {
+        self.resume_timeout = timeout;
+
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+
+        self
+    }
+
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+
+        self
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+
+        self
+    }
+
+    /// Builds and validates the config.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfig`] if a required field is missing or the
+    /// config fails [`NodeConfig::validate`].
+    ///
+    /// [`Error::InvalidConfig`]: ../enum.Error.html#variant.InvalidConfig
+    /// [`NodeConfig::validate`]: struct.NodeConfig.html#method.validate
+    pub fn build(self) -> Result<NodeConfig> {
+        let config = NodeConfig {
+            http_host: required("http_host", self.http_host)?,
+            websocket_host: required("websocket_host", self.websocket_host)?,
+            user_id: required("user_id", self.user_id)?,
+            password: required("password", self.password)?,
+            num_shards: required("num_shards", self.num_shards)?,
+            name: self.name,
+            region: self.region,
+            resume_key: self.resume_key,
+            resume_timeout: self.resume_timeout,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            heartbeat_interval: self.heartbeat_interval,
+            reconnect: self.reconnect,
+            tls: self.tls,
+            protocol: self.protocol,
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+/// How to reconnect to a node after its connection is lost.
+///
+/// Delays grow exponentially from `initial_delay`, doubling after each failed
+/// attempt, up to `max_delay`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    /// The maximum number of consecutive attempts, or `None` to retry
+    /// forever.
+    pub max_attempts: Option<u32>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// A policy that never reconnects.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// The delay to wait before the given attempt, starting at 0.
+    ///
+    /// Returns `None` if no more attempts should be made.
+    pub fn delay_for(&self, attempt: u32) -> Option<Duration> {
+        if !self.enabled || self.max_attempts.map_or(false, |max| attempt >= max) {
+            return None;
+        }
+
+        let factor = 1u32.checked_shl(attempt.min(31)).unwrap_or(u32::max_value());
+        let delay = self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay);
+
+        Some(delay.min(self.max_delay))
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: None,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+/// TLS settings used for `wss://` and `https://` hosts.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TlsConfig {
+    /// Paths to additional PEM-encoded root certificates to trust.
+    pub ca_certificates: Vec<PathBuf>,
+    /// Whether to accept invalid certificates and hostnames.
+    ///
+    /// This should only be enabled in development.
+    pub accept_invalid_certs: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawNodeConfig {
+    http_host: Option<String>,
+    websocket_host: Option<String>,
+    user_id: Option<RawUserId>,
+    password: Option<String>,
+    num_shards: Option<u64>,
+    name: Option<String>,
+    region: Option<String>,
+    resume_key: Option<String>,
+    resume_timeout_secs: Option<u64>,
+    connect_timeout_ms: Option<u64>,
+    read_timeout_ms: Option<u64>,
+    heartbeat_interval_ms: Option<u64>,
+    reconnect: Option<RawReconnectPolicy>,
+    tls: Option<RawTlsConfig>,
+    protocol_version: Option<u64>,
+}
+
+impl RawNodeConfig {
+    fn build(self) -> Result<NodeConfig> {
+        let mut builder = NodeConfig::builder();
+        builder.http_host = self.http_host;
+        builder.websocket_host = self.websocket_host;
+        builder.user_id = self.user_id.map(|id| match id {
+            RawUserId::Num(id) => id.to_string(),
+            RawUserId::Str(id) => id,
+        });
+        builder.password = self.password;
+        builder.num_shards = self.num_shards;
+        builder.name = self.name;
+        builder.region = self.region;
+        builder.resume_key = self.resume_key;
+        builder.connect_timeout = self.connect_timeout_ms.map(Duration::from_millis);
+        builder.read_timeout = self.read_timeout_ms.map(Duration::from_millis);
+
+        if let Some(timeout) = self.resume_timeout_secs {
+            builder.resume_timeout = Duration::from_secs(timeout);
+        }
+
+        if let Some(interval) = self.heartbeat_interval_ms {
+            builder.heartbeat_interval = Duration::from_millis(interval);
+        }
+
+        if let Some(reconnect) = self.reconnect {
+            let default = ReconnectPolicy::default();
+
+            builder.reconnect = ReconnectPolicy {
+                enabled: reconnect.enabled.unwrap_or(default.enabled),
+                max_attempts: reconnect.max_attempts,
+                initial_delay: reconnect.initial_delay_ms
+                    .map_or(default.initial_delay, Duration::from_millis),
+                max_delay: reconnect.max_delay_ms
+                    .map_or(default.max_delay, Duration::from_millis),
+            };
+        }
+
+        if let Some(version) = self.protocol_version {
+            builder.protocol = Protocol::from_version(version).ok_or_else(|| {
+                invalid(format!("protocol_version must be 3 or 4, got {}", version))
+            })?;
+        }
+
+        if let Some(tls) = self.tls {
+            builder.tls = TlsConfig {
+                ca_certificates: tls.ca_certificates,
+                accept_invalid_certs: tls.accept_invalid_certs,
+            };
+        }
+
+        builder.build()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawUserId {
+    Num(u64),
+    Str(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawReconnectPolicy {
+    enabled: Option<bool>,
+    max_attempts: Option<u32>,
+    initial_delay_ms: Option<u64>,
+    max_delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawTlsConfig {
+    ca_certificates: Vec<PathBuf>,
+    accept_invalid_certs: bool,
+}
+
+fn invalid<S: Into<String>>(reason: S) -> Error {
+    Error::InvalidConfig(reason.into())
+}
+
+fn required<T>(field: &str, value: Option<T>) -> Result<T> {
+    value.ok_or_else(|| invalid(format!("{} is required", field)))
+}
+
+fn validate_url(field: &str, value: &str, schemes: &[&str]) -> Result<()> {
+    let url = Url::parse(value)
+        .map_err(|why| invalid(format!("{} is not a valid URL ({}): {:?}", field, why, value)))?;
+
+    if !schemes.contains(&url.scheme()) {
+        return Err(invalid(format!(
+            "{} must use one of the schemes {:?}, got {:?}",
+            field,
+            schemes,
+            url.scheme(),
+        )));
+    }
+
+    if url.host_str().map_or(true, str::is_empty) {
+        return Err(invalid(format!("{} has no host: {:?}", field, value)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::time::Duration;
+    use super::*;
+
+    fn builder() -> NodeConfigBuilder {
+        NodeConfig::builder()
+            .http_host("http://localhost:2333")
+            .websocket_host("ws://localhost:80")
+            .user_id(1u64)
+            .password("password")
+            .num_shards(1)
+    }
+
+    fn assert_invalid(result: Result<NodeConfig>, reason: &str) {
+        match result {
+            Err(Error::InvalidConfig(ref why)) if why.contains(reason) => {},
+            other => panic!("expected an invalid config mentioning {:?}, got {:?}", reason, other),
+        }
+    }
+
+    #[test]
+    fn builder_uses_defaults() {
+        let config = builder().build().unwrap();
+
+        assert_eq!(config.user_id, "1");
+        assert_eq!(config.heartbeat_interval, Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL_SECS));
+        assert_eq!(config.resume_timeout, Duration::from_secs(DEFAULT_RESUME_TIMEOUT_SECS));
+        assert_eq!(config.dead_after(), config.heartbeat_interval * 3);
+        assert_eq!(config.display_name(), "ws://localhost:80");
+        assert_eq!(config.reconnect, ReconnectPolicy::default());
+        assert_eq!(config.protocol, Protocol::V3);
+        assert!(config.resume_key.is_none());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_fields() {
+        assert_invalid(NodeConfig::builder().build(), "http_host is required");
+        assert_invalid(builder().http_host("localhost:2333").build(), "http_host");
+        assert_invalid(builder().http_host("ws://localhost:2333").build(), "http_host must use");
+        assert_invalid(builder().websocket_host("http://localhost").build(), "websocket_host must use");
+        assert_invalid(builder().password("").build(), "password");
+        assert_invalid(builder().user_id("bot").build(), "user_id");
+        assert_invalid(builder().num_shards(0).build(), "num_shards");
+        assert_invalid(builder().heartbeat_interval(Duration::from_secs(0)).build(), "heartbeat_interval");
+
+        let mut config = builder().build().unwrap();
+        assert!(config.validate().is_ok());
+
+        config.websocket_host = "wss://".to_owned();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn from_json_reads_every_field() {
+        let config = NodeConfig::from_json(r#"{
+            "http_host": "https://lavalink.example.com",
+            "websocket_host": "wss://lavalink.example.com",
+            "user_id": 1234,
+            "password": "password",
+            "num_shards": 2,
+            "name": "main",
+            "region": "eu",
+            "resume_key": "key",
+            "resume_timeout_secs": 30,
+            "connect_timeout_ms": 1000,
+            "read_timeout_ms": 2000,
+            "heartbeat_interval_ms": 3000,
+            "reconnect": {"enabled": true, "max_attempts": 5, "initial_delay_ms": 100},
+            "tls": {"ca_certificates": ["ca.pem"], "accept_invalid_certs": true},
+            "protocol_version": 4
+        }"#).unwrap();
+
+        assert_eq!(config.user_id, "1234");
+        assert_eq!(config.num_shards, 2);
+        assert_eq!(config.display_name(), "main");
+        assert_eq!(config.region, Some("eu".to_owned()));
+        assert_eq!(config.resume_key, Some("key".to_owned()));
+        assert_eq!(config.resume_timeout, Duration::from_secs(30));
+        assert_eq!(config.connect_timeout, Some(Duration::from_millis(1000)));
+        assert_eq!(config.dead_after(), Duration::from_millis(2000));
+        assert_eq!(config.heartbeat_interval, Duration::from_millis(3000));
+        assert_eq!(config.reconnect.max_attempts, Some(5));
+        assert_eq!(config.reconnect.initial_delay, Duration::from_millis(100));
+        assert_eq!(config.reconnect.max_delay, ReconnectPolicy::default().max_delay);
+        assert_eq!(config.tls.ca_certificates.len(), 1);
+        assert!(config.tls.accept_invalid_certs);
+        assert_eq!(config.protocol, Protocol::V4);
+    }
+
+    #[test]
+    fn from_json_validates() {
+        assert_invalid(NodeConfig::from_json(r#"{"http_host": "http://localhost"}"#), "required");
+        assert_invalid(NodeConfig::from_json(r#"{
+            "http_host": "http://localhost",
+            "websocket_host": "ws://localhost",
+            "user_id": "1",
+            "password": "password",
+            "num_shards": 1,
+            "protocol_version": 2
+        }"#), "protocol_version");
+        assert!(NodeConfig::from_json("{").is_err());
+
+        let configs = NodeConfig::list_from_json(r#"[
+            {"http_host": "http://a", "websocket_host": "ws://a", "user_id": "1", "password": "p", "num_shards": 1},
+            {"http_host": "http://b", "websocket_host": "ws://b", "user_id": "1", "password": "p", "num_shards": 1}
+        ]"#).unwrap();
+
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[1].websocket_host, "ws://b");
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn from_toml_reads_tables() {
+        let config = NodeConfig::from_toml(r#"
+            http_host = "http://localhost:2333"
+            websocket_host = "ws://localhost:2333"
+            user_id = "1"
+            password = "password"
+            num_shards = 1
+            resume_key = "key"
+
+            [reconnect]
+            enabled = false
+        "#).unwrap();
+
+        assert_eq!(config.resume_key, Some("key".to_owned()));
+        assert!(!config.reconnect.enabled);
+
+        let configs = NodeConfig::list_from_toml(r#"
+            [[nodes]]
+            http_host = "http://a"
+            websocket_host = "ws://a"
+            user_id = 1
+            password = "p"
+            num_shards = 1
+
+            [[nodes]]
+            http_host = "http://b"
+            websocket_host = "ws://b"
+            user_id = 1
+            password = "p"
+            num_shards = 1
+            protocol_version = 4
+        "#).unwrap();
+
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[1].protocol, Protocol::V4);
+
+        assert_invalid(NodeConfig::from_toml("http_host = "), "invalid TOML");
+        assert_invalid(NodeConfig::from_toml(r#"http_host = "http://a""#), "required");
+    }
+
+    #[test]
+    fn from_env_reads_prefixed_variables() {
+        // each test uses its own prefix, as tests share the environment
+        let vars = [
+            ("HTTP_HOST", "http://localhost:2333"),
+            ("WEBSOCKET_HOST", "ws://localhost:2333"),
+            ("USER_ID", "1"),
+            ("PASSWORD", "password"),
+            ("NUM_SHARDS", "3"),
+            ("RESUME_KEY", "key"),
+            ("HEARTBEAT_INTERVAL_MS", "5000"),
+            ("RECONNECT", "false"),
+            ("PROTOCOL_VERSION", "4"),
+        ];
+
+        for &(name, value) in &vars {
+            env::set_var(format!("CONFIG_TEST_ENV_{}", name), value);
+        }
+
+        let config = NodeConfig::from_env("CONFIG_TEST_ENV").unwrap();
+
+        assert_eq!(config.num_shards, 3);
+        assert_eq!(config.resume_key, Some("key".to_owned()));
+        assert_eq!(config.heartbeat_interval, Duration::from_millis(5000));
+        assert!(!config.reconnect.enabled);
+        assert_eq!(config.protocol, Protocol::V4);
+
+        env::set_var("CONFIG_TEST_ENV_NUM_SHARDS", "many");
+        assert_invalid(NodeConfig::from_env("CONFIG_TEST_ENV"), "CONFIG_TEST_ENV_NUM_SHARDS must be numeric");
+
+        env::set_var("CONFIG_TEST_ENV_NUM_SHARDS", "1");
+        env::set_var("CONFIG_TEST_ENV_RECONNECT", "sometimes");
+        assert_invalid(NodeConfig::from_env("CONFIG_TEST_ENV"), "must be true or false");
+
+        assert_invalid(NodeConfig::from_env("CONFIG_TEST_MISSING"), "http_host is required");
+    }
+
+    #[test]
+    fn reconnect_delays_grow_up_to_the_maximum() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(10),
+            ..ReconnectPolicy::default()
+        };
+
+        assert_eq!(policy.delay_for(0), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay_for(3), Some(Duration::from_secs(8)));
+        assert_eq!(policy.delay_for(9), Some(Duration::from_secs(60)));
+        assert_eq!(policy.delay_for(10), None);
+        assert_eq!(ReconnectPolicy::disabled().delay_for(0), None);
+    }
+}
@@ -0,0 +1,159 @@
+use hyper::Url;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use super::{NodeConfig, Protocol};
+use tls::{self, SharedTlsStream};
+use websocket::header::Headers;
+use websocket::receiver::Reader as WebSocketReader;
+use websocket::sender::Writer as WebSocketWriter;
+use websocket::stream::sync::ReadWritePair;
+use websocket::{ClientBuilder, OwnedMessage};
+use ::prelude::*;
+
+pub type NodeReader = Box<Read + Send>;
+pub type NodeWriter = Box<Write + Send>;
+pub type NodeStream = ReadWritePair<NodeReader, NodeWriter>;
+
+/// An established websocket connection to a node.
+pub struct Connection {
+    pub reader: WebSocketReader<NodeReader>,
+    pub writer: ConnectionWriter,
+}
+
+/// The writing half of a connection, which can also tear the connection down.
+pub struct ConnectionWriter {
+    writer: WebSocketWriter<NodeWriter>,
+    socket: TcpStream,
+}
+
+impl ConnectionWriter {
+    pub fn send(&mut self, message: &OwnedMessage) -> Result<()> {
+        self.writer.send_message(message).map_err(From::from)
+    }
+
+    /// Shuts down the underlying socket, unblocking any pending read on the
+    /// connection.
+    pub fn shutdown(&self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+/// The path of the websocket endpoint of v4 nodes.
+const V4_WEBSOCKET_PATH: &str = "/v4/websocket";
+
+/// Connects to a node's websocket host and performs the websocket handshake.
+///
+/// The connection is wrapped in TLS for `wss://` hosts, and the config's
+/// connect timeout applies to establishing the socket and to each handshake,
+/// including the websocket upgrade over TLS.
+///
+/// If the config has a resume key, the handshake asks to resume the v3
+/// session with that key, or the given previous v4 session.
+pub fn connect(config: &NodeConfig, session_id: Option<&str>) -> Result<Connection> {
+    let mut headers = Headers::new();
+    headers.set_raw("Authorization", vec![config.password.clone().as_bytes().to_vec()]);
+    headers.set_raw("Num-Shards", vec![config.num_shards.to_string().as_bytes().to_vec()]);
+    headers.set_raw("User-Id", vec![config.user_id.clone().as_bytes().to_vec()]);
+
+    if config.protocol == Protocol::V4 {
+        let client_name = concat!("serenity-lavalink/", env!("CARGO_PKG_VERSION"));
+
+        headers.set_raw("Client-Name", vec![client_name.as_bytes().to_vec()]);
+    }
+
+    if let Some(ref resume_key) = config.resume_key {
+        match config.protocol {
+            Protocol::V3 => headers.set_raw("Resume-Key", vec![resume_key.as_bytes().to_vec()]),
+            Protocol::V4 => if let Some(session_id) = session_id {
+                headers.set_raw("Session-Id", vec![session_id.as_bytes().to_vec()]);
+            },
+        }
+    }
+
+    let (stream, socket, tls) = open(config)?;
+
+    let client = ClientBuilder::new(&websocket_url(config)?)?
+        .add_protocol("rust-websocket")
+        .custom_headers(&headers)
+        .connect_on(stream)?;
+
+    // TLS streams poll the socket with their own short timeout, so only plain
+    // sockets use the dead node window as their read timeout
+    match tls {
+        Some(tls) => tls.set_established(),
+        None => socket.set_read_timeout(Some(config.dead_after()))?,
+    }
+    socket.set_write_timeout(None)?;
+
+    let (reader, writer) = client.split()?;
+
+    Ok(Connection {
+        reader,
+        writer: ConnectionWriter {
+            writer,
+            socket,
+        },
+    })
+}
+
+/// The URL of the node's websocket endpoint, which v4 nodes serve at
+/// `/v4/websocket` unless the host already has a path.
+fn websocket_url(config: &NodeConfig) -> Result<String> {
+    let mut url = Url::parse(&config.websocket_host)?;
+
+    if config.protocol == Protocol::V4 && (url.path().is_empty() || url.path() == "/") {
+        url.set_path(V4_WEBSOCKET_PATH);
+    }
+
+    Ok(url.into_string())
+}
+
+/// Opens the underlying stream to a node's websocket host.
+///
+/// Returns the stream, a handle to its socket, and the TLS stream if it uses
+/// TLS.
+fn open(config: &NodeConfig) -> Result<(NodeStream, TcpStream, Option<SharedTlsStream>)> {
+    let url = Url::parse(&config.websocket_host)?;
+    let host = url.host_str().unwrap_or_default().to_owned();
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let stream = connect_tcp(&host, port, config)?;
+    let socket = stream.try_clone()?;
+
+    if url.scheme() != "wss" {
+        let reader = stream.try_clone()?;
+
+        return Ok((ReadWritePair(Box::new(reader), Box::new(stream)), socket, None));
+    }
+
+    let stream = tls::connector(&config.tls)?.connect(&host, stream)?;
+    let stream = SharedTlsStream::new(stream, config.connect_timeout)?;
+
+    Ok((ReadWritePair(Box::new(stream.clone()), Box::new(stream.clone())), socket, Some(stream)))
+}
+
+fn connect_tcp(host: &str, port: u16, config: &NodeConfig) -> Result<TcpStream> {
+    let timeout = match config.connect_timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((host, port)).map_err(From::from),
+    };
+
+    let mut last_err = None;
+
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+
+                return Ok(stream);
+            },
+            Err(why) => last_err = Some(why),
+        }
+    }
+
+    Err(Error::Io(last_err.unwrap_or_else(|| IoError::new(
+        ErrorKind::AddrNotAvailable,
+        "could not resolve the websocket host",
+    ))))
+}
@@ -0,0 +1,239 @@
+use rest::RestClient;
+use serde_json;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use super::{v4, NodeSender, NodeState, NodeStatus, Protocol, RestSender};
+use websocket::OwnedMessage;
+use ::prelude::*;
+
+/// An op sent to a node to control a guild's player.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutgoingOp {
+    Play {
+        guild_id: u64,
+        track: String,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    },
+    Stop {
+        guild_id: u64,
+    },
+    Pause {
+        guild_id: u64,
+        pause: bool,
+    },
+    Seek {
+        guild_id: u64,
+        position: i64,
+    },
+    Volume {
+        guild_id: u64,
+        volume: i32,
+    },
+    Destroy {
+        guild_id: u64,
+    },
+    VoiceUpdate {
+        guild_id: u64,
+        session_id: String,
+        /// The raw `VOICE_SERVER_UPDATE` event received from Discord.
+        event: Value,
+    },
+}
+
+impl OutgoingOp {
+    /// The name of the op in the websocket protocol.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OutgoingOp::Play { .. } => "play",
+            OutgoingOp::Stop { .. } => "stop",
+            OutgoingOp::Pause { .. } => "pause",
+            OutgoingOp::Seek { .. } => "seek",
+            OutgoingOp::Volume { .. } => "volume",
+            OutgoingOp::Destroy { .. } => "destroy",
+            OutgoingOp::VoiceUpdate { .. } => "voiceUpdate",
+        }
+    }
+
+    /// The ID of the guild whose player the op controls.
+    pub fn guild_id(&self) -> u64 {
+        match *self {
+            OutgoingOp::Play { guild_id, .. }
+            | OutgoingOp::Stop { guild_id }
+            | OutgoingOp::Pause { guild_id, .. }
+            | OutgoingOp::Seek { guild_id, .. }
+            | OutgoingOp::Volume { guild_id, .. }
+            | OutgoingOp::Destroy { guild_id }
+            | OutgoingOp::VoiceUpdate { guild_id, .. } => guild_id,
+        }
+    }
+
+    /// Serializes the op into its websocket payload.
+    pub fn to_json(&self) -> Value {
+        let mut json = json!({
+            "op": self.name(),
+            "guildId": self.guild_id().to_string(),
+        });
+
+        match *self {
+            OutgoingOp::Play { ref track, start_time, end_time, .. } => {
+                json["track"] = json!(track);
+
+                if let Some(start_time) = start_time {
+                    json["startTime"] = json!(start_time);
+                }
+
+                if let Some(end_time) = end_time {
+                    json["endTime"] = json!(end_time);
+                }
+            },
+            OutgoingOp::Pause { pause, .. } => json["pause"] = json!(pause),
+            OutgoingOp::Seek { position, .. } => json["position"] = json!(position),
+            OutgoingOp::Volume { volume, .. } => json["volume"] = json!(volume),
+            OutgoingOp::VoiceUpdate { ref session_id, ref event, .. } => {
+                json["sessionId"] = json!(session_id);
+                json["event"] = event.clone();
+            },
+            OutgoingOp::Stop { .. } | OutgoingOp::Destroy { .. } => {},
+        }
+
+        json
+    }
+}
+
+/// A handle to a node, used to send it typed ops.
+///
+/// Ops are sent over the websocket to v3 nodes, and as REST requests to the
+/// player in the session of v4 nodes. Either way they are queued for the
+/// node's own thread rather than sent on the calling one.
+///
+/// Handles are cheap to clone and stay valid across reconnects. Sending fails
+/// while the node is reconnecting, as ops can't reach the node, and once the
+/// node is closed or has given up reconnecting.
+#[derive(Clone)]
+pub struct NodeHandle {
+    name: String,
+    websocket_host: String,
+    sender: NodeSender,
+    /// Where REST requests are queued, for v4 nodes.
+    rest_sender: Option<RestSender>,
+    rest: RestClient,
+    state: NodeState,
+}
+
+impl NodeHandle {
+    pub(crate) fn new(
+        name: String,
+        websocket_host: String,
+        sender: NodeSender,
+        rest_sender: Option<RestSender>,
+        rest: RestClient,
+        state: NodeState,
+    ) -> Self {
+        Self {
+            name,
+            websocket_host,
+            sender,
+            rest_sender,
+            rest,
+            state,
+        }
+    }
+
+    /// The name of the node, which is its websocket host unless configured
+    /// otherwise.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The websocket host of the node, which uniquely identifies it.
+    #[inline]
+    pub fn websocket_host(&self) -> &str {
+        &self.websocket_host
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        self.state.read().status
+    }
+
+    /// Whether the node was closed or gave up reconnecting, meaning no more
+    /// ops can be sent through this handle.
+    pub fn is_closed(&self) -> bool {
+        match self.status() {
+            NodeStatus::Closed | NodeStatus::Dead => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this handle refers to the same node as another.
+    pub fn is_same_node(&self, other: &NodeHandle) -> bool {
+        self.websocket_host == other.websocket_host
+    }
+
+    /// The protocol ops are sent to the node with.
+    #[inline]
+    pub fn protocol(&self) -> Protocol {
+        self.rest.protocol()
+    }
+
+    /// The shared state of the node.
+    pub fn state(&self) -> &NodeState {
+        &self.state
+    }
+
+    /// Sends an op to the node.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NodeReconnecting`] if the node lost its connection
+    /// and has not reconnected yet, [`Error::NodeClosed`] if the node is
+    /// closed or gave up reconnecting, or [`Error::NoSession`] if a v4 node
+    /// has not opened its session yet.
+    ///
+    /// Requests to v4 nodes are made after this returns, so a request the
+    /// node fails is logged rather than returned.
+    ///
+    /// [`Error::NodeClosed`]: ../enum.Error.html#variant.NodeClosed
+    /// [`Error::NodeReconnecting`]: ../enum.Error.html#variant.NodeReconnecting
+    /// [`Error::NoSession`]: ../enum.Error.html#variant.NoSession
+    pub fn send(&self, op: OutgoingOp) -> Result<()> {
+        match self.status() {
+            NodeStatus::Connected => {},
+            NodeStatus::Connecting | NodeStatus::Reconnecting => return Err(Error::NodeReconnecting),
+            NodeStatus::Closed | NodeStatus::Dead => return Err(Error::NodeClosed),
+        }
+
+        if let Some(ref rest_sender) = self.rest_sender {
+            let session_id = self.state.read().session_id.clone().ok_or(Error::NoSession)?;
+
+            #[cfg(feature = "metrics")]
+            ::metrics::record_frame_sent(&self.websocket_host, op.name());
+
+            let request = v4::RestRequest::Op {
+                session_id,
+                op,
+            };
+
+            return rest_sender.lock().send(request).map_err(|_| Error::NodeClosed);
+        }
+
+        let message = OwnedMessage::Text(serde_json::to_string(&op.to_json())?);
+
+        self.sender.lock().send(message).map_err(|_| Error::NodeClosed)?;
+
+        #[cfg(feature = "metrics")]
+        ::metrics::record_frame_sent(&self.websocket_host, op.name());
+
+        Ok(())
+    }
+}
+
+impl Debug for NodeHandle {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("NodeHandle")
+            .field("name", &self.name)
+            .field("websocket_host", &self.websocket_host)
+            .field("protocol", &self.protocol())
+            .finish()
+    }
+}
@@ -0,0 +1,284 @@
+use lavalink::stats::RemoteStats;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The default number of samples retained by a [`StatsHistory`].
+///
+/// Lavalink sends a stats frame roughly every minute, so this keeps around six
+/// hours of history.
+///
+/// [`StatsHistory`]: struct.StatsHistory.html
+pub const DEFAULT_HISTORY_CAPACITY: usize = 360;
+
+/// A single stats frame received from a node, along with when it arrived.
+#[derive(Clone, Debug)]
+pub struct StatsSample {
+    pub received_at: Instant,
+    pub stats: RemoteStats,
+}
+
+/// A numeric field of [`RemoteStats`] that can be summarized over time.
+///
+/// [`RemoteStats`]: ../../lavalink/stats/struct.RemoteStats.html
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StatsField {
+    Players,
+    PlayingPlayers,
+    Uptime,
+    MemoryFree,
+    MemoryUsed,
+    MemoryAllocated,
+    MemoryReservable,
+    CpuCores,
+    SystemLoad,
+    LavalinkLoad,
+    FramesSent,
+    FramesNulled,
+    FramesDeficit,
+}
+
+impl StatsField {
+    /// Extracts the value of this field from a stats frame.
+    ///
+    /// Returns `None` for frame fields when the node did not report frame
+    /// stats.
+    pub fn extract(&self, stats: &RemoteStats) -> Option<f64> {
+        use self::StatsField::*;
+
+        let value = match *self {
+            Players => f64::from(stats.players),
+            PlayingPlayers => f64::from(stats.playing_players),
+            Uptime => stats.uptime as f64,
+            MemoryFree => stats.memory.free as f64,
+            MemoryUsed => stats.memory.used as f64,
+            MemoryAllocated => stats.memory.allocated as f64,
+            MemoryReservable => stats.memory.reservable as f64,
+            CpuCores => f64::from(stats.cpu.cores),
+            SystemLoad => stats.cpu.system_load,
+            LavalinkLoad => stats.cpu.lavalink_load,
+            FramesSent => f64::from(stats.frame_stats.as_ref()?.sent),
+            FramesNulled => f64::from(stats.frame_stats.as_ref()?.nulled),
+            FramesDeficit => f64::from(stats.frame_stats.as_ref()?.deficit),
+        };
+
+        Some(value)
+    }
+}
+
+/// The minimum, average, and maximum of a field over a window of samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatsSummary {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+    /// The number of samples that contributed to the summary.
+    pub samples: usize,
+}
+
+/// A bounded ring buffer of the most recent stats frames received from a node.
+#[derive(Clone, Debug)]
+pub struct StatsHistory {
+    capacity: usize,
+    samples: VecDeque<StatsSample>,
+}
+
+impl StatsHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records a new stats frame, evicting the oldest one if the history is
+    /// full.
+    pub fn push(&mut self, stats: RemoteStats) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        while self.samples.len() >= self.capacity {
+            let _ = self.samples.pop_front();
+        }
+
+        self.samples.push_back(StatsSample {
+            received_at: Instant::now(),
+            stats,
+        });
+    }
+
+    /// The maximum number of samples retained.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The most recently received sample, if any.
+    pub fn latest(&self) -> Option<&StatsSample> {
+        self.samples.back()
+    }
+
+    /// Iterates over the retained samples, oldest first.
+    pub fn samples(&self) -> ::std::collections::vec_deque::Iter<StatsSample> {
+        self.samples.iter()
+    }
+
+    /// Iterates over the samples received within the given window, oldest
+    /// first.
+    pub fn within(&self, window: Duration) -> Vec<&StatsSample> {
+        let now = Instant::now();
+
+        self.samples
+            .iter()
+            .filter(|sample| now.duration_since(sample.received_at) <= window)
+            .collect()
+    }
+
+    /// Summarizes a field over the samples received within the given window.
+    ///
+    /// Returns `None` if no sample within the window has a value for the
+    /// field.
+    pub fn summarize(&self, field: StatsField, window: Duration) -> Option<StatsSummary> {
+        let values = self.within(window)
+            .into_iter()
+            .filter_map(|sample| field.extract(&sample.stats))
+            .collect::<Vec<f64>>();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        let min = values.iter().cloned().fold(::std::f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max);
+        let avg = values.iter().sum::<f64>() / values.len() as f64;
+
+        Some(StatsSummary {
+            min,
+            avg,
+            max,
+            samples: values.len(),
+        })
+    }
+
+    /// Drops all retained samples.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+impl Default for StatsHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lavalink::stats::RemoteStats;
+    use serde_json;
+    use std::time::Duration;
+    use super::{StatsField, StatsHistory};
+
+    /// Stats with the given number of players and, optionally, frames sent.
+    fn stats(players: i32, frames_sent: Option<i32>) -> RemoteStats {
+        let mut json = json!({
+            "op": "stats",
+            "players": players,
+            "playingPlayers": players,
+            "uptime": 0,
+            "memory": {
+                "free": 0,
+                "used": 0,
+                "allocated": 0,
+                "reservable": 0,
+            },
+            "cpu": {
+                "cores": 1,
+                "systemLoad": 0.5,
+                "lavalinkLoad": 0.25,
+            },
+        });
+
+        if let Some(sent) = frames_sent {
+            json["frameStats"] = json!({
+                "sent": sent,
+                "nulled": 0,
+                "deficit": 0,
+            });
+        }
+
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn players(history: &StatsHistory) -> Vec<f64> {
+        history.samples().map(|sample| f64::from(sample.stats.players)).collect()
+    }
+
+    #[test]
+    fn evicts_the_oldest_samples_at_capacity() {
+        let mut history = StatsHistory::new(3);
+
+        for i in 0..5 {
+            history.push(stats(i, None));
+        }
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(players(&history), vec![2.0, 3.0, 4.0]);
+        assert_eq!(history.latest().map(|sample| f64::from(sample.stats.players)), Some(4.0));
+    }
+
+    #[test]
+    fn keeps_nothing_with_no_capacity() {
+        let mut history = StatsHistory::new(0);
+
+        history.push(stats(1, None));
+
+        assert!(history.is_empty());
+        assert!(history.latest().is_none());
+        assert_eq!(history.summarize(StatsField::Players, Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn summarizes_fields_over_samples() {
+        let mut history = StatsHistory::new(10);
+
+        history.push(stats(1, Some(3000)));
+        history.push(stats(4, None));
+        history.push(stats(7, Some(1000)));
+
+        let players = history.summarize(StatsField::Players, Duration::from_secs(60)).unwrap();
+
+        assert_eq!((players.min, players.avg, players.max), (1.0, 4.0, 7.0));
+        assert_eq!(players.samples, 3);
+
+        // samples without frame stats don't count towards frame fields
+        let sent = history.summarize(StatsField::FramesSent, Duration::from_secs(60)).unwrap();
+
+        assert_eq!((sent.min, sent.avg, sent.max), (1000.0, 2000.0, 3000.0));
+        assert_eq!(sent.samples, 2);
+    }
+
+    #[test]
+    fn summarizes_nothing_without_values() {
+        let mut history = StatsHistory::new(10);
+
+        history.push(stats(1, None));
+        history.push(stats(2, None));
+
+        assert_eq!(history.summarize(StatsField::FramesNulled, Duration::from_secs(60)), None);
+
+        history.clear();
+
+        assert_eq!(history.summarize(StatsField::Players, Duration::from_secs(60)), None);
+    }
+}
@@ -1,8 +1,33 @@
+mod config;
+mod connection;
+mod handle;
+mod history;
 mod node;
 mod node_manager;
+mod selector;
+mod v4;
 
+pub use self::config::{
+    NodeConfig,
+    NodeConfigBuilder,
+    Protocol,
+    ReconnectPolicy,
+    TlsConfig,
+    DEFAULT_HEARTBEAT_INTERVAL_SECS,
+    DEFAULT_RESUME_TIMEOUT_SECS,
+};
+pub use self::handle::{NodeHandle, OutgoingOp};
+pub use self::history::{
+    StatsField,
+    StatsHistory,
+    StatsSample,
+    StatsSummary,
+    DEFAULT_HISTORY_CAPACITY,
+};
 pub use self::node::Node;
-pub use self::node_manager::NodeManager;
+pub use self::node_manager::{NodeManager, WeakNodeManager};
+pub(crate) use self::node_manager::destroy_player;
+pub use self::selector::{LowestPenalty, NodeSelector, PreferRegion};
 
 use player::*;
 use lavalink::stats::*;
@@ -11,29 +36,89 @@ use parking_lot::{Mutex, RwLock};
 use serenity::client::bridge::gateway::ShardManager;
 use std::sync::Arc;
 use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 use websocket::OwnedMessage;
 
+/// The number of samples the smoothed latency averages over; each new sample
+/// contributes `1 / LATENCY_SMOOTHING` of the new value.
+const LATENCY_SMOOTHING: u32 = 5;
+
 pub type NodeAudioPlayerManager = Arc<RwLock<AudioPlayerManager>>;
 pub type NodeSender = Arc<Mutex<Sender<OwnedMessage>>>;
+pub(crate) type RestSender = Arc<Mutex<Sender<v4::RestRequest>>>;
 pub type NodeState = Arc<RwLock<State>>;
 pub type SerenityShardManager = Arc<Mutex<ShardManager>>;
 
-#[derive(Clone, Debug)]
-pub struct NodeConfig {
-    pub http_host: String,
-    pub websocket_host: String,
-    pub user_id: String,
-    pub password: String,
-    pub num_shards: u64,
-}
-
 #[derive(Clone, Debug, Default)]
 pub struct State {
     pub stats: Option<RemoteStats>,
+    pub history: StatsHistory,
+    pub status: NodeStatus,
+    /// The round trip time of the last answered ping.
+    pub latency: Option<Duration>,
+    /// An exponentially weighted moving average of ping round trip times.
+    pub smoothed_latency: Option<Duration>,
+    /// When the node last sent stats or answered a ping.
+    pub last_heartbeat: Option<Instant>,
+    /// When the last unanswered ping was sent.
+    pub last_ping: Option<Instant>,
+    /// The number of times the node has been reconnected to.
+    pub reconnects: u32,
+    /// The ID of the session the node opened on the current connection, for
+    /// nodes speaking the v4 protocol.
+    pub session_id: Option<String>,
 }
 
 impl State {
     fn new() -> Self {
         Self::default()
     }
+
+    /// Sets the latest stats, recording them in the history.
+    fn update_stats(&mut self, stats: RemoteStats) {
+        self.history.push(stats.clone());
+        self.stats = Some(stats);
+        self.last_heartbeat = Some(Instant::now());
+    }
+
+    fn record_pong(&mut self) {
+        if let Some(sent) = self.last_ping.take() {
+            let latency = sent.elapsed();
+
+            self.latency = Some(latency);
+            self.smoothed_latency = Some(match self.smoothed_latency {
+                Some(smoothed) => (smoothed * (LATENCY_SMOOTHING - 1) + latency) / LATENCY_SMOOTHING,
+                None => latency,
+            });
+        }
+
+        self.last_heartbeat = Some(Instant::now());
+    }
+
+    fn set_connected(&mut self) {
+        // a new connection opens a new session once the node is ready
+        self.session_id = None;
+        self.status = NodeStatus::Connected;
+        self.last_heartbeat = Some(Instant::now());
+        self.last_ping = None;
+    }
+}
+
+/// The connection status of a node.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum NodeStatus {
+    Connecting,
+    Connected,
+    /// The connection was lost and is being reestablished.
+    Reconnecting,
+    /// The connection was lost and the reconnect policy gave up.
+    Dead,
+    /// The node was closed by the user.
+    Closed,
+}
+
+impl Default for NodeStatus {
+    fn default() -> Self {
+        NodeStatus::Connecting
+    }
 }
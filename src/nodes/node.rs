@@ -1,77 +1,143 @@
 use parking_lot::{Mutex, RwLock};
+use decoder;
+use hyper::status::StatusCode;
+use rest::{LoadedTrack, LoadedTracks, RestClient, RoutePlannerStatus, TrackInfo};
 use serde_json;
-use std::net::TcpStream;
 use std::str::FromStr;
-use std::sync::mpsc::{self, Receiver as MpscReceiver, Sender as MpscSender};
+use std::sync::mpsc::{self, Receiver as MpscReceiver, RecvTimeoutError, Sender as MpscSender};
 use std::sync::Arc;
-use std::thread::{Builder as ThreadBuilder, JoinHandle};
+use std::thread::{self, Builder as ThreadBuilder, JoinHandle};
+use std::time::{Duration, Instant};
+use super::connection::{self, ConnectionWriter, NodeReader};
 use super::{
     NodeAudioPlayerManager,
     NodeConfig,
+    NodeHandle,
     NodeSender,
     NodeState,
+    NodeStatus,
+    Protocol,
+    RestSender,
     State,
+    StatsField,
+    StatsSummary,
 };
-use websocket::header::Headers;
+use super::v4::{self, RestRequest};
 use websocket::receiver::Reader as WebSocketReader;
-use websocket::sender::Writer as WebSocketWriter;
-use websocket::{ClientBuilder, Message, OwnedMessage};
+use websocket::OwnedMessage;
 use lavalink::opcodes::Opcode;
+use lavalink::stats::{Cpu, Memory, RemoteStats};
+use ::listener::{self, AudioPlayerListener};
+use ::player::TrackData;
 use ::prelude::*;
 
+/// The writing half of the current connection, shared between the loops so
+/// that it can be replaced on reconnect.
+type WriterSlot = Arc<Mutex<Option<ConnectionWriter>>>;
+
 #[derive(Debug)]
 pub struct Node {
+    pub name: String,
+    pub region: Option<String>,
     pub websocket_host: String,
+    pub rest: RestClient,
     pub sender: NodeSender,
     pub send_loop: JoinHandle<()>,
     pub recv_loop: JoinHandle<()>,
+    /// The thread making REST requests to v4 nodes.
+    pub rest_loop: Option<JoinHandle<()>>,
     pub state: NodeState,
+    rest_sender: Option<RestSender>,
 }
 
 impl Node {
+    /// Connects to a node, failing if the initial connection can't be made.
+    ///
+    /// Once connected, the node is pinged every heartbeat interval. If it stops
+    /// sending stats and answering pings for longer than
+    /// [`NodeConfig::dead_after`], or the connection is lost, the node is
+    /// reconnected to according to its [`ReconnectPolicy`].
+    ///
+    /// [`NodeConfig::dead_after`]: struct.NodeConfig.html#method.dead_after
+    /// [`ReconnectPolicy`]: struct.ReconnectPolicy.html
     pub fn connect(config: &NodeConfig, player_manager: NodeAudioPlayerManager) -> Result<Self> {
-        let mut headers = Headers::new();
-        headers.set_raw("Authorization", vec![config.password.clone().as_bytes().to_vec()]);
-        headers.set_raw("Num-Shards", vec![config.num_shards.to_string().as_bytes().to_vec()]);
-        headers.set_raw("User-Id", vec![config.user_id.clone().as_bytes().to_vec()]);
+        config.validate()?;
 
-        let client = ClientBuilder::new(config.websocket_host.clone().as_ref())?
-            .add_protocol("rust-websocket")
-            .custom_headers(&headers)
-            .connect_insecure()?;
+        let rest = RestClient::new(config)?;
+        let connection = connection::connect(config, None)?;
 
-        let (mut receiver, mut sender) = client.split()?;
+        #[cfg(feature = "metrics")]
+        ::metrics::record_connect(&config.websocket_host);
 
-        let (ws_tx, mut ws_rx) = mpsc::channel();
-        let ws_tx_1 = ws_tx.clone();
+        let (ws_tx, ws_rx) = mpsc::channel();
 
         let state = Arc::new(RwLock::new(State::new()));
+        state.write().set_connected();
+
+        let slot = Arc::new(Mutex::new(Some(connection.writer)));
 
+        let mut send_loop = SendLoop {
+            host: config.websocket_host.clone(),
+            heartbeat_interval: config.heartbeat_interval,
+            dead_after: config.dead_after(),
+            ws_rx,
+            slot: Arc::clone(&slot),
+            state: Arc::clone(&state),
+        };
         let builder = ThreadBuilder::new().name("send loop".into());
-        let send_loop = builder.spawn(move || {
-            send_loop(&mut ws_rx, &mut sender);
-        }).unwrap();
+        let send_loop = builder.spawn(move || send_loop.run())?;
+
+        if let Some(message) = configure_resuming(config) {
+            let _ = ws_tx.send(message);
+        }
+
+        let (rest_sender, rest_loop) = if config.protocol == Protocol::V4 {
+            let (rest_tx, rest_loop) = v4::spawn_worker(config.websocket_host.clone(), rest.clone())?;
 
-        let recv_state = Arc::clone(&state); // clone state for the recv loop otherwise ownership passed
+            (Some(Arc::new(Mutex::new(rest_tx))), Some(rest_loop))
+        } else {
+            (None, None)
+        };
+
+        let sender = Arc::new(Mutex::new(ws_tx.clone()));
+        let handle = NodeHandle::new(
+            config.display_name().to_owned(),
+            config.websocket_host.clone(),
+            Arc::clone(&sender),
+            rest_sender.clone(),
+            rest.clone(),
+            Arc::clone(&state),
+        );
+
+        let mut recv_loop = ReceiveLoop {
+            config: config.clone(),
+            rest_sender: rest_sender.clone(),
+            receiver: connection.reader,
+            ws_tx,
+            slot,
+            recv_state: Arc::clone(&state), // clone state for the recv loop otherwise ownership passed
+            handle: handle.clone(),
+            player_manager: Arc::clone(&player_manager),
+        };
 
-        //let player_manager_cloned = player_manager.clone(); // clone for move to recv loop
+        // before the receive loop starts, so it comes before any event
+        let listener = Arc::clone(&player_manager.read().listener);
+        listener::dispatch("node_connected", || listener.node_connected(&handle));
 
         let builder = ThreadBuilder::new().name("recv loop".into());
-        let recv_loop = builder.spawn(move || {
-            ReceiveLoop {
-                player_manager: &player_manager,
-                receiver: &mut receiver,
-                recv_state: &recv_state,
-                ws_tx_1: &ws_tx_1,
-            }.run();
-        }).unwrap();
+        let recv_loop = builder.spawn(move || recv_loop.run())?;
 
         Ok(Node {
+            name: config.display_name().to_owned(),
+            region: config.region.clone(),
             websocket_host: config.websocket_host.clone(),
-            sender: Arc::new(Mutex::new(ws_tx)),
+            rest,
+            sender,
             send_loop,
             recv_loop,
+            rest_loop,
             state,
+            rest_sender,
         })
     }
 
@@ -79,31 +145,224 @@ impl Node {
         self.sender.lock().send(message).map_err(From::from)
     }
 
+    /// A handle for sending typed ops to the node.
+    pub fn handle(&self) -> NodeHandle {
+        NodeHandle::new(
+            self.name.clone(),
+            self.websocket_host.clone(),
+            Arc::clone(&self.sender),
+            self.rest_sender.clone(),
+            self.rest.clone(),
+            Arc::clone(&self.state),
+        )
+    }
+
+    pub fn status(&self) -> NodeStatus {
+        self.state.read().status
+    }
+
+    /// Whether the node is connected and can be given new players.
+    pub fn is_available(&self) -> bool {
+        self.status() == NodeStatus::Connected
+    }
+
+    /// The round trip time of the last ping answered by the node.
+    pub fn last_ping_latency(&self) -> Option<Duration> {
+        self.state.read().latency
+    }
+
+    /// The smoothed websocket round trip time to the node.
+    ///
+    /// Returns `None` until the node has answered a ping.
+    pub fn latency(&self) -> Option<Duration> {
+        self.state.read().smoothed_latency
+    }
+
+    /// Resolves an identifier, such as a URL or a search like
+    /// `ytsearch:query`, into tracks using the node's REST API.
+    pub fn load_tracks(&self, identifier: &str) -> Result<LoadedTracks> {
+        self.rest.load_tracks(identifier)
+    }
+
+    /// Decodes the info of a base64-encoded track using the node's REST API.
+    ///
+    /// Falls back to decoding the track locally if the node's version has no
+    /// `/decodetrack` endpoint or the node fails to decode it.
+    pub fn decode_track(&self, track: &str) -> Result<TrackInfo> {
+        match self.rest.decode_track(track) {
+            Err(ref why) if should_decode_locally(why) => {
+                debug!("node {} can't decode tracks, decoding locally", self.name);
+
+                decoder::decode_track(track)
+            },
+            result => result,
+        }
+    }
+
+    /// Decodes the info of several base64-encoded tracks in one request using
+    /// the node's REST API.
+    ///
+    /// Falls back to decoding the tracks locally if the node's version has no
+    /// `/decodetracks` endpoint or the node fails to decode them.
+    pub fn decode_tracks<T: AsRef<str>>(&self, tracks: &[T]) -> Result<Vec<LoadedTrack>> {
+        match self.rest.decode_tracks(tracks) {
+            Err(ref why) if should_decode_locally(why) => {
+                debug!("node {} can't decode tracks, decoding locally", self.name);
+
+                tracks
+                    .iter()
+                    .map(|track| {
+                        Ok(LoadedTrack {
+                            track: track.as_ref().to_owned(),
+                            info: decoder::decode_track(track.as_ref())?,
+                        })
+                    })
+                    .collect()
+            },
+            result => result,
+        }
+    }
+
+    /// The status of the node's route planner, or `None` if it has none.
+    pub fn route_planner_status(&self) -> Result<Option<RoutePlannerStatus>> {
+        self.rest.route_planner_status()
+    }
+
+    /// Makes the node's route planner use a failing address again, such as
+    /// once it is no longer rate limited.
+    pub fn free_address(&self, address: &str) -> Result<()> {
+        self.rest.free_address(address)
+    }
+
+    /// Makes the node's route planner use every failing address again.
+    pub fn free_all_addresses(&self) -> Result<()> {
+        self.rest.free_all_addresses()
+    }
+
+    /// A copy of the most recently received stats, if any.
+    pub fn stats(&self) -> Option<RemoteStats> {
+        self.state.read().stats.clone()
+    }
+
+    /// The total number of players on the node.
+    pub fn players(&self) -> Option<i32> {
+        self.state.read().stats.as_ref().map(|stats| stats.players)
+    }
+
+    /// The number of players on the node that are currently playing a track.
+    pub fn playing_players(&self) -> Option<i32> {
+        self.state.read().stats.as_ref().map(|stats| stats.playing_players)
+    }
+
+    /// How long the node has been running, as reported by the node.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.state.read().stats.as_ref().map(|stats| {
+            let uptime = stats.uptime.max(0) as u64;
+
+            Duration::from_millis(uptime)
+        })
+    }
+
+    pub fn memory(&self) -> Option<Memory> {
+        self.state.read().stats.as_ref().map(|stats| stats.memory.clone())
+    }
+
+    pub fn cpu(&self) -> Option<Cpu> {
+        self.state.read().stats.as_ref().map(|stats| stats.cpu.clone())
+    }
+
+    /// The average number of frames per minute that the node could not
+    /// provide in time.
+    ///
+    /// Returns `None` if no stats were received or the node did not report
+    /// frame stats.
+    pub fn frame_deficit(&self) -> Option<i32> {
+        let state = self.state.read();
+
+        state.stats.as_ref()?.frame_stats.as_ref().map(|frames| frames.deficit)
+    }
+
+    /// The average number of frames per minute that the node sent as null.
+    ///
+    /// Returns `None` if no stats were received or the node did not report
+    /// frame stats.
+    pub fn frame_nulled(&self) -> Option<i32> {
+        let state = self.state.read();
+
+        state.stats.as_ref()?.frame_stats.as_ref().map(|frames| frames.nulled)
+    }
+
+    /// Summarizes a stats field over the samples received within the given
+    /// window.
+    pub fn stats_summary(&self, field: StatsField, window: Duration) -> Option<StatsSummary> {
+        self.state.read().history.summarize(field, window)
+    }
+
+    /// Closes the connection, waiting for the node's threads to stop.
+    ///
+    /// The REST worker of v4 nodes makes the requests queued before it
+    /// stops.
     pub fn close(self) {
         info!("closing lavalink socket!");
 
+        self.state.write().status = NodeStatus::Closed;
+
         let _ = self.send(OwnedMessage::Close(None));
         let _ = self.send_loop.join();
         let _ = self.recv_loop.join();
+
+        if let Some(ref rest_sender) = self.rest_sender {
+            let _ = rest_sender.lock().send(RestRequest::Shutdown);
+        }
+
+        if let Some(rest_loop) = self.rest_loop {
+            let _ = rest_loop.join();
+        }
     }
 }
 
-struct ReceiveLoop<'a> {
-    receiver: &'a mut WebSocketReader<TcpStream>,
-    ws_tx_1: &'a MpscSender<OwnedMessage>,
-    recv_state: &'a NodeState,
-    player_manager: &'a NodeAudioPlayerManager,
+struct ReceiveLoop {
+    config: NodeConfig,
+    rest_sender: Option<RestSender>,
+    receiver: WebSocketReader<NodeReader>,
+    ws_tx: MpscSender<OwnedMessage>,
+    slot: WriterSlot,
+    recv_state: NodeState,
+    handle: NodeHandle,
+    player_manager: NodeAudioPlayerManager,
 }
 
-impl<'a> ReceiveLoop<'a> {
+impl ReceiveLoop {
     fn run(&mut self) {
+        loop {
+            self.receive();
+
+            let listener = self.listener();
+            listener::dispatch("node_disconnected", || listener.node_disconnected(&self.handle));
+
+            if self.is_closed() {
+                info!("Shutting down receive loop for {}", self.config.websocket_host);
+
+                return;
+            }
+
+            if !self.reconnect() {
+                return;
+            }
+        }
+    }
+
+    /// Receives messages until the connection is lost.
+    fn receive(&mut self) {
         loop {
             let msg: OwnedMessage = match self.recv() {
                 Ok(msg) => msg,
                 Err(why) => {
-                    error!("Error receiving msg: {:?}", why);
-                    info!("Shutting down receive loop");
-                    let _ = self.ws_tx_1.send(OwnedMessage::Close(None));
+                    if !self.is_closed() {
+                        error!("Error receiving msg from {}: {:?}", self.config.websocket_host, why);
+
+                        self.report_error(&why);
+                    }
 
                     return;
                 },
@@ -119,34 +378,147 @@ impl<'a> ReceiveLoop<'a> {
         self.receiver.recv_message().map_err(From::from)
     }
 
+    fn is_closed(&self) -> bool {
+        self.recv_state.read().status == NodeStatus::Closed
+    }
+
+    /// The global listener, which receives node-level events.
+    fn listener(&self) -> Arc<AudioPlayerListener> {
+        Arc::clone(&self.player_manager.read().listener)
+    }
+
+    fn report_error(&self, error: &Error) {
+        let listener = self.listener();
+
+        listener::dispatch("error", || listener.error(error));
+    }
+
+    /// Reconnects according to the node's reconnect policy.
+    ///
+    /// Returns whether a new connection was made.
+    fn reconnect(&mut self) -> bool {
+        let host = self.config.websocket_host.clone();
+
+        if let Some(writer) = self.slot.lock().take() {
+            writer.shutdown();
+        }
+
+        self.recv_state.write().status = NodeStatus::Reconnecting;
+
+        let mut attempt = 0;
+
+        loop {
+            let delay = match self.config.reconnect.delay_for(attempt) {
+                Some(delay) => delay,
+                None => {
+                    error!("Giving up reconnecting to {} after {} attempts", host, attempt);
+
+                    let mut state = self.recv_state.write();
+
+                    if state.status != NodeStatus::Closed {
+                        state.status = NodeStatus::Dead;
+                    }
+
+                    return false;
+                },
+            };
+
+            info!("Reconnecting to {} in {:?}", host, delay);
+            thread::sleep(delay);
+
+            if self.is_closed() {
+                return false;
+            }
+
+            // v4 sessions are resumed by the ID of the lost session
+            let session_id = self.recv_state.read().session_id.clone();
+            let result = connection::connect(&self.config, session_id.as_ref().map(|id| &id[..]));
+
+            let connection = match result {
+                Ok(connection) => connection,
+                Err(why) => {
+                    warn!("Err reconnecting to {}: {:?}", host, why);
+                    self.report_error(&why);
+                    attempt += 1;
+
+                    continue;
+                },
+            };
+
+            {
+                let mut state = self.recv_state.write();
+
+                if state.status == NodeStatus::Closed {
+                    connection.writer.shutdown();
+
+                    return false;
+                }
+
+                state.set_connected();
+                state.reconnects += 1;
+                *self.slot.lock() = Some(connection.writer);
+                self.receiver = connection.reader;
+            }
+
+            if let Some(message) = configure_resuming(&self.config) {
+                let _ = self.ws_tx.send(message);
+            }
+
+            #[cfg(feature = "metrics")]
+            ::metrics::record_connect(&host);
+
+            info!("Reconnected to {}", host);
+
+            let listener = self.listener();
+            listener::dispatch("node_connected", || listener.node_connected(&self.handle));
+
+            return true;
+        }
+    }
+
     /// Handles the received message.
     ///
     /// Returns whether to continue the loop.
     fn handle_message(&self, msg: OwnedMessage) -> bool {
         match msg {
             OwnedMessage::Close(_) => {
-                // sever sent close msg, pass to send loop & break from loop
-                let _ = self.ws_tx_1.send(OwnedMessage::Close(None));
+                // server sent close msg, break from loop to reconnect
+                info!("Node {} closed the connection", self.config.websocket_host);
 
                 return false;
             },
             OwnedMessage::Ping(data) => {
-                if let Err(why) = self.ws_tx_1.send(OwnedMessage::Pong(data)) {
+                if let Err(why) = self.ws_tx.send(OwnedMessage::Pong(data)) {
                     error!("Error ponging in receive loop: {:?}", why);
 
                     return false;
                 }
             },
+            OwnedMessage::Pong(_) => {
+                self.recv_state.write().record_pong();
+            },
             OwnedMessage::Text(data) => {
                 let json = match serde_json::from_str::<Value>(data.as_ref()) {
                     Ok(json) => json,
                     Err(why) => {
                         error!("Err parsing JSON in receive loop: {:?}", why);
 
+                        self.report_error(&Error::Json(why));
+
                         return true;
                     },
                 };
 
+                #[cfg(feature = "metrics")]
+                ::metrics::record_frame_received(&self.config.websocket_host, json["op"].as_str().unwrap_or("unknown"));
+
+                // the v4 op opening a session isn't a known opcode
+                if json["op"] == "ready" {
+                    self.handle_ready(&json);
+
+                    return true;
+                }
+
                 let opcode = match json["op"].as_str() {
                     Some(opcode) => match Opcode::from_str(opcode) {
                         Ok(opcode) => opcode,
@@ -176,17 +548,59 @@ impl<'a> ReceiveLoop<'a> {
         true
     }
 
-    fn handle_opcode(&self, json: Value, opcode: &Opcode) {
+    fn handle_opcode(&self, mut json: Value, opcode: &Opcode) {
         use self::Opcode::*;
 
         match *opcode {
             PlayerUpdate => self.handle_player_update(&json),
             Stats => self.handle_state(json),
-            Event => self.handle_event(&json),
+            Event => {
+                if self.config.protocol == Protocol::V4 {
+                    v4::normalize_event(&mut json);
+                }
+
+                self.handle_event(&json)
+            },
             _ => return,
         }
     }
 
+    /// Records the session a v4 node opened, which players are controlled
+    /// through.
+    fn handle_ready(&self, json: &Value) {
+        let session_id = match json["sessionId"].as_str() {
+            Some(session_id) => session_id.to_owned(),
+            None => {
+                error!("Ready op from {} had no session ID", self.config.websocket_host);
+
+                return;
+            },
+        };
+
+        info!(
+            "Node {} opened session {} (resumed: {})",
+            self.config.websocket_host,
+            session_id,
+            json["resumed"].as_bool().unwrap_or(false),
+        );
+
+        if let (&Some(_), &Some(ref rest_sender)) = (&self.config.resume_key, &self.rest_sender) {
+            let request = RestRequest::UpdateSession {
+                session_id: session_id.clone(),
+                update: json!({
+                    "resuming": true,
+                    "timeout": self.config.resume_timeout.as_secs(),
+                }),
+            };
+
+            if rest_sender.lock().send(request).is_err() {
+                warn!("Err configuring resuming for {}: worker stopped", self.config.websocket_host);
+            }
+        }
+
+        self.recv_state.write().session_id = Some(session_id);
+    }
+
     fn handle_event(&self, json: &Value) {
 
         let guild_id_str = json["guildId"]
@@ -195,9 +609,6 @@ impl<'a> ReceiveLoop<'a> {
         let guild_id = guild_id_str
             .parse::<u64>()
             .expect("could not parse json guild_id into u64");
-        let track = json["track"]
-            .as_str()
-            .expect("invalid json track - should be str");
 
         let player_manager = self.player_manager.read();
 
@@ -215,35 +626,118 @@ impl<'a> ReceiveLoop<'a> {
 
         let mut player = player.lock();
 
-        match json["type"].as_str().expect("Err parsing type to str") {
+        let kind = json["type"].as_str().expect("Err parsing type to str");
+
+        // the voice websocket event is the only one not about a track
+        if kind == "WebSocketClosedEvent" {
+            let code = json["code"]
+                .as_i64()
+                .expect("invalid json code - should be i64");
+            let reason = json["reason"].as_str().unwrap_or_default();
+            let by_remote = json["byRemote"].as_bool().unwrap_or(false);
+
+            let listener = player.listener();
+
+            listener::dispatch("voice_websocket_closed", || {
+                listener.voice_websocket_closed(&mut player, code, reason, by_remote)
+            });
+
+            return;
+        }
+
+        let track = json["track"]
+            .as_str()
+            .expect("invalid json track - should be str");
+
+        match kind {
             "TrackEndEvent" => {
                 let reason = json["reason"]
                     .as_str()
                     .expect("invalid json reason - should be str");
 
-                // Set the player's track so nothing is playing, reset
-                // the time, and reset the position
-                player.track = None;
-                player.time = 0;
-                player.position = 0;
+                // a replaced track's end arrives after the new one started
+                let is_current = reason != "REPLACED"
+                    && player.track.as_ref().map_or(false, |current| current == track);
+                let position = player.position;
+
+                // the end of a track stopped by the player was reported then
+                if reason == "STOPPED" && !is_current {
+                    return;
+                }
+
+                let data = if is_current {
+                    player.finish_track(reason)
+                } else {
+                    TrackData::default()
+                };
+
+                let listener = player.listener();
+
+                listener::dispatch("track_end", || {
+                    listener.track_end_with_data(&mut player, track, reason, &data)
+                });
+
+                let pending = player.take_pending_recovery();
+
+                if !is_current || !player_manager.run_track_end_tasks(&mut player) {
+                    return;
+                }
+
+                let result = match pending {
+                    Some(action) => player.recover(action, track, position, true),
+                    // only these reasons allow starting the next track
+                    None if player.auto_advance && (reason == "FINISHED" || reason == "LOAD_FAILED") => {
+                        player.play_next().map(|_| ())
+                    },
+                    None => Ok(()),
+                };
 
-                self.player_manager.read().listener.track_end(&mut player, track, reason);
+                if let Err(why) = result {
+                    warn!("Err continuing playback for guild {}: {:?}", guild_id, why);
+                }
             },
             "TrackExceptionEvent" => {
+                // Lavalink v3 nests the error in an exception object, while
+                // older versions send it as a string. Exceptions without a
+                // message have a null one.
                 let error = json["error"]
                     .as_str()
-                    .expect("invalid json error - should be str");
+                    .or_else(|| json["exception"]["message"].as_str())
+                    .unwrap_or("unknown error");
+
+                let severity = json["exception"]["severity"].as_str();
+
+                #[cfg(feature = "metrics")]
+                ::metrics::record_track_exception(severity.unwrap_or("UNKNOWN"));
 
-                // TODO: determine if should keep playing
+                let listener = player.listener();
 
-                self.player_manager.read().listener.track_exception(&mut player, track, error);
+                listener::dispatch("track_exception", || listener.track_exception(&mut player, track, error));
+
+                // the node ends the track next, so recover once it does
+                let severity = severity.and_then(|severity| severity.parse().ok());
+                let action = player.recovery.for_exception(severity);
+                player.set_pending_recovery(action);
             },
             "TrackStuckEvent" => {
                 let threshold_ms = json["thresholdMs"]
                     .as_i64()
                     .expect("invalid json thresholdMs - should be i64");
 
-                self.player_manager.read().listener.track_stuck(&mut player, track, threshold_ms);
+                let listener = player.listener();
+
+                listener::dispatch("track_stuck", || listener.track_stuck(&mut player, track, threshold_ms));
+
+                if player.track.as_ref().map_or(true, |current| current != track) {
+                    return;
+                }
+
+                let action = player.recovery.for_stuck(threshold_ms);
+                let position = player.position;
+
+                if let Err(why) = player.recover(action, track, position, false) {
+                    warn!("Err recovering stuck track for guild {}: {:?}", guild_id, why);
+                }
             },
             other => {
                 warn!("Unexpected event type: {}", other);
@@ -282,45 +776,189 @@ impl<'a> ReceiveLoop<'a> {
             },
         };
 
-        let mut player = player.lock();
-        player.time = time;
-        player.position = position;
+        player.lock().update_position(time, position);
     }
 
     fn handle_state(&self, json: Value) {
-        match serde_json::from_value(json) {
+        match serde_json::from_value::<RemoteStats>(json) {
             Ok(stats) => {
-                let mut state = self.recv_state.write();
-                state.stats = Some(stats);
+                self.recv_state.write().update_stats(stats.clone());
+
+                let listener = self.listener();
+
+                listener::dispatch("stats_received", || listener.stats_received(&self.handle, &stats));
+            },
+            Err(why) => {
+                error!("Err parsing stats: {:?}", why);
+
+                self.report_error(&Error::Json(why));
             },
-            Err(e) => println!("Error parsing stats! {:?}", e),
         }
     }
 }
 
-fn send_loop(
-    ws_rx: &mut MpscReceiver<OwnedMessage>,
-    sender: &mut WebSocketWriter<TcpStream>,
-) {
-    loop {
-        let message = match ws_rx.recv() {
-            Ok(m) => m,
-            Err(e) => {
-                error!("Send loop: {:?}", e);
-                return;
+struct SendLoop {
+    host: String,
+    heartbeat_interval: Duration,
+    dead_after: Duration,
+    ws_rx: MpscReceiver<OwnedMessage>,
+    slot: WriterSlot,
+    state: NodeState,
+}
+
+impl SendLoop {
+    fn run(&mut self) {
+        let mut next_ping = Instant::now() + self.heartbeat_interval;
+
+        loop {
+            let now = Instant::now();
+            let timeout = if next_ping > now {
+                next_ping - now
+            } else {
+                Duration::from_secs(0)
+            };
+
+            match self.ws_rx.recv_timeout(timeout) {
+                // handle close message, exit loop
+                Ok(message @ OwnedMessage::Close(_)) => {
+                    if let Some(mut writer) = self.slot.lock().take() {
+                        let _ = writer.send(&message);
+                        writer.shutdown();
+                    }
+
+                    return;
+                },
+                Ok(message) => self.send(&message),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => {
+                    error!("Send loop for {}: channel disconnected", self.host);
+
+                    return;
+                },
+            }
+
+            if Instant::now() >= next_ping {
+                self.check_heartbeat();
+                self.ping();
+
+                next_ping = Instant::now() + self.heartbeat_interval;
+            }
+        }
+    }
+
+    fn send(&self, message: &OwnedMessage) {
+        let mut slot = self.slot.lock();
+
+        let failed = match *slot {
+            Some(ref mut writer) => match writer.send(message) {
+                Ok(()) => false,
+                Err(why) => {
+                    error!("Send loop for {}: {:?}", self.host, why);
+
+                    // unblock the receive loop so that it reconnects
+                    writer.shutdown();
+
+                    true
+                },
+            },
+            None => {
+                warn!("Dropping message to {}; not connected", self.host);
+
+                false
             },
         };
 
-        // handle close message, exit loop
-        if let OwnedMessage::Close(_) = message {
-            let _ = sender.send_message(&message);
+        if failed {
+            *slot = None;
+            self.set_reconnecting();
+        }
+    }
+
+    /// Marks a lost connection as reconnecting right away, so that ops fail
+    /// instead of being dropped until the receive loop notices.
+    fn set_reconnecting(&self) {
+        let mut state = self.state.write();
+
+        if state.status == NodeStatus::Connected {
+            state.status = NodeStatus::Reconnecting;
+        }
+    }
+
+    fn ping(&self) {
+        if self.state.read().status != NodeStatus::Connected {
             return;
         }
 
-        if let Err(e) = sender.send_message(&message) {
-            error!("Send loop: {:?}", e);
-            let _ = sender.send_message(&Message::close());
+        self.state.write().last_ping = Some(Instant::now());
+        self.send(&OwnedMessage::Ping(Vec::new()));
+    }
+
+    /// Tears down the connection if the node hasn't sent stats or answered a
+    /// ping within the dead node window.
+    fn check_heartbeat(&self) {
+        let silent_for = {
+            let state = self.state.read();
+
+            if state.status != NodeStatus::Connected {
+                return;
+            }
+
+            match state.last_heartbeat {
+                Some(last_heartbeat) => last_heartbeat.elapsed(),
+                None => return,
+            }
+        };
+
+        if silent_for <= self.dead_after {
             return;
         }
+
+        warn!(
+            "Node {} has not sent stats or pongs for {:?}; considering it dead",
+            self.host,
+            silent_for,
+        );
+
+        if let Some(writer) = self.slot.lock().take() {
+            writer.shutdown();
+        }
+
+        self.set_reconnecting();
+    }
+}
+
+/// The op asking a v3 node to keep the session for resuming with the config's
+/// resume key, if it has one.
+fn configure_resuming(config: &NodeConfig) -> Option<OwnedMessage> {
+    if config.protocol != Protocol::V3 {
+        return None;
+    }
+
+    let key = config.resume_key.as_ref()?;
+
+    Some(OwnedMessage::Text(json!({
+        "op": "configureResuming",
+        "key": key,
+        "timeout": config.resume_timeout.as_secs(),
+    }).to_string()))
+}
+
+/// Whether a decoding request failed in a way decoding locally may not.
+///
+/// Versions without the endpoint answer `404 Not Found` or `405 Method Not
+/// Allowed`, while versions with it answer `400 Bad Request` or `500 Internal
+/// Server Error` for tracks they can't decode, such as ones from sources they
+/// don't have enabled. Other errors, such as a wrong password or an
+/// unreachable node, are returned as is.
+fn should_decode_locally(error: &Error) -> bool {
+    match *error {
+        Error::UnsuccessfulRequest(status) => match status {
+            StatusCode::NotFound
+            | StatusCode::MethodNotAllowed
+            | StatusCode::BadRequest
+            | StatusCode::InternalServerError => true,
+            _ => false,
+        },
+        _ => false,
     }
 }
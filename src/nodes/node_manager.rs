@@ -1,56 +1,345 @@
-use parking_lot::RwLock;
-use std::sync::Arc;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::{Arc, Weak};
+use cache::TrackCache;
 use listener::AudioPlayerListener;
 use player::*;
-use super::{Node, NodeAudioPlayerManager, NodeConfig};
+use rest::{LoadedTracks, RoutePlannerStatus};
+use search::{self, SearchSource, DEFAULT_SEARCH_LIMIT};
+use super::{LowestPenalty, Node, NodeAudioPlayerManager, NodeConfig, NodeSelector};
 use ::prelude::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct NodeManager {
     pub nodes: Arc<RwLock<Vec<Arc<Node>>>>,
     pub player_manager: NodeAudioPlayerManager,
+    /// The penalty added per millisecond of smoothed node latency when
+    /// selecting a node, if any.
+    pub latency_weight: Option<f64>,
+    /// Chooses the node new players are created on.
+    pub selector: Arc<NodeSelector>,
+    /// The cache shared by the REST clients of every node.
+    pub track_cache: Option<TrackCache>,
+    /// The websocket host of the node each guild's player was created on.
+    assignments: Arc<RwLock<HashMap<u64, String>>>,
 }
 
 impl NodeManager {
     pub fn new(listener: Arc<AudioPlayerListener>) -> Self {
+        let player_manager = Arc::new(RwLock::new(AudioPlayerManager::new(listener)));
+        let assignments = Arc::new(RwLock::new(HashMap::new()));
+
+        {
+            // scheduled tasks reach the players and their nodes through these
+            let mut manager = player_manager.write();
+            manager.this = Arc::downgrade(&player_manager);
+            manager.assignments = Arc::downgrade(&assignments);
+        }
+
         Self {
             nodes: Arc::new(RwLock::new(Vec::default())),
-            player_manager: Arc::new(RwLock::new(AudioPlayerManager::new(listener))),
+            player_manager,
+            latency_weight: None,
+            selector: Arc::new(LowestPenalty),
+            track_cache: None,
+            assignments,
+        }
+    }
+
+    /// A handle to the manager that doesn't keep its nodes and players alive.
+    ///
+    /// Listeners and threads owned by the manager, such as those registered
+    /// in its listener chain, should hold this rather than a clone, so that
+    /// the manager can still be closed.
+    pub fn downgrade(&self) -> WeakNodeManager {
+        WeakNodeManager {
+            nodes: Arc::downgrade(&self.nodes),
+            player_manager: Arc::downgrade(&self.player_manager),
+            latency_weight: self.latency_weight,
+            selector: Arc::clone(&self.selector),
+            track_cache: self.track_cache.clone(),
+            assignments: Arc::downgrade(&self.assignments),
+        }
+    }
+
+    /// Sets the selector used to choose the node new players are created on.
+    pub fn set_selector<S: NodeSelector + 'static>(&mut self, selector: S) {
+        self.selector = Arc::new(selector);
+    }
+
+    /// Sets the penalty added per millisecond of smoothed node latency when
+    /// selecting a node.
+    ///
+    /// Pass `None` to ignore latency, which is the default.
+    pub fn set_latency_weight(&mut self, weight: Option<f64>) {
+        self.latency_weight = weight;
+    }
+
+    /// Sets the cache of `/loadtracks` results shared by the REST clients of
+    /// every node, including those already added.
+    pub fn set_track_cache(&mut self, cache: Option<TrackCache>) {
+        for node in self.nodes.read().iter() {
+            node.rest.set_cache(cache.clone());
         }
+
+        self.track_cache = cache;
     }
 
     pub fn add_node(&mut self, config: &NodeConfig)
         -> Result<()> {
-        let node = Node::connect(config, Arc::clone(&self.player_manager));
+        let node = Node::connect(config, Arc::clone(&self.player_manager))?;
+        node.rest.set_cache(self.track_cache.clone());
 
         let mut nodes = self.nodes.write();
-        nodes.push(Arc::new(node?));
+        nodes.push(Arc::new(node));
 
         Ok(())
     }
 
+    /// Determines the available node with the lowest penalty.
+    ///
+    /// Nodes that are reconnecting, dead, or closed are never chosen, and
+    /// nodes that have not sent their stats yet are only chosen if no other
+    /// node is available.
     pub fn determine_best_node(&self) -> Option<Arc<Node>> {
+        self.determine_best_node_where(|_| true)
+    }
+
+    /// Determines the available node with the lowest penalty out of those
+    /// matching the filter.
+    pub fn determine_best_node_where<F>(&self, filter: F) -> Option<Arc<Node>>
+        where F: Fn(&Node) -> bool {
         let nodes = self.nodes.read();
 
-        let mut record = i32::max_value();
+        let mut record = None;
         let mut best = None;
 
         for node in nodes.iter() {
-            let total = Self::get_penalty(node).unwrap_or(0);
+            if !node.is_available() || !filter(node) {
+                continue;
+            }
 
-            if total < record {
+            // nodes without stats rank after every node with them
+            let total = match self.get_penalty(node) {
+                Ok(penalty) => (false, penalty),
+                Err(_) => (true, 0),
+            };
+
+            let better = match record {
+                Some(record) => total < record,
+                None => true,
+            };
+
+            if better {
                 best = Some(Arc::clone(node));
-                record = total;
+                record = Some(total);
             }
         }
 
         best
     }
 
-    pub fn get_penalty(node: &Arc<Node>) -> Result<i32> {
-        let state = node.state.read();
+    /// Creates a player for the guild on the node chosen by the selector,
+    /// returning it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::PlayerAlreadyExists`] if the guild already has a
+    /// player, or [`Error::NoNodesAvailable`] if the selector found no node.
+    ///
+    /// [`Error::NoNodesAvailable`]: ../enum.Error.html#variant.NoNodesAvailable
+    /// [`Error::PlayerAlreadyExists`]: ../enum.Error.html#variant.PlayerAlreadyExists
+    pub fn create_player(&self, guild_id: u64) -> Result<Arc<Mutex<AudioPlayer>>> {
+        if self.player_manager.read().has_player(&guild_id) {
+            return Err(Error::PlayerAlreadyExists);
+        }
+
+        // select before locking, so selectors can look at the players
+        let node = self.selector
+            .select(self, guild_id)
+            .ok_or(Error::NoNodesAvailable)?;
+
+        let player = {
+            let mut player_manager = self.player_manager.write();
+
+            // another thread may have created the player while selecting
+            if player_manager.has_player(&guild_id) {
+                return Err(Error::PlayerAlreadyExists);
+            }
+
+            let player = player_manager.create_player(node.handle(), guild_id)?;
+
+            self.assignments.write().insert(guild_id, node.websocket_host.clone());
+
+            player
+        };
+
+        debug!("created player for guild {} on node {}", guild_id, node.name);
+
+        dispatch_created(&player);
+
+        Ok(player)
+    }
+
+    /// Destroys the guild's player on its node and stops tracking it,
+    /// returning the removed player if there was one.
+    pub fn destroy_player(&self, guild_id: u64) -> Option<Arc<Mutex<AudioPlayer>>> {
+        destroy_player(&self.player_manager, &self.assignments, guild_id)
+    }
+
+    /// Searches for tracks on the least loaded node, keeping the
+    /// [`DEFAULT_SEARCH_LIMIT`] best matching search results.
+    ///
+    /// URLs are loaded as is, whatever the source.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoNodesAvailable`] if no node is available, or the
+    /// error of the node's REST request.
+    ///
+    /// [`DEFAULT_SEARCH_LIMIT`]: ../search/constant.DEFAULT_SEARCH_LIMIT.html
+    /// [`Error::NoNodesAvailable`]: ../enum.Error.html#variant.NoNodesAvailable
+    pub fn search(&self, query: &str, source: SearchSource) -> Result<LoadedTracks> {
+        self.search_with_limit(query, source, DEFAULT_SEARCH_LIMIT)
+    }
+
+    /// Searches for tracks on the least loaded node, keeping the `limit` best
+    /// matching search results.
+    ///
+    /// Loaded tracks and playlists are returned whole.
+    pub fn search_with_limit(
+        &self,
+        query: &str,
+        source: SearchSource,
+        limit: usize,
+    ) -> Result<LoadedTracks> {
+        let node = self.determine_best_node().ok_or(Error::NoNodesAvailable)?;
+
+        let mut loaded = node.load_tracks(&source.identifier(query))?;
+        search::rank(query, &mut loaded, limit);
+
+        Ok(loaded)
+    }
+
+    /// The status of the route planner of every node, or `None` for nodes
+    /// without one.
+    pub fn route_planner_statuses(&self) -> Vec<(Arc<Node>, Result<Option<RoutePlannerStatus>>)> {
+        self.nodes.read()
+            .iter()
+            .map(|node| (Arc::clone(node), node.route_planner_status()))
+            .collect()
+    }
+
+    /// Frees an address on every node whose route planner lists it as
+    /// failing, returning how many nodes it was freed on.
+    ///
+    /// Nodes whose route planner status can't be fetched are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of a node that failed to free the address,
+    /// after trying every node.
+    pub fn free_address(&self, address: &str) -> Result<usize> {
+        let mut freed = 0;
+        let mut error = None;
+
+        for (node, status) in self.route_planner_statuses() {
+            match status {
+                Ok(Some(ref status)) if status.is_failing(address) => {},
+                Ok(_) => continue,
+                Err(why) => {
+                    warn!("Err getting route planner status of node {}: {:?}", node.name, why);
 
-        let stats = match state.stats.clone() {
+                    continue;
+                },
+            }
+
+            match node.free_address(address) {
+                Ok(()) => freed += 1,
+                Err(why) => {
+                    warn!("Err freeing address {} on node {}: {:?}", address, node.name, why);
+
+                    error = error.or(Some(why));
+                },
+            }
+        }
+
+        match error {
+            Some(why) => Err(why),
+            None => Ok(freed),
+        }
+    }
+
+    /// Frees every failing address on every node with a route planner,
+    /// returning how many nodes they were freed on.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of a node that failed to free its addresses,
+    /// after trying every node.
+    pub fn free_all_addresses(&self) -> Result<usize> {
+        let mut freed = 0;
+        let mut error = None;
+
+        for (node, status) in self.route_planner_statuses() {
+            match status {
+                Ok(Some(_)) => {},
+                Ok(None) => continue,
+                Err(why) => {
+                    warn!("Err getting route planner status of node {}: {:?}", node.name, why);
+
+                    continue;
+                },
+            }
+
+            match node.free_all_addresses() {
+                Ok(()) => freed += 1,
+                Err(why) => {
+                    warn!("Err freeing addresses on node {}: {:?}", node.name, why);
+
+                    error = error.or(Some(why));
+                },
+            }
+        }
+
+        match error {
+            Some(why) => Err(why),
+            None => Ok(freed),
+        }
+    }
+
+    /// The node the guild's player was created on, if it is still managed.
+    pub fn node_for_guild(&self, guild_id: u64) -> Option<Arc<Node>> {
+        let host = self.assignments.read().get(&guild_id).cloned()?;
+
+        self.nodes.read()
+            .iter()
+            .find(|node| node.websocket_host == host)
+            .cloned()
+    }
+
+    /// The IDs of the guilds whose players were created on the node with the
+    /// given websocket host.
+    pub fn guilds_on_node(&self, websocket_host: &str) -> Vec<u64> {
+        self.assignments.read()
+            .iter()
+            .filter(|&(_, host)| host == websocket_host)
+            .map(|(guild_id, _)| *guild_id)
+            .collect()
+    }
+
+    /// Calculates the penalty of a node from its stats, including the
+    /// weighted latency term if a latency weight is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StatsNotPresent`] if the node has not sent its stats
+    /// yet.
+    ///
+    /// [`Error::StatsNotPresent`]: ../enum.Error.html#variant.StatsNotPresent
+    pub fn get_penalty(&self, node: &Arc<Node>) -> Result<i32> {
+        let stats = match node.state.read().stats.clone() {
             Some(stats) => stats,
             None => return Err(Error::StatsNotPresent),
         };
@@ -67,7 +356,17 @@ impl NodeManager {
             None => (0f64, 0f64),
         };
 
-        Ok(stats.playing_players + cpu as i32 + deficit_frame as i32 + null_frame as i32)
+        let latency = match (self.latency_weight, node.latency()) {
+            (Some(weight), Some(latency)) => {
+                let millis = latency.as_secs() as f64 * 1000f64
+                    + f64::from(latency.subsec_nanos()) / 1_000_000f64;
+
+                (millis * weight) as i32
+            },
+            _ => 0,
+        };
+
+        Ok(stats.playing_players + cpu as i32 + deficit_frame as i32 + null_frame as i32 + latency)
     }
 
     pub fn close(self) -> bool {
@@ -95,3 +394,76 @@ impl NodeManager {
         true
     }
 }
+
+/// A handle to a [`NodeManager`] that doesn't keep it alive, made with
+/// [`NodeManager::downgrade`].
+///
+/// [`NodeManager`]: struct.NodeManager.html
+/// [`NodeManager::downgrade`]: struct.NodeManager.html#method.downgrade
+#[derive(Clone)]
+pub struct WeakNodeManager {
+    nodes: Weak<RwLock<Vec<Arc<Node>>>>,
+    player_manager: Weak<RwLock<AudioPlayerManager>>,
+    latency_weight: Option<f64>,
+    selector: Arc<NodeSelector>,
+    track_cache: Option<TrackCache>,
+    assignments: Weak<RwLock<HashMap<u64, String>>>,
+}
+
+impl WeakNodeManager {
+    /// The manager, unless it was closed or dropped.
+    ///
+    /// The manager can't be closed while the returned clone is alive, so it
+    /// should only be kept for as long as it is used.
+    pub fn upgrade(&self) -> Option<NodeManager> {
+        Some(NodeManager {
+            nodes: self.nodes.upgrade()?,
+            player_manager: self.player_manager.upgrade()?,
+            latency_weight: self.latency_weight,
+            selector: Arc::clone(&self.selector),
+            track_cache: self.track_cache.clone(),
+            assignments: self.assignments.upgrade()?,
+        })
+    }
+}
+
+impl Debug for WeakNodeManager {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("WeakNodeManager")
+            .field("latency_weight", &self.latency_weight)
+            .field("track_cache", &self.track_cache)
+            .finish()
+    }
+}
+
+/// Destroys a guild's player and forgets the node it was on, as done by
+/// [`NodeManager::destroy_player`] and by scheduled tasks leaving a guild.
+///
+/// [`NodeManager::destroy_player`]: struct.NodeManager.html#method.destroy_player
+pub(crate) fn destroy_player(
+    player_manager: &RwLock<AudioPlayerManager>,
+    assignments: &RwLock<HashMap<u64, String>>,
+    guild_id: u64,
+) -> Option<Arc<Mutex<AudioPlayer>>> {
+    let player = player_manager.write().destroy_player(&guild_id);
+
+    assignments.write().remove(&guild_id);
+
+    if let Some(ref player) = player {
+        dispatch_destroyed(player);
+    }
+
+    player
+}
+
+impl Debug for NodeManager {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("NodeManager")
+            .field("nodes", &self.nodes)
+            .field("player_manager", &self.player_manager)
+            .field("latency_weight", &self.latency_weight)
+            .field("track_cache", &self.track_cache)
+            .field("assignments", &self.assignments)
+            .finish()
+    }
+}
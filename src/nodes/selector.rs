@@ -0,0 +1,42 @@
+use std::sync::Arc;
+use super::{Node, NodeManager};
+
+/// Chooses the node that a new player is created on.
+///
+/// Closures taking the manager and the guild ID implement this trait.
+pub trait NodeSelector: Send + Sync {
+    /// Selects a node for the guild's player, or `None` if no node is
+    /// suitable.
+    fn select(&self, manager: &NodeManager, guild_id: u64) -> Option<Arc<Node>>;
+}
+
+impl<F> NodeSelector for F
+    where F: Fn(&NodeManager, u64) -> Option<Arc<Node>> + Send + Sync {
+    fn select(&self, manager: &NodeManager, guild_id: u64) -> Option<Arc<Node>> {
+        self(manager, guild_id)
+    }
+}
+
+/// Selects the available node with the lowest penalty.
+///
+/// This is the default selector.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LowestPenalty;
+
+impl NodeSelector for LowestPenalty {
+    fn select(&self, manager: &NodeManager, _: u64) -> Option<Arc<Node>> {
+        manager.determine_best_node()
+    }
+}
+
+/// Selects the available node with the lowest penalty in a region, falling
+/// back to any available node.
+#[derive(Clone, Debug)]
+pub struct PreferRegion(pub String);
+
+impl NodeSelector for PreferRegion {
+    fn select(&self, manager: &NodeManager, _: u64) -> Option<Arc<Node>> {
+        manager.determine_best_node_where(|node| node.region.as_ref() == Some(&self.0))
+            .or_else(|| manager.determine_best_node())
+    }
+}
@@ -0,0 +1,260 @@
+//! Translation between the websocket ops players are controlled with and the
+//! REST-based v4 protocol.
+//!
+//! Ops become updates of the player in the node's session, and events are
+//! rewritten into their v3 form so they are handled the same on either
+//! version.
+//!
+//! The requests are made by a worker thread per node, in the order they were
+//! queued, so that neither the threads sending ops nor the receive loop wait
+//! on the node.
+
+use rest::RestClient;
+use std::sync::mpsc::{self, Sender as MpscSender};
+use std::thread::{Builder as ThreadBuilder, JoinHandle};
+use super::OutgoingOp;
+use ::prelude::*;
+
+/// A request queued for a node's REST worker.
+#[derive(Debug)]
+pub enum RestRequest {
+    /// Sends an op to the player it controls in the session.
+    Op {
+        session_id: String,
+        op: OutgoingOp,
+    },
+    /// Updates the session with the given fields.
+    UpdateSession {
+        session_id: String,
+        update: Value,
+    },
+    /// Stops the worker once the requests queued before it were made.
+    Shutdown,
+}
+
+/// Spawns the worker making the REST requests queued for a node.
+///
+/// Failed requests are logged, as whoever queued them has moved on. The
+/// worker stops on a [`RestRequest::Shutdown`], or once every sender is
+/// dropped.
+///
+/// [`RestRequest::Shutdown`]: enum.RestRequest.html#variant.Shutdown
+pub fn spawn_worker(host: String, rest: RestClient) -> Result<(MpscSender<RestRequest>, JoinHandle<()>)> {
+    let (tx, rx) = mpsc::channel::<RestRequest>();
+
+    let builder = ThreadBuilder::new().name("rest loop".into());
+    let worker = builder.spawn(move || {
+        for request in rx {
+            let result = match request {
+                RestRequest::Op { ref session_id, ref op } => send(&rest, session_id, op),
+                RestRequest::UpdateSession { ref session_id, ref update } => {
+                    rest.update_session(session_id, update)
+                },
+                RestRequest::Shutdown => break,
+            };
+
+            if let Err(why) = result {
+                warn!("Err making request {:?} to {}: {:?}", request, host, why);
+            }
+        }
+    })?;
+
+    Ok((tx, worker))
+}
+
+/// Sends an op as a request to the player in the node's session.
+pub fn send(rest: &RestClient, session_id: &str, op: &OutgoingOp) -> Result<()> {
+    match *op {
+        OutgoingOp::Destroy { guild_id } => rest.destroy_player(session_id, guild_id),
+        _ => rest.update_player(session_id, op.guild_id(), &player_update(op)),
+    }
+}
+
+/// The fields of a player update applying an op.
+fn player_update(op: &OutgoingOp) -> Value {
+    match *op {
+        OutgoingOp::Play { ref track, start_time, end_time, .. } => {
+            let mut update = json!({
+                "track": {
+                    "encoded": track,
+                },
+                "position": start_time.unwrap_or(0),
+            });
+
+            if let Some(end_time) = end_time {
+                update["endTime"] = json!(end_time);
+            }
+
+            update
+        },
+        OutgoingOp::Stop { .. } => json!({
+            "track": {
+                "encoded": null,
+            },
+        }),
+        OutgoingOp::Pause { pause, .. } => json!({
+            "paused": pause,
+        }),
+        OutgoingOp::Seek { position, .. } => json!({
+            "position": position,
+        }),
+        OutgoingOp::Volume { volume, .. } => json!({
+            "volume": volume,
+        }),
+        OutgoingOp::VoiceUpdate { ref session_id, ref event, .. } => json!({
+            "voice": {
+                "token": event["token"],
+                "endpoint": event["endpoint"],
+                "sessionId": session_id,
+            },
+        }),
+        OutgoingOp::Destroy { .. } => json!({}),
+    }
+}
+
+/// Rewrites a v4 event into its v3 form.
+///
+/// Tracks are sent as objects rather than their encoded form, and track end
+/// reasons and exception severities are camel case rather than upper snake
+/// case. The reasons of other events, such as the websocket closing, are
+/// text and kept as is.
+pub fn normalize_event(json: &mut Value) {
+    if json["track"].is_object() {
+        let encoded = json["track"]["encoded"].take();
+
+        json["track"] = encoded;
+    }
+
+    let reason = if json["type"] == "TrackEndEvent" {
+        json["reason"].as_str().map(upper_snake_case)
+    } else {
+        None
+    };
+
+    if let Some(reason) = reason {
+        json["reason"] = json!(reason);
+    }
+
+    let severity = json["exception"]["severity"].as_str().map(upper_snake_case);
+
+    if let Some(severity) = severity {
+        json["exception"]["severity"] = json!(severity);
+    }
+}
+
+/// Converts a camel case name, such as `loadFailed`, to upper snake case.
+fn upper_snake_case(name: &str) -> String {
+    let mut converted = String::with_capacity(name.len() + 4);
+
+    for c in name.chars() {
+        if c.is_uppercase() && !converted.is_empty() {
+            converted.push('_');
+        }
+
+        converted.extend(c.to_uppercase());
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_event, player_update, upper_snake_case};
+    use nodes::OutgoingOp;
+
+    #[test]
+    fn converts_camel_case_to_upper_snake_case() {
+        assert_eq!(upper_snake_case("finished"), "FINISHED");
+        assert_eq!(upper_snake_case("loadFailed"), "LOAD_FAILED");
+        assert_eq!(upper_snake_case("suspiciousMaybe"), "SUSPICIOUS_MAYBE");
+        assert_eq!(upper_snake_case(""), "");
+    }
+
+    #[test]
+    fn normalizes_track_end_events() {
+        let mut event = json!({
+            "op": "event",
+            "type": "TrackEndEvent",
+            "guildId": "7",
+            "track": {
+                "encoded": "encoded",
+                "info": {
+                    "title": "Never Gonna Give You Up",
+                },
+            },
+            "reason": "loadFailed",
+        });
+
+        normalize_event(&mut event);
+
+        assert_eq!(event, json!({
+            "op": "event",
+            "type": "TrackEndEvent",
+            "guildId": "7",
+            "track": "encoded",
+            "reason": "LOAD_FAILED",
+        }));
+    }
+
+    #[test]
+    fn normalizes_exception_severities() {
+        let mut event = json!({
+            "op": "event",
+            "type": "TrackExceptionEvent",
+            "guildId": "7",
+            "track": {
+                "encoded": "encoded",
+            },
+            "exception": {
+                "message": "it broke",
+                "severity": "suspicious",
+            },
+        });
+
+        normalize_event(&mut event);
+
+        assert_eq!(event["track"], "encoded");
+        assert_eq!(event["exception"]["severity"], "SUSPICIOUS");
+        assert_eq!(event["exception"]["message"], "it broke");
+    }
+
+    #[test]
+    fn leaves_events_without_tracks_alone() {
+        let closed = json!({
+            "op": "event",
+            "type": "WebSocketClosedEvent",
+            "guildId": "7",
+            "code": 4006,
+            "reason": "Session is no longer valid.",
+            "byRemote": true,
+        });
+        let mut event = closed.clone();
+
+        normalize_event(&mut event);
+
+        assert_eq!(event, closed);
+    }
+
+    #[test]
+    fn builds_player_updates() {
+        let play = player_update(&OutgoingOp::Play {
+            guild_id: 7,
+            track: "encoded".to_owned(),
+            start_time: None,
+            end_time: Some(1000),
+        });
+
+        assert_eq!(play, json!({
+            "track": {
+                "encoded": "encoded",
+            },
+            "position": 0,
+            "endTime": 1000,
+        }));
+
+        let stop = player_update(&OutgoingOp::Stop { guild_id: 7 });
+
+        assert_eq!(stop["track"]["encoded"], json!(null));
+        assert_eq!(player_update(&OutgoingOp::Pause { guild_id: 7, pause: true }), json!({ "paused": true }));
+    }
+}
@@ -1,47 +1,281 @@
-use parking_lot::Mutex;
-use serde_json;
-use std::collections::HashMap;
+use decoder;
+use parking_lot::{Mutex, RwLock};
+use rest::TrackInfo;
+use serenity::model::id::{ChannelId, UserId};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Debug, Formatter, Result as FmtResult};
-use std::sync::mpsc::Sender;
-use std::sync::Arc;
-use lavalink::model::{Pause, Play, Stop, Volume};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ::inactivity::VoiceLeaver;
+use ::nodes::{self, NodeHandle, OutgoingOp};
 use ::prelude::*;
-use ::listener::AudioPlayerListener;
-use websocket::OwnedMessage;
+use ::listener::{self, AudioPlayerListener};
+use ::recovery::{RecoveryAction, RecoveryPolicy};
+use ::schedule::{ScheduledAction, ScheduledTask, ScheduledTasks, ScheduleTrigger};
+use ::scheduler::Scheduler;
 
 type AudioPlayerMap = HashMap<u64, Arc<Mutex<AudioPlayer>>>;
 
+/// The default number of ended tracks each player remembers.
+pub const DEFAULT_PLAYER_HISTORY: usize = 20;
+
+/// The highest volume a node accepts.
+pub const MAX_VOLUME: i32 = 1000;
+
+/// The interval between the volume steps of a fade, in milliseconds.
+const FADE_STEP_MS: u64 = 100;
+
+/// Custom data attached to a track.
+///
+/// Any `Send + Sync` type implements this trait.
+pub trait UserData: Any + Send + Sync {
+    fn as_any(&self) -> &Any;
+}
+
+impl<T: Any + Send + Sync> UserData for T {
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// Data going along with a track from the queue to the history, such as who
+/// asked for it.
+#[derive(Clone, Default)]
+pub struct TrackData {
+    /// The user who asked for the track.
+    pub requester: Option<UserId>,
+    /// The text channel the track was asked for in.
+    pub text_channel: Option<ChannelId>,
+    user_data: Option<Arc<UserData>>,
+}
+
+impl TrackData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn requester(mut self, requester: UserId) -> Self {
+        self.requester = Some(requester);
+
+        self
+    }
+
+    pub fn text_channel(mut self, text_channel: ChannelId) -> Self {
+        self.text_channel = Some(text_channel);
+
+        self
+    }
+
+    /// Attaches custom data, replacing any attached before.
+    pub fn user_data<T: UserData>(mut self, data: T) -> Self {
+        self.user_data = Some(Arc::new(data));
+
+        self
+    }
+
+    /// The attached custom data, if it is of the given type.
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        let data = self.user_data.as_ref()?;
+
+        // deref to the trait object, as the Arc itself is also user data
+        (**data).as_any().downcast_ref()
+    }
+
+    pub fn has_user_data(&self) -> bool {
+        self.user_data.is_some()
+    }
+}
+
+impl Debug for TrackData {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("TrackData")
+            .field("requester", &self.requester)
+            .field("text_channel", &self.text_channel)
+            .field("has_user_data", &self.user_data.is_some())
+            .finish()
+    }
+}
+
+/// A track waiting in a player's queue.
+#[derive(Clone, Debug)]
+pub struct QueuedTrack {
+    /// The base64-encoded track.
+    pub track: String,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    /// The gain in decibels applied to the player's volume while the track
+    /// plays, to even out the loudness of tracks.
+    pub gain_db: Option<f64>,
+    pub data: TrackData,
+}
+
+impl QueuedTrack {
+    pub fn new<S: Into<String>>(track: S) -> Self {
+        Self {
+            track: track.into(),
+            start_time: None,
+            end_time: None,
+            gain_db: None,
+            data: TrackData::default(),
+        }
+    }
+
+    /// Sets the loudness normalization gain in decibels.
+    pub fn with_gain(mut self, gain_db: f64) -> Self {
+        self.gain_db = Some(gain_db);
+
+        self
+    }
+
+    /// Attaches data, such as who asked for the track.
+    pub fn with_data(mut self, data: TrackData) -> Self {
+        self.data = data;
+
+        self
+    }
+}
+
+/// A track that a player played.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    /// The base64-encoded track.
+    pub track: String,
+    /// The decoded track, if it could be decoded.
+    pub info: Option<TrackInfo>,
+    pub started_at: SystemTime,
+    pub ended_at: SystemTime,
+    /// Why the track ended, such as `FINISHED` or `REPLACED`.
+    pub reason: String,
+    /// The normalization gain the track was played with, in decibels.
+    pub gain_db: f64,
+    pub data: TrackData,
+}
+
+impl From<String> for QueuedTrack {
+    fn from(track: String) -> Self {
+        Self::new(track)
+    }
+}
+
+impl<'a> From<&'a str> for QueuedTrack {
+    fn from(track: &'a str) -> Self {
+        Self::new(track)
+    }
+}
+
 // todo potentially split state into child struct to avoid mutable reference of AudioPlayer
 // where mutablity should not be nessesary for non state fields
 #[derive(Clone)]
 pub struct AudioPlayer {
-    pub sender: Arc<Mutex<Sender<OwnedMessage>>>,
+    node: NodeHandle,
     pub guild_id: u64,
     pub track: Option<String>,
     pub time: i64,
     pub position: i64,
     pub paused: bool,
     pub volume: i32,
+    /// The tracks played next, by [`play_next`] or when the current one
+    /// ends if [`auto_advance`] is set.
+    ///
+    /// [`auto_advance`]: #structfield.auto_advance
+    /// [`play_next`]: #method.play_next
+    pub queue: VecDeque<QueuedTrack>,
+    /// How the player recovers from stuck tracks and track exceptions.
+    pub recovery: RecoveryPolicy,
+    /// Whether the next queued track is played when the node finishes the
+    /// current one or fails to load it.
+    pub auto_advance: bool,
+    /// When the position was last updated, by the local clock.
+    updated_at: Option<Instant>,
+    /// When the current track started.
+    started_at: Option<SystemTime>,
+    /// The position the current track is played until, if not its end.
+    end_time: Option<u64>,
+    /// The normalization gain of the current track, in decibels.
+    gain_db: f64,
+    /// The data of the current track.
+    data: TrackData,
+    /// The tracks that ended, oldest first.
+    history: VecDeque<HistoryEntry>,
+    history_capacity: usize,
+    /// The number of times the current track was retried in a row.
+    retries: u32,
+    /// The action chosen for a track exception, taken once the node ends the
+    /// failed track.
+    pending_recovery: Option<RecoveryAction>,
+    /// Incremented by every volume change, cancelling the steps of fades
+    /// started before it.
+    fade_id: u64,
+    scheduler: Scheduler,
+    /// The player itself, for scheduled fade steps.
+    this: Weak<Mutex<AudioPlayer>>,
     listener: Arc<AudioPlayerListener>,
+    listener_override: Option<Arc<AudioPlayerListener>>,
 }
 
 impl AudioPlayer {
-    fn new(sender: Arc<Mutex<Sender<OwnedMessage>>>, guild_id: u64, listener: Arc<AudioPlayerListener>) -> Self {
+    fn new(
+        node: NodeHandle,
+        guild_id: u64,
+        listener: Arc<AudioPlayerListener>,
+        recovery: RecoveryPolicy,
+        auto_advance: bool,
+        scheduler: Scheduler,
+    ) -> Self {
         Self {
-            sender,
+            node,
             guild_id,
             track: None,
             time: 0,
             position: 0,
             paused: false,
             volume: 100,
+            queue: VecDeque::new(),
+            recovery,
+            auto_advance,
+            updated_at: None,
+            started_at: None,
+            end_time: None,
+            gain_db: 0f64,
+            data: TrackData::default(),
+            history: VecDeque::new(),
+            history_capacity: DEFAULT_PLAYER_HISTORY,
+            retries: 0,
+            pending_recovery: None,
+            fade_id: 0,
+            scheduler,
+            this: Weak::new(),
             listener,
+            listener_override: None,
         }
     }
 
+    /// The listener receiving this player's events, which is the manager's
+    /// listener unless overridden.
+    pub fn listener(&self) -> Arc<AudioPlayerListener> {
+        match self.listener_override {
+            Some(ref listener) => Arc::clone(listener),
+            None => Arc::clone(&self.listener),
+        }
+    }
+
+    /// Overrides the listener receiving this player's events.
+    ///
+    /// Pass `None` to fall back to the manager's listener.
+    pub fn set_listener(&mut self, listener: Option<Arc<AudioPlayerListener>>) {
+        self.listener_override = listener;
+    }
+
+    /// Whether the player has its own listener rather than the manager's.
+    pub fn has_listener_override(&self) -> bool {
+        self.listener_override.is_some()
+    }
+
+    /// The node the player lives on.
     #[inline]
-    fn send(&self, message: Vec<u8>) -> Result<()> {
-        self.sender.lock().send(OwnedMessage::Binary(message)).map_err(From::from)
+    pub fn node(&self) -> &NodeHandle {
+        &self.node
     }
 
     pub fn play(
@@ -50,45 +284,367 @@ impl AudioPlayer {
         start_time: Option<u64>,
         end_time: Option<u64>,
     ) -> Result<()> {
-        let result = self.send(serde_json::to_vec(&Play::new(
-            &self.guild_id.to_string()[..],
-            track,
+        self.play_queued(QueuedTrack {
+            track: track.to_owned(),
+            start_time,
+            end_time,
+            gain_db: None,
+            data: TrackData::default(),
+        })
+    }
+
+    /// Plays a track along with who asked for it.
+    pub fn play_queued(&mut self, track: QueuedTrack) -> Result<()> {
+        self.retries = 0;
+        self.pending_recovery = None;
+
+        let replaced = self.history_entry("REPLACED");
+
+        self.play_with_gain(
+            &track.track,
+            track.start_time,
+            track.end_time,
+            track.gain_db.unwrap_or(0f64),
+            track.data,
+            replaced,
+        )
+    }
+
+    /// The data of the current track, such as who asked for it.
+    #[inline]
+    pub fn track_data(&self) -> &TrackData {
+        &self.data
+    }
+
+    /// The position the current track is played until, if it was played
+    /// with an end time.
+    #[inline]
+    pub fn end_time(&self) -> Option<u64> {
+        self.end_time
+    }
+
+    /// The position of the current track, extrapolated from the last update
+    /// sent by the node.
+    ///
+    /// The time since the update is measured from when it was received
+    /// rather than from the node's time, as the clocks may differ.
+    pub fn estimated_position(&self) -> i64 {
+        let updated_at = match self.updated_at {
+            Some(updated_at) if !self.paused && self.track.is_some() => updated_at,
+            _ => return self.position,
+        };
+
+        let elapsed = updated_at.elapsed();
+
+        self.position + elapsed.as_secs() as i64 * 1000 + i64::from(elapsed.subsec_nanos() / 1_000_000)
+    }
+
+    /// Records a player update sent by the node.
+    pub(crate) fn update_position(&mut self, time: i64, position: i64) {
+        self.time = time;
+        self.position = position;
+        self.updated_at = Some(Instant::now());
+    }
+
+    /// The user who asked for the current track.
+    #[inline]
+    pub fn requester(&self) -> Option<UserId> {
+        self.data.requester
+    }
+
+    /// The tracks that ended, oldest first.
+    #[inline]
+    pub fn history(&self) -> &VecDeque<HistoryEntry> {
+        &self.history
+    }
+
+    /// Sets the number of ended tracks the player remembers, forgetting the
+    /// oldest ones over it.
+    pub fn set_history_capacity(&mut self, capacity: usize) {
+        self.history_capacity = capacity;
+
+        while self.history.len() > capacity {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Replays the last track in the history, returning whether there was
+    /// one.
+    ///
+    /// The current track, if any, is put back at the front of the queue
+    /// rather than into the history.
+    pub fn previous(&mut self) -> Result<bool> {
+        let entry = match self.history.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+
+        let requeued = match self.track.clone() {
+            Some(current) => {
+                self.queue.push_front(QueuedTrack {
+                    track: current,
+                    start_time: None,
+                    end_time: None,
+                    gain_db: Some(self.gain_db),
+                    data: self.data.clone(),
+                });
+
+                true
+            },
+            None => false,
+        };
+
+        self.retries = 0;
+        self.pending_recovery = None;
+
+        let data = entry.data.clone();
+
+        if let Err(why) = self.play_with_gain(&entry.track, None, None, entry.gain_db, data, None) {
+            if requeued {
+                self.queue.pop_front();
+            }
+
+            self.history.push_back(entry);
+
+            return Err(why);
+        }
+
+        Ok(true)
+    }
+
+    /// Adds a track to the end of the queue.
+    pub fn enqueue<T: Into<QueuedTrack>>(&mut self, track: T) {
+        self.queue.push_back(track.into());
+    }
+
+    /// Plays the next queued track, returning whether there was one.
+    pub fn play_next(&mut self) -> Result<bool> {
+        let next = match self.queue.pop_front() {
+            Some(next) => next,
+            None => return Ok(false),
+        };
+
+        self.play_queued(next)?;
+
+        Ok(true)
+    }
+
+    /// Plays the next queued track, or stops if the queue is empty.
+    pub fn skip(&mut self) -> Result<()> {
+        if self.play_next()? {
+            return Ok(());
+        }
+
+        self.stop()
+    }
+
+    /// Records the action to take once the node ends a track that threw an
+    /// exception.
+    pub(crate) fn set_pending_recovery(&mut self, action: RecoveryAction) {
+        self.pending_recovery = match action {
+            RecoveryAction::Ignore => None,
+            action => Some(action),
+        };
+    }
+
+    /// Takes the action recorded for a track exception, if any.
+    pub(crate) fn take_pending_recovery(&mut self) -> Option<RecoveryAction> {
+        self.pending_recovery.take()
+    }
+
+    /// Recovers from the failure of a track.
+    ///
+    /// `ended` is whether the node already ended the track, in which case
+    /// there is nothing to stop.
+    pub(crate) fn recover(
+        &mut self,
+        action: RecoveryAction,
+        track: &str,
+        position: i64,
+        ended: bool,
+    ) -> Result<()> {
+        match action {
+            RecoveryAction::Ignore => Ok(()),
+            RecoveryAction::Retry(max) if self.retries < max => {
+                self.retries += 1;
+
+                debug!(
+                    "retrying track for guild {} from {} ({}/{})",
+                    self.guild_id,
+                    position,
+                    self.retries,
+                    max,
+                );
+
+                let data = self.data.clone();
+
+                self.send_play(track, Some(position.max(0) as u64), None, data, None)
+            },
+            RecoveryAction::Retry(_) | RecoveryAction::Skip => if ended {
+                self.play_next().map(|_| ())
+            } else {
+                self.skip()
+            },
+            RecoveryAction::Stop => if ended {
+                Ok(())
+            } else {
+                self.stop()
+            },
+        }
+    }
+
+    /// Records the current track in the history and resets the player so
+    /// nothing is playing, returning the ended track's data.
+    pub(crate) fn finish_track(&mut self, reason: &str) -> TrackData {
+        if let Some(entry) = self.history_entry(reason) {
+            self.push_history(entry);
+        }
+
+        // Set the player's track so nothing is playing, reset the time, and
+        // reset the position
+        self.track = None;
+        self.time = 0;
+        self.position = 0;
+        self.updated_at = None;
+        self.started_at = None;
+        self.end_time = None;
+
+        ::std::mem::replace(&mut self.data, TrackData::default())
+    }
+
+    /// Creates a history entry for the current track ending now.
+    fn history_entry(&self, reason: &str) -> Option<HistoryEntry> {
+        let track = self.track.as_ref()?;
+
+        Some(HistoryEntry {
+            track: track.clone(),
+            info: decoder::decode_track(track).ok(),
+            started_at: self.started_at.unwrap_or_else(SystemTime::now),
+            ended_at: SystemTime::now(),
+            reason: reason.to_owned(),
+            gain_db: self.gain_db,
+            data: self.data.clone(),
+        })
+    }
+
+    fn push_history(&mut self, entry: HistoryEntry) {
+        if self.history_capacity == 0 {
+            return;
+        }
+
+        while self.history.len() >= self.history_capacity {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(entry);
+    }
+
+    /// Applies a track's normalization gain and plays it, so it starts at
+    /// the right volume.
+    ///
+    /// The previous gain is put back if the track could not be played.
+    fn play_with_gain(
+        &mut self,
+        track: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        gain_db: f64,
+        data: TrackData,
+        replaced: Option<HistoryEntry>,
+    ) -> Result<()> {
+        let previous_gain = self.gain_db;
+
+        self.set_gain(gain_db)?;
+
+        let result = self.send_play(track, start_time, end_time, data, replaced);
+
+        if result.is_err() {
+            let _ = self.set_gain(previous_gain);
+        }
+
+        result
+    }
+
+    fn send_play(
+        &mut self,
+        track: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        data: TrackData,
+        replaced: Option<HistoryEntry>,
+    ) -> Result<()> {
+        let result = self.node.send(OutgoingOp::Play {
+            guild_id: self.guild_id,
+            track: track.to_string(),
             start_time,
             end_time,
-        ))?);
+        });
 
         match result {
             Ok(_) => {
+                if let Some(replaced) = replaced {
+                    self.push_history(replaced);
+                }
+
                 self.track = Some(track.to_string());
+                self.started_at = Some(SystemTime::now());
+                self.end_time = end_time;
+                self.data = data.clone();
+
+                // until the node sends an update, assume the track started now
+                self.time = now_millis();
+                self.updated_at = Some(Instant::now());
+                self.position = start_time.unwrap_or(0) as i64;
 
-                self.listener.clone()
-                    .track_start(self, track);
+                let listener = self.listener();
+
+                listener::dispatch("track_start", || listener.track_start_with_data(self, track, &data));
             },
             Err(e) => {
                 error!("play websocket send error {:?}", e);
+
+                return Err(e);
             },
         }
 
         Ok(())
     }
 
+    /// Stops playing, ending the current track with the `STOPPED` reason.
+    ///
+    /// The track's end is reported to the listener here rather than when the
+    /// node's event for it arrives, so the player's state is up to date once
+    /// this returns.
     pub fn stop(&mut self) -> Result<()> {
-        let result = self.send(serde_json::to_vec(&Stop::new(
-            &self.guild_id.to_string()[..],
-        ))?);
+        let result = self.node.send(OutgoingOp::Stop {
+            guild_id: self.guild_id,
+        });
 
         match result {
             Ok(_) => {
-                let track = self.track.clone().unwrap_or_else(|| "no track in state".to_string());
-                self.track = None;
+                let track = match self.track.clone() {
+                    Some(track) => track,
+                    None => return Ok(()),
+                };
+
+                let data = self.finish_track("STOPPED");
 
-                self.listener.clone()
-                    .track_end(self, &track, "no reason");
+                let listener = self.listener();
+
+                listener::dispatch("track_end", || {
+                    listener.track_end_with_data(self, &track, "STOPPED", &data)
+                });
 
                 debug!("stopped playing track {:?}", track);
             },
             Err(e) => {
                 error!("stop websocket send error {:?}", e);
+
+                return Err(e);
             },
         }
 
@@ -96,43 +652,167 @@ impl AudioPlayer {
     }
 
     pub fn pause(&mut self, pause: bool) -> Result<()> {
-        let result = self.send(serde_json::to_vec(&Pause::new(
-            &self.guild_id.to_string()[..],
+        let result = self.node.send(OutgoingOp::Pause {
+            guild_id: self.guild_id,
             pause,
-        ))?);
+        });
 
         match result {
             Ok(_) => {
+                // keep extrapolating the position from where it was paused
+                self.position = self.estimated_position();
+                self.time = now_millis();
+                self.updated_at = Some(Instant::now());
                 self.paused = pause;
 
-                let listener = self.listener.clone();
+                let listener = self.listener();
 
                 if pause {
-                    listener.player_pause(self);
+                    listener::dispatch("player_pause", || listener.player_pause(self));
                 } else {
-                    listener.player_resume(self);
+                    listener::dispatch("player_resume", || listener.player_resume(self));
                 }
 
                 debug!("pause audio player: {}", pause);
             },
             Err(e) => {
                 error!("pause websocket send error {:?}", e);
+
+                return Err(e);
             },
         }
 
         Ok(())
     }
 
-    #[allow(unused)]
-    pub fn seek(&mut self, position: i64) {
-        unimplemented!()
+    pub fn seek(&mut self, position: i64) -> Result<()> {
+        let result = self.node.send(OutgoingOp::Seek {
+            guild_id: self.guild_id,
+            position,
+        });
+
+        match result {
+            Ok(_) => {
+                self.position = position;
+                self.time = now_millis();
+                self.updated_at = Some(Instant::now());
+
+                debug!("seek audio player to {}", position);
+            },
+            Err(e) => {
+                error!("seek websocket send error {:?}", e);
+
+                return Err(e);
+            },
+        }
+
+        Ok(())
     }
 
+    /// Sets the volume, cancelling any fade in progress.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidVolume`] if the volume is not between 0 and
+    /// [`MAX_VOLUME`].
+    ///
+    /// [`Error::InvalidVolume`]: ../enum.Error.html#variant.InvalidVolume
+    /// [`MAX_VOLUME`]: constant.MAX_VOLUME.html
     pub fn volume(&mut self, volume: i32) -> Result<()> {
-        let result = self.send(serde_json::to_vec(&Volume::new(
-            &self.guild_id.to_string()[..],
-            volume,
-        ))?);
+        check_volume(volume)?;
+
+        self.fade_id += 1;
+
+        self.send_volume(volume)
+    }
+
+    /// Fades the volume to the target over a duration, in steps sent on a
+    /// scheduler thread.
+    ///
+    /// The fade is cancelled by later volume changes, including other fades.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidVolume`] if the target is not between 0 and
+    /// [`MAX_VOLUME`].
+    ///
+    /// [`Error::InvalidVolume`]: ../enum.Error.html#variant.InvalidVolume
+    /// [`MAX_VOLUME`]: constant.MAX_VOLUME.html
+    pub fn fade_volume(&mut self, target: i32, duration: Duration) -> Result<()> {
+        check_volume(target)?;
+
+        self.fade_id += 1;
+
+        let steps = (duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000))
+            / FADE_STEP_MS;
+
+        if steps == 0 || self.this.upgrade().is_none() {
+            return self.send_volume(target);
+        }
+
+        let fade_id = self.fade_id;
+        let start = i64::from(self.volume);
+
+        for step in 1..steps + 1 {
+            let this = self.this.clone();
+            let volume = start + (i64::from(target) - start) * step as i64 / steps as i64;
+
+            self.scheduler.schedule(Duration::from_millis(FADE_STEP_MS * step), move || {
+                let player = match this.upgrade() {
+                    Some(player) => player,
+                    None => return,
+                };
+
+                let mut player = player.lock();
+
+                if player.fade_id != fade_id {
+                    return;
+                }
+
+                let _ = player.send_volume(volume as i32);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The normalization gain of the current track, in decibels.
+    #[inline]
+    pub fn gain_db(&self) -> f64 {
+        self.gain_db
+    }
+
+    /// The volume sent to the node, which is the volume with the current
+    /// track's normalization gain applied.
+    #[inline]
+    pub fn effective_volume(&self) -> i32 {
+        apply_gain(self.volume, self.gain_db)
+    }
+
+    /// Applies a normalization gain, sending the new effective volume if it
+    /// changed.
+    fn set_gain(&mut self, gain_db: f64) -> Result<()> {
+        if (self.gain_db - gain_db).abs() < ::std::f64::EPSILON {
+            return Ok(());
+        }
+
+        let previous = ::std::mem::replace(&mut self.gain_db, gain_db);
+        let volume = self.volume;
+
+        if let Err(why) = self.send_volume(volume) {
+            self.gain_db = previous;
+
+            return Err(why);
+        }
+
+        Ok(())
+    }
+
+    fn send_volume(&mut self, volume: i32) -> Result<()> {
+        let result = self.node.send(OutgoingOp::Volume {
+            guild_id: self.guild_id,
+            volume: apply_gain(volume, self.gain_db),
+        });
 
         match result {
             Ok(_) => {
@@ -141,7 +821,9 @@ impl AudioPlayer {
                 debug!("set volume {:?}", self.volume);
             },
             Err(e) => {
-                error!("play websocket send error {:?}", e);
+                error!("volume websocket send error {:?}", e);
+
+                return Err(e);
             },
         }
 
@@ -149,16 +831,45 @@ impl AudioPlayer {
     }
 }
 
+fn check_volume(volume: i32) -> Result<()> {
+    if volume < 0 || volume > MAX_VOLUME {
+        return Err(Error::InvalidVolume(volume));
+    }
+
+    Ok(())
+}
+
+/// Scales a volume by a gain in decibels, keeping it in the range a node
+/// accepts.
+fn apply_gain(volume: i32, gain_db: f64) -> i32 {
+    let volume = f64::from(volume) * 10f64.powf(gain_db / 20f64);
+
+    (volume.round() as i32).max(0).min(MAX_VOLUME)
+}
+
+/// The milliseconds since the Unix epoch, as player update times are sent.
+fn now_millis() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    now.as_secs() as i64 * 1000 + i64::from(now.subsec_nanos() / 1_000_000)
+}
+
 impl Debug for AudioPlayer {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         fmt.debug_struct("AudioPlayer")
-            .field("sender", &self.sender)
+            .field("node", &self.node)
             .field("guild_id", &self.guild_id)
             .field("track", &self.track)
             .field("time", &self.time)
             .field("position", &self.position)
             .field("paused", &self.paused)
             .field("volume", &self.volume)
+            .field("queue", &self.queue)
+            .field("recovery", &self.recovery)
+            .field("auto_advance", &self.auto_advance)
+            .field("has_listener_override", &self.listener_override.is_some())
             .finish()
     }
 }
@@ -167,6 +878,23 @@ impl Debug for AudioPlayer {
 pub struct AudioPlayerManager {
     players: AudioPlayerMap,
     pub listener: Arc<AudioPlayerListener>,
+    /// The recovery policy new players start with.
+    pub recovery: RecoveryPolicy,
+    /// Whether new players play their next queued track when the current
+    /// one ends. Off by default.
+    pub auto_advance: bool,
+    /// Leaves voice channels for scheduled [`ScheduledAction::Leave`]s.
+    ///
+    /// [`ScheduledAction::Leave`]: ../schedule/enum.ScheduledAction.html#variant.Leave
+    pub leaver: Option<Arc<VoiceLeaver>>,
+    scheduler: Scheduler,
+    tasks: ScheduledTasks,
+    /// The manager itself as shared by its node manager, for running
+    /// scheduled tasks.
+    pub(crate) this: Weak<RwLock<AudioPlayerManager>>,
+    /// The node manager's record of the node each guild's player is on,
+    /// which scheduled tasks leaving a guild update.
+    pub(crate) assignments: Weak<RwLock<HashMap<u64, String>>>,
 }
 
 impl AudioPlayerManager {
@@ -174,18 +902,41 @@ impl AudioPlayerManager {
         Self {
             players: HashMap::default(),
             listener,
+            recovery: RecoveryPolicy::default(),
+            auto_advance: false,
+            leaver: None,
+            scheduler: Scheduler::new(),
+            tasks: ScheduledTasks::default(),
+            this: Weak::new(),
+            assignments: Weak::new(),
         }
     }
 
     // utility assosiated function for creating AudioPlayer instances wrapped in Arc & Mutex
-    fn new_player(&self, sender: Arc<Mutex<Sender<OwnedMessage>>>, guild_id: u64) -> Arc<Mutex<AudioPlayer>> {
-        Arc::new(Mutex::new(AudioPlayer::new(sender, guild_id, self.listener.clone())))
+    fn new_player(&self, node: NodeHandle, guild_id: u64) -> Arc<Mutex<AudioPlayer>> {
+        let player = Arc::new(Mutex::new(AudioPlayer::new(
+            node,
+            guild_id,
+            self.listener.clone(),
+            self.recovery.clone(),
+            self.auto_advance,
+            self.scheduler.clone(),
+        )));
+
+        player.lock().this = Arc::downgrade(&player);
+
+        player
     }
 
     pub fn has_player(&self, guild_id: &u64) -> bool {
         self.players.contains_key(guild_id)
     }
 
+    /// The IDs of the guilds that have a player.
+    pub fn guild_ids(&self) -> Vec<u64> {
+        self.players.keys().cloned().collect()
+    }
+
     pub fn get_player(&self, guild_id: &u64) -> Option<Arc<Mutex<AudioPlayer>>> {
         let player = match self.players.get(guild_id) {
             Some(player) => player,
@@ -195,21 +946,244 @@ impl AudioPlayerManager {
         Some(Arc::clone(player))
     }
 
-    pub fn create_player(&mut self, sender: Arc<Mutex<Sender<OwnedMessage>>>, guild_id: u64) -> Result<Arc<Mutex<AudioPlayer>>> {
+    /// Creates a player for the guild on the node, returning it.
+    ///
+    /// The listener's `player_created` hook is not called here, as the
+    /// manager is locked by the caller. [`NodeManager::create_player`] calls
+    /// it once the lock is released.
+    ///
+    /// [`NodeManager::create_player`]: ../nodes/struct.NodeManager.html#method.create_player
+    pub fn create_player(&mut self, node: NodeHandle, guild_id: u64) -> Result<Arc<Mutex<AudioPlayer>>> {
         // we dont use #has_key yet because it would get its own players clone & mutex lock
         if self.players.contains_key(&guild_id) {
             return Err(Error::PlayerAlreadyExists);
         }
 
-        let player = self.new_player(sender, guild_id);
-        let _ = self.players.insert(guild_id, player);
+        let player = self.new_player(node, guild_id);
+        let _ = self.players.insert(guild_id, Arc::clone(&player));
+
+        Ok(player)
+    }
+
+    /// Removes the guild's player and tells its node to destroy it, returning
+    /// the removed player if there was one.
+    ///
+    /// The player is removed even if the node could not be told to destroy
+    /// it, such as when the node is closed. As with [`create_player`], the
+    /// listener's `player_destroyed` hook is left to
+    /// [`NodeManager::destroy_player`].
+    ///
+    /// [`create_player`]: #method.create_player
+    /// [`NodeManager::destroy_player`]: ../nodes/struct.NodeManager.html#method.destroy_player
+    pub fn destroy_player(&mut self, guild_id: &u64) -> Option<Arc<Mutex<AudioPlayer>>> {
+        let player = self.players.remove(guild_id)?;
+
+        self.cancel_all_scheduled(*guild_id);
+
+        {
+            let player = player.lock();
+
+            if let Err(why) = player.node.send(OutgoingOp::Destroy { guild_id: *guild_id }) {
+                warn!("Err destroying player for guild {} on its node: {:?}", guild_id, why);
+            }
+        }
+
+        Some(player)
+    }
+
+    /// Sets what leaves voice channels for scheduled
+    /// [`ScheduledAction::Leave`]s.
+    ///
+    /// [`ScheduledAction::Leave`]: ../schedule/enum.ScheduledAction.html#variant.Leave
+    pub fn set_leaver<L: VoiceLeaver + 'static>(&mut self, leaver: L) {
+        self.leaver = Some(Arc::new(leaver));
+    }
+
+    /// Schedules an action on a guild's player, returning the ID of the
+    /// task.
+    ///
+    /// Delayed tasks run on a scheduler thread once the manager belongs to a
+    /// [`NodeManager`]. Tasks for a guild without a player do nothing when due.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidVolume`] if a volume action's volume is out of
+    /// range, or [`Error::NoVoiceLeaver`] if the action is to leave but no
+    /// leaver is set.
+    ///
+    /// [`Error::InvalidVolume`]: ../enum.Error.html#variant.InvalidVolume
+    /// [`Error::NoVoiceLeaver`]: ../enum.Error.html#variant.NoVoiceLeaver
+    /// [`NodeManager`]: ../nodes/struct.NodeManager.html
+    pub fn schedule(
+        &self,
+        guild_id: u64,
+        trigger: ScheduleTrigger,
+        action: ScheduledAction,
+    ) -> Result<u64> {
+        match action {
+            ScheduledAction::Volume(volume) => check_volume(volume)?,
+            ScheduledAction::Leave if self.leaver.is_none() => return Err(Error::NoVoiceLeaver),
+            _ => {},
+        }
+
+        let id = self.tasks.insert(guild_id, action, trigger);
+
+        if let ScheduleTrigger::After(delay) = trigger {
+            let manager = self.this.clone();
+            let tasks = self.tasks.clone();
+
+            let job = self.scheduler.schedule(delay, move || {
+                // cancelled tasks are removed before their job would run
+                let task = match tasks.remove(id) {
+                    Some((task, _)) => task,
+                    None => return,
+                };
+
+                if let Some(manager) = manager.upgrade() {
+                    run_task(&manager, task.guild_id, task.action);
+                }
+            });
+
+            self.tasks.set_job(id, job);
+        }
+
+        debug!("scheduled {:?} for guild {} on {:?}", action, guild_id, trigger);
+
+        Ok(id)
+    }
+
+    /// Cancels a scheduled task, returning whether it was still due.
+    pub fn cancel_scheduled(&self, id: u64) -> bool {
+        match self.tasks.remove(id) {
+            Some((_, job)) => {
+                if let Some(job) = job {
+                    self.scheduler.cancel(job);
+                }
+
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Cancels every task scheduled for a guild, returning how many were
+    /// still due.
+    pub fn cancel_all_scheduled(&self, guild_id: u64) -> usize {
+        let removed = self.tasks.remove_where(guild_id, |_| true);
+
+        for &(_, job) in &removed {
+            if let Some(job) = job {
+                self.scheduler.cancel(job);
+            }
+        }
+
+        removed.len()
+    }
+
+    /// The tasks still due for a guild, in the order they were scheduled.
+    pub fn scheduled(&self, guild_id: u64) -> Vec<ScheduledTask> {
+        self.tasks.list(guild_id)
+    }
+
+    /// Runs the tasks scheduled for the end of a player's track, returning
+    /// whether the next track should still be started.
+    pub(crate) fn run_track_end_tasks(&self, player: &mut AudioPlayer) -> bool {
+        let guild_id = player.guild_id;
+        let tasks = self.tasks.remove_where(guild_id, |task| {
+            task.trigger == ScheduleTrigger::TrackEnd
+        });
+
+        let mut keep_playing = true;
+
+        for (task, _) in tasks {
+            let result = match task.action {
+                ScheduledAction::Stop => {
+                    keep_playing = false;
+
+                    Ok(())
+                },
+                // the node keeps the player paused for the next track
+                ScheduledAction::Pause => player.pause(true),
+                ScheduledAction::Volume(volume) => player.volume(volume),
+                ScheduledAction::Leave => {
+                    keep_playing = false;
+
+                    // destroying the player needs the locks held here
+                    let delay = Duration::from_secs(0);
+
+                    self.schedule(guild_id, ScheduleTrigger::After(delay), ScheduledAction::Leave)
+                        .map(|_| ())
+                },
+            };
+
+            if let Err(why) = result {
+                warn!("Err running scheduled {:?} for guild {}: {:?}", task.action, guild_id, why);
+            }
+        }
+
+        keep_playing
+    }
+}
+
+/// Runs a due task's action on the guild's player.
+fn run_task(manager: &RwLock<AudioPlayerManager>, guild_id: u64, action: ScheduledAction) {
+    if action == ScheduledAction::Leave {
+        let (leaver, assignments) = {
+            let manager = manager.read();
+
+            (manager.leaver.clone(), manager.assignments.upgrade())
+        };
+
+        if let Some(leaver) = leaver {
+            leaver.leave(guild_id);
+        }
+
+        if let Some(assignments) = assignments {
+            nodes::destroy_player(manager, &assignments, guild_id);
+        }
+
+        return;
+    }
+
+    let player = match manager.read().get_player(&guild_id) {
+        Some(player) => player,
+        None => return,
+    };
+
+    let mut player = player.lock();
+
+    let result = match action {
+        ScheduledAction::Stop => player.stop(),
+        ScheduledAction::Pause => player.pause(true),
+        ScheduledAction::Volume(volume) => player.volume(volume),
+        ScheduledAction::Leave => Ok(()),
+    };
 
-        // unwrap because we can assert it exists after insertion
-        let player = &self.players[&guild_id];
-        Ok(Arc::clone(player))
+    if let Err(why) = result {
+        warn!("Err running scheduled {:?} for guild {}: {:?}", action, guild_id, why);
     }
 }
 
+/// Calls the `player_created` hook of a player's listener.
+///
+/// This is called once the manager is unlocked, so the hook can use it.
+pub(crate) fn dispatch_created(player: &Arc<Mutex<AudioPlayer>>) {
+    let mut player = player.lock();
+    let listener = player.listener();
+
+    listener::dispatch("player_created", || listener.player_created(&mut player));
+}
+
+/// Calls the `player_destroyed` hook of a player's listener.
+///
+/// This is called once the manager is unlocked, so the hook can use it.
+pub(crate) fn dispatch_destroyed(player: &Arc<Mutex<AudioPlayer>>) {
+    let mut player = player.lock();
+    let listener = player.listener();
+
+    listener::dispatch("player_destroyed", || listener.player_destroyed(&mut player));
+}
+
 impl Debug for AudioPlayerManager {
     fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
         fmt.debug_struct("AudioPlayerManager")
@@ -217,3 +1191,246 @@ impl Debug for AudioPlayerManager {
             .finish()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use nodes::{NodeConfig, NodeStatus, State};
+    use rest::RestClient;
+    use serde_json::{self, Value};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use websocket::OwnedMessage;
+    use super::*;
+    use ::listener::ListenerChain;
+
+    /// A player on a connected node, with the ops sent to the node.
+    pub(crate) fn player() -> (Arc<Mutex<AudioPlayer>>, Receiver<OwnedMessage>) {
+        let config = NodeConfig::builder()
+            .http_host("http://localhost:2333")
+            .websocket_host("ws://localhost:80")
+            .user_id(1u64)
+            .password("password")
+            .num_shards(1)
+            .build()
+            .unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let state = Arc::new(RwLock::new(State {
+            status: NodeStatus::Connected,
+            ..State::default()
+        }));
+        let node = NodeHandle::new(
+            config.display_name().to_owned(),
+            config.websocket_host.clone(),
+            Arc::new(Mutex::new(tx)),
+            None,
+            RestClient::new(&config).unwrap(),
+            state,
+        );
+
+        let player = Arc::new(Mutex::new(AudioPlayer::new(
+            node,
+            7,
+            Arc::new(ListenerChain::new()),
+            RecoveryPolicy::default(),
+            false,
+            Scheduler::new(),
+        )));
+
+        player.lock().this = Arc::downgrade(&player);
+
+        (player, rx)
+    }
+
+    /// The ops sent since the last call.
+    pub(crate) fn sent(ops: &Receiver<OwnedMessage>) -> Vec<Value> {
+        ops.try_iter()
+            .filter_map(|message| match message {
+                OwnedMessage::Text(text) => serde_json::from_str(&text).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The volumes sent since the last call.
+    fn volumes(ops: &Receiver<OwnedMessage>) -> Vec<i64> {
+        sent(ops)
+            .into_iter()
+            .filter(|op| op["op"] == "volume")
+            .filter_map(|op| op["volume"].as_i64())
+            .collect()
+    }
+
+    fn history_tracks(player: &AudioPlayer) -> Vec<&str> {
+        player.history().iter().map(|entry| &entry.track[..]).collect()
+    }
+
+    #[test]
+    fn history_forgets_the_oldest_tracks_at_capacity() {
+        let (player, _ops) = player();
+        let mut player = player.lock();
+
+        player.set_history_capacity(2);
+
+        for track in &["a", "b", "c", "d"] {
+            player.play(track, None, None).unwrap();
+        }
+
+        assert_eq!(history_tracks(&player), vec!["b", "c"]);
+        assert!(player.history().iter().all(|entry| entry.reason == "REPLACED"));
+
+        player.set_history_capacity(1);
+        assert_eq!(history_tracks(&player), vec!["c"]);
+
+        player.set_history_capacity(0);
+        player.play("e", None, None).unwrap();
+        assert!(player.history().is_empty());
+    }
+
+    #[test]
+    fn previous_requeues_the_current_track() {
+        let (player, ops) = player();
+        let mut player = player.lock();
+
+        player.play("a", None, None).unwrap();
+        player.play("b", None, None).unwrap();
+        player.enqueue("c");
+        sent(&ops);
+
+        assert!(player.previous().unwrap());
+
+        assert_eq!(player.track, Some("a".to_owned()));
+        assert!(player.history().is_empty());
+
+        let queued = player.queue.iter().map(|queued| &queued.track[..]).collect::<Vec<_>>();
+        assert_eq!(queued, vec!["b", "c"]);
+
+        let sent = sent(&ops);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["op"], "play");
+        assert_eq!(sent[0]["track"], "a");
+    }
+
+    #[test]
+    fn previous_without_history_does_nothing() {
+        let (player, ops) = player();
+        let mut player = player.lock();
+
+        player.play("a", None, None).unwrap();
+        player.clear_history();
+        sent(&ops);
+
+        assert!(!player.previous().unwrap());
+        assert_eq!(player.track, Some("a".to_owned()));
+        assert!(player.queue.is_empty());
+        assert!(sent(&ops).is_empty());
+    }
+
+    #[test]
+    fn gain_scales_the_volume_in_decibels() {
+        assert_eq!(apply_gain(100, 0f64), 100);
+        assert_eq!(apply_gain(100, 6f64), 200);
+        assert_eq!(apply_gain(100, -6f64), 50);
+        assert_eq!(apply_gain(100, -20f64), 10);
+        assert_eq!(apply_gain(0, 12f64), 0);
+    }
+
+    #[test]
+    fn gain_keeps_the_volume_in_range() {
+        assert_eq!(apply_gain(800, 6f64), MAX_VOLUME);
+        assert_eq!(apply_gain(MAX_VOLUME, 0.1), MAX_VOLUME);
+        assert_eq!(apply_gain(1, -60f64), 0);
+    }
+
+    #[test]
+    fn volumes_must_be_in_range() {
+        assert!(check_volume(0).is_ok());
+        assert!(check_volume(MAX_VOLUME).is_ok());
+
+        match check_volume(-1) {
+            Err(Error::InvalidVolume(-1)) => {},
+            other => panic!("expected an invalid volume, got {:?}", other),
+        }
+
+        match check_volume(MAX_VOLUME + 1) {
+            Err(Error::InvalidVolume(1001)) => {},
+            other => panic!("expected an invalid volume, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_volumes_are_not_sent() {
+        let (player, ops) = player();
+        let mut player = player.lock();
+
+        assert!(player.volume(MAX_VOLUME + 1).is_err());
+        assert!(player.fade_volume(-1, Duration::from_millis(500)).is_err());
+
+        assert_eq!(player.volume, 100);
+        assert!(sent(&ops).is_empty());
+    }
+
+    #[test]
+    fn gain_is_applied_to_sent_volumes() {
+        let (player, ops) = player();
+        let mut player = player.lock();
+
+        player.play_queued(QueuedTrack::new("a").with_gain(-6f64)).unwrap();
+
+        assert_eq!(volumes(&ops), vec![50]);
+        assert_eq!(player.volume, 100);
+        assert_eq!(player.effective_volume(), 50);
+
+        player.volume(200).unwrap();
+        assert_eq!(volumes(&ops), vec![100]);
+
+        // the next track without a gain goes back to the plain volume
+        player.play("b", None, None).unwrap();
+        assert_eq!(volumes(&ops), vec![200]);
+    }
+
+    #[test]
+    fn fades_step_evenly_to_the_target() {
+        let (player, ops) = player();
+
+        player.lock().fade_volume(0, Duration::from_millis(500)).unwrap();
+
+        let mut stepped = Vec::new();
+
+        while stepped.len() < 5 {
+            match ops.recv_timeout(Duration::from_secs(5)).unwrap() {
+                OwnedMessage::Text(text) => {
+                    let op: Value = serde_json::from_str(&text).unwrap();
+                    stepped.push(op["volume"].as_i64().unwrap());
+                },
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+
+        assert_eq!(stepped, vec![80, 60, 40, 20, 0]);
+        assert_eq!(player.lock().volume, 0);
+    }
+
+    #[test]
+    fn fades_shorter_than_a_step_set_the_volume_at_once() {
+        let (player, ops) = player();
+
+        player.lock().fade_volume(50, Duration::from_millis(50)).unwrap();
+
+        assert_eq!(volumes(&ops), vec![50]);
+        assert_eq!(player.lock().volume, 50);
+    }
+
+    #[test]
+    fn volume_changes_cancel_fades() {
+        let (player, ops) = player();
+
+        player.lock().fade_volume(0, Duration::from_millis(300)).unwrap();
+        player.lock().volume(30).unwrap();
+
+        thread::sleep(Duration::from_millis(500));
+
+        assert_eq!(volumes(&ops), vec![30]);
+        assert_eq!(player.lock().volume, 30);
+    }
+}
@@ -0,0 +1,116 @@
+//! Policies for recovering players from stuck tracks and track exceptions.
+
+use std::str::FromStr;
+
+/// How severe a track exception is, as reported by the node.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ExceptionSeverity {
+    /// The cause is known and expected, such as a video being unavailable.
+    Common,
+    /// The cause might not be exactly known, but is possibly caused by
+    /// outside factors, such as the network.
+    Suspicious,
+    /// The probable cause is a bug in the node.
+    Fault,
+}
+
+impl FromStr for ExceptionSeverity {
+    type Err = ();
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, ()> {
+        match s {
+            "COMMON" => Ok(ExceptionSeverity::Common),
+            "SUSPICIOUS" => Ok(ExceptionSeverity::Suspicious),
+            "FAULT" => Ok(ExceptionSeverity::Fault),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What a player does when its track gets stuck or throws an exception.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RecoveryAction {
+    /// Leave it to the listener.
+    Ignore,
+    /// Play the same track again from its last position, up to the given
+    /// number of times in a row, then skip it.
+    Retry(u32),
+    /// Play the next queued track, stopping if the queue is empty.
+    Skip,
+    /// Stop playing.
+    Stop,
+}
+
+/// Chooses the [`RecoveryAction`] for a player's failing track.
+///
+/// [`RecoveryAction`]: enum.RecoveryAction.html
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RecoveryPolicy {
+    /// The action for exceptions with a `COMMON` severity.
+    pub common: RecoveryAction,
+    /// The action for exceptions with a `SUSPICIOUS` severity, or without a
+    /// severity, as sent by nodes older than Lavalink v3.
+    pub suspicious: RecoveryAction,
+    /// The action for exceptions with a `FAULT` severity.
+    pub fault: RecoveryAction,
+    /// The action for stuck tracks.
+    pub stuck: RecoveryAction,
+    /// The stuck threshold, in milliseconds, below which stuck tracks are
+    /// left to the listener.
+    pub min_stuck_threshold: i64,
+}
+
+impl RecoveryPolicy {
+    /// A policy skipping tracks failing for known reasons or bugs, and
+    /// retrying tracks failing for possibly transient reasons twice before
+    /// skipping them.
+    pub fn retry_then_skip() -> Self {
+        Self {
+            common: RecoveryAction::Skip,
+            suspicious: RecoveryAction::Retry(2),
+            fault: RecoveryAction::Skip,
+            stuck: RecoveryAction::Retry(2),
+            min_stuck_threshold: 0,
+        }
+    }
+
+    /// A policy leaving every failure to the listener.
+    pub fn disabled() -> Self {
+        Self {
+            common: RecoveryAction::Ignore,
+            suspicious: RecoveryAction::Ignore,
+            fault: RecoveryAction::Ignore,
+            stuck: RecoveryAction::Ignore,
+            min_stuck_threshold: 0,
+        }
+    }
+
+    /// The action for an exception of the given severity.
+    pub fn for_exception(&self, severity: Option<ExceptionSeverity>) -> RecoveryAction {
+        match severity {
+            Some(ExceptionSeverity::Common) => self.common,
+            Some(ExceptionSeverity::Suspicious) | None => self.suspicious,
+            Some(ExceptionSeverity::Fault) => self.fault,
+        }
+    }
+
+    /// The action for a track stuck for the given threshold.
+    pub fn for_stuck(&self, threshold_ms: i64) -> RecoveryAction {
+        if threshold_ms < self.min_stuck_threshold {
+            RecoveryAction::Ignore
+        } else {
+            self.stuck
+        }
+    }
+}
+
+impl Default for RecoveryPolicy {
+    /// Leaves every failure to the listener, as [`disabled`] does, so
+    /// recovering is opt-in, such as with [`retry_then_skip`].
+    ///
+    /// [`disabled`]: #method.disabled
+    /// [`retry_then_skip`]: #method.retry_then_skip
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
@@ -0,0 +1,720 @@
+//! A client for a node's REST API.
+
+use cache::TrackCache;
+use hyper::client::{Client, Response};
+use hyper::method::Method;
+use hyper::header::{ContentType, Headers};
+use hyper::net::HttpsConnector;
+use hyper::status::StatusCode;
+use hyper::Url;
+use hyper_native_tls::NativeTlsClient;
+use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::io::Read;
+use std::sync::Arc;
+use nodes::{NodeConfig, Protocol};
+use tls;
+use ::prelude::*;
+
+/// How a `/loadtracks` request was resolved.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LoadType {
+    TrackLoaded,
+    PlaylistLoaded,
+    SearchResult,
+    NoMatches,
+    LoadFailed,
+}
+
+/// Information about a track, as returned by the node.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackInfo {
+    pub identifier: String,
+    pub is_seekable: bool,
+    pub author: String,
+    pub length: u64,
+    pub is_stream: bool,
+    pub position: u64,
+    pub title: String,
+    #[serde(default)]
+    pub uri: Option<String>,
+}
+
+/// A track resolved by the node, along with its encoded form.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct LoadedTrack {
+    /// The base64-encoded track to pass to `AudioPlayer::play`.
+    pub track: String,
+    pub info: TrackInfo,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistInfo {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub selected_track: Option<i64>,
+}
+
+/// The result of a `/loadtracks` request.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadedTracks {
+    pub load_type: LoadType,
+    #[serde(default)]
+    pub playlist_info: PlaylistInfo,
+    #[serde(default)]
+    pub tracks: Vec<LoadedTrack>,
+}
+
+/// A track as returned by v4 nodes.
+#[derive(Deserialize)]
+struct V4Track {
+    encoded: String,
+    info: TrackInfo,
+}
+
+impl From<V4Track> for LoadedTrack {
+    fn from(track: V4Track) -> Self {
+        Self {
+            track: track.encoded,
+            info: track.info,
+        }
+    }
+}
+
+/// The block of IP addresses a route planner rotates through.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct IpBlock {
+    /// The type of the block, either `Inet4Address` or `Inet6Address`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The number of addresses in the block.
+    pub size: String,
+}
+
+/// An address a route planner stopped using after it was rate limited.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailingAddress {
+    pub address: String,
+    /// When the address failed, in milliseconds since the Unix epoch.
+    pub failing_timestamp: u64,
+    /// When the address failed, formatted by the node.
+    pub failing_time: String,
+}
+
+/// The status of a route planner switching addresses on every rate limit.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotatingIpDetails {
+    pub ip_block: IpBlock,
+    pub failing_addresses: Vec<FailingAddress>,
+    /// The number of rotations.
+    pub rotate_index: String,
+    /// The index of the current address in the block.
+    pub ip_index: String,
+    pub current_address: String,
+}
+
+/// The status of a route planner switching addresses on every request.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NanoIpDetails {
+    pub ip_block: IpBlock,
+    pub failing_addresses: Vec<FailingAddress>,
+    /// The index of the current address in the block.
+    pub current_address_index: String,
+}
+
+/// The status of a route planner switching addresses on every request, and
+/// switching blocks on every rate limit.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotatingNanoIpDetails {
+    pub ip_block: IpBlock,
+    pub failing_addresses: Vec<FailingAddress>,
+    /// The index of the current block.
+    pub block_index: String,
+    /// The index of the current address in the block.
+    pub current_address_index: String,
+}
+
+/// The status of a route planner choosing random addresses.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancingIpDetails {
+    pub ip_block: IpBlock,
+    pub failing_addresses: Vec<FailingAddress>,
+}
+
+/// The status of a node's route planner, as returned by
+/// `/routeplanner/status`.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(tag = "class", content = "details")]
+pub enum RoutePlannerStatus {
+    #[serde(rename = "RotatingIpRoutePlanner")]
+    RotatingIp(RotatingIpDetails),
+    #[serde(rename = "NanoIpRoutePlanner")]
+    NanoIp(NanoIpDetails),
+    #[serde(rename = "RotatingNanoIpRoutePlanner")]
+    RotatingNanoIp(RotatingNanoIpDetails),
+    #[serde(rename = "BalancingIpRoutePlanner")]
+    BalancingIp(BalancingIpDetails),
+}
+
+impl RoutePlannerStatus {
+    pub fn ip_block(&self) -> &IpBlock {
+        match *self {
+            RoutePlannerStatus::RotatingIp(ref details) => &details.ip_block,
+            RoutePlannerStatus::NanoIp(ref details) => &details.ip_block,
+            RoutePlannerStatus::RotatingNanoIp(ref details) => &details.ip_block,
+            RoutePlannerStatus::BalancingIp(ref details) => &details.ip_block,
+        }
+    }
+
+    /// The addresses the route planner stopped using.
+    pub fn failing_addresses(&self) -> &[FailingAddress] {
+        match *self {
+            RoutePlannerStatus::RotatingIp(ref details) => &details.failing_addresses,
+            RoutePlannerStatus::NanoIp(ref details) => &details.failing_addresses,
+            RoutePlannerStatus::RotatingNanoIp(ref details) => &details.failing_addresses,
+            RoutePlannerStatus::BalancingIp(ref details) => &details.failing_addresses,
+        }
+    }
+
+    /// Whether the route planner stopped using an address.
+    pub fn is_failing(&self, address: &str) -> bool {
+        self.failing_addresses().iter().any(|failing| failing.address == address)
+    }
+}
+
+/// A client for a single node's REST API.
+///
+/// Clients are cheap to clone and share their connection pool.
+#[derive(Clone)]
+pub struct RestClient {
+    client: Arc<Client>,
+    host: String,
+    password: String,
+    protocol: Protocol,
+    /// Shared by the clones of the client, so setting it applies to all of
+    /// them.
+    cache: Arc<RwLock<Option<TrackCache>>>,
+}
+
+impl RestClient {
+    /// Creates a client for the config's `http_host`, using its TLS settings
+    /// for `https://` hosts.
+    pub fn new(config: &NodeConfig) -> Result<Self> {
+        let connector = NativeTlsClient::from(tls::connector(&config.tls)?);
+        let client = Client::with_connector(HttpsConnector::new(connector));
+
+        Ok(Self {
+            client: Arc::new(client),
+            host: config.http_host.trim_right_matches('/').to_owned(),
+            password: config.password.clone(),
+            protocol: config.protocol,
+            cache: Arc::new(RwLock::new(None)),
+        })
+    }
+
+    /// The host requests are made to.
+    #[inline]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The protocol of the node, which decides the paths requested and how
+    /// responses are read.
+    #[inline]
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// The cache `/loadtracks` results are looked up in first, if any.
+    pub fn cache(&self) -> Option<TrackCache> {
+        self.cache.read().clone()
+    }
+
+    /// Sets the cache `/loadtracks` results are looked up in first and stored
+    /// in, which can be shared with other clients.
+    ///
+    /// The cache is also set on the clones of this client, such as those of
+    /// a node's handles.
+    pub fn set_cache(&self, cache: Option<TrackCache>) {
+        *self.cache.write() = cache;
+    }
+
+    /// Resolves an identifier, such as a URL or a search like
+    /// `ytsearch:query`, into tracks.
+    ///
+    /// The result is taken from and stored in the client's cache under the
+    /// client's protocol, if it has one.
+    pub fn load_tracks(&self, identifier: &str) -> Result<LoadedTracks> {
+        let cache = self.cache();
+
+        if let Some(loaded) = cache.as_ref().and_then(|cache| cache.get(self.protocol, identifier)) {
+            return Ok(loaded);
+        }
+
+        let loaded = self.request_tracks(identifier)?;
+
+        if let Some(cache) = cache {
+            cache.insert(self.protocol, identifier, &loaded);
+        }
+
+        Ok(loaded)
+    }
+
+    fn request_tracks(&self, identifier: &str) -> Result<LoadedTracks> {
+        let json = self.get_json::<Value>("/loadtracks", &[("identifier", identifier)])?;
+
+        if self.protocol == Protocol::V4 {
+            return v4_load_result(json);
+        }
+
+        // Lavalink versions before v3 respond with a bare array of tracks
+        if json.is_array() {
+            let tracks: Vec<LoadedTrack> = serde_json::from_value(json)?;
+            let load_type = if tracks.is_empty() {
+                LoadType::NoMatches
+            } else {
+                LoadType::SearchResult
+            };
+
+            return Ok(LoadedTracks {
+                load_type,
+                playlist_info: PlaylistInfo::default(),
+                tracks,
+            });
+        }
+
+        serde_json::from_value(json).map_err(From::from)
+    }
+
+    /// Decodes the info of a base64-encoded track.
+    pub fn decode_track(&self, track: &str) -> Result<TrackInfo> {
+        match self.protocol {
+            Protocol::V3 => self.get_json("/decodetrack", &[("track", track)]),
+            Protocol::V4 => self.get_json::<V4Track>("/decodetrack", &[("encodedTrack", track)])
+                .map(|track| track.info),
+        }
+    }
+
+    /// Decodes the info of several base64-encoded tracks in one request.
+    pub fn decode_tracks<T: AsRef<str>>(&self, tracks: &[T]) -> Result<Vec<LoadedTrack>> {
+        let tracks = tracks.iter().map(|track| track.as_ref()).collect::<Vec<_>>();
+        let response = self.post("/decodetracks", &json!(tracks))?;
+
+        match self.protocol {
+            Protocol::V3 => serde_json::from_reader(response).map_err(From::from),
+            Protocol::V4 => {
+                let tracks: Vec<V4Track> = serde_json::from_reader(response)?;
+
+                Ok(tracks.into_iter().map(From::from).collect())
+            },
+        }
+    }
+
+    /// Updates a v4 session with the given fields, such as whether it can be
+    /// resumed.
+    pub(crate) fn update_session(&self, session_id: &str, update: &Value) -> Result<()> {
+        let path = format!("/sessions/{}", session_id);
+
+        self.request(Method::Patch, &path, &[], Some(update)).map(|_| ())
+    }
+
+    /// Updates a guild's player in a v4 session with the given fields.
+    pub(crate) fn update_player(&self, session_id: &str, guild_id: u64, update: &Value) -> Result<()> {
+        let path = format!("/sessions/{}/players/{}", session_id, guild_id);
+
+        self.request(Method::Patch, &path, &[], Some(update)).map(|_| ())
+    }
+
+    /// Destroys a guild's player in a v4 session.
+    pub(crate) fn destroy_player(&self, session_id: &str, guild_id: u64) -> Result<()> {
+        let path = format!("/sessions/{}/players/{}", session_id, guild_id);
+
+        self.request(Method::Delete, &path, &[], None).map(|_| ())
+    }
+
+    /// The status of the node's route planner, or `None` if it has none.
+    pub fn route_planner_status(&self) -> Result<Option<RoutePlannerStatus>> {
+        let response = self.get("/routeplanner/status", &[])?;
+        let status = response.status;
+
+        route_planner_result(status, response)
+    }
+
+    /// Makes the node's route planner use a failing address again.
+    pub fn free_address(&self, address: &str) -> Result<()> {
+        self.post("/routeplanner/free/address", &json!({
+            "address": address,
+        })).map(|_| ())
+    }
+
+    /// Makes the node's route planner use every failing address again.
+    pub fn free_all_addresses(&self) -> Result<()> {
+        self.post("/routeplanner/free/all", &json!({})).map(|_| ())
+    }
+
+    pub(crate) fn get_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T> {
+        let response = self.get(path, query)?;
+
+        serde_json::from_reader(response).map_err(From::from)
+    }
+
+    pub(crate) fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<Response> {
+        self.request(Method::Get, path, query, None)
+    }
+
+    pub(crate) fn post(&self, path: &str, body: &Value) -> Result<Response> {
+        self.request(Method::Post, path, &[], Some(body))
+    }
+
+    /// Makes a request with an optional JSON body.
+    ///
+    /// Paths are given without a version, as v4 nodes serve every endpoint
+    /// under `/v4`.
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<&Value>,
+    ) -> Result<Response> {
+        let prefix = match self.protocol {
+            Protocol::V3 => "",
+            Protocol::V4 => "/v4",
+        };
+
+        let mut url = Url::parse(&format!("{}{}{}", self.host, prefix, path))?;
+
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        let body = match body {
+            Some(body) => Some(serde_json::to_string(body)?),
+            None => None,
+        };
+
+        let mut headers = self.headers();
+        let mut request = self.client.request(method, url);
+
+        if let Some(ref body) = body {
+            headers.set(ContentType::json());
+            request = request.body(&body[..]);
+        }
+
+        let response = request.headers(headers).send()?;
+
+        if !response.status.is_success() {
+            return Err(Error::UnsuccessfulRequest(response.status));
+        }
+
+        Ok(response)
+    }
+
+    fn headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Authorization", vec![self.password.clone().into_bytes()]);
+
+        headers
+    }
+}
+
+impl Debug for RestClient {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("RestClient")
+            .field("host", &self.host)
+            .field("protocol", &self.protocol)
+            .field("cache", &*self.cache.read())
+            .finish()
+    }
+}
+
+/// Reads a `/routeplanner/status` response, which has no content or a null
+/// class if the node has no route planner.
+fn route_planner_result<R: Read>(
+    status: StatusCode,
+    body: R,
+) -> Result<Option<RoutePlannerStatus>> {
+    if status == StatusCode::NoContent {
+        return Ok(None);
+    }
+
+    let json: Value = serde_json::from_reader(body)?;
+
+    if json["class"].is_null() {
+        return Ok(None);
+    }
+
+    serde_json::from_value(json).map(Some).map_err(From::from)
+}
+
+/// Reads a v4 `/loadtracks` response, whose data depends on the load type.
+fn v4_load_result(mut json: Value) -> Result<LoadedTracks> {
+    let data = json["data"].take();
+
+    let (load_type, playlist_info, tracks) = match json["loadType"].as_str().unwrap_or_default() {
+        "track" => {
+            let track: V4Track = serde_json::from_value(data)?;
+
+            (LoadType::TrackLoaded, PlaylistInfo::default(), vec![track])
+        },
+        "playlist" => {
+            let mut data = data;
+            let info: PlaylistInfo = serde_json::from_value(data["info"].take())?;
+            let tracks: Vec<V4Track> = serde_json::from_value(data["tracks"].take())?;
+
+            (LoadType::PlaylistLoaded, info, tracks)
+        },
+        "search" => (LoadType::SearchResult, PlaylistInfo::default(), serde_json::from_value(data)?),
+        "empty" => (LoadType::NoMatches, PlaylistInfo::default(), vec![]),
+        _ => {
+            warn!("Err loading tracks: {}", data["message"].as_str().unwrap_or("unknown error"));
+
+            (LoadType::LoadFailed, PlaylistInfo::default(), vec![])
+        },
+    };
+
+    Ok(LoadedTracks {
+        load_type,
+        playlist_info,
+        tracks: tracks.into_iter().map(From::from).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::status::StatusCode;
+    use serde_json::{self, Value};
+    use super::{route_planner_result, v4_load_result, LoadType, RoutePlannerStatus};
+
+    fn track(encoded: &str, title: &str) -> Value {
+        json!({
+            "encoded": encoded,
+            "info": {
+                "identifier": title,
+                "isSeekable": true,
+                "author": "RickAstleyVEVO",
+                "length": 212000,
+                "isStream": false,
+                "position": 0,
+                "title": title,
+                "uri": null,
+                "sourceName": "youtube",
+            },
+            "pluginInfo": {},
+        })
+    }
+
+    #[test]
+    fn reads_v4_tracks() {
+        let loaded = v4_load_result(json!({
+            "loadType": "track",
+            "data": track("encoded", "Never Gonna Give You Up"),
+        })).unwrap();
+
+        assert_eq!(loaded.load_type, LoadType::TrackLoaded);
+        assert_eq!(loaded.tracks.len(), 1);
+        assert_eq!(loaded.tracks[0].track, "encoded");
+        assert_eq!(loaded.tracks[0].info.title, "Never Gonna Give You Up");
+    }
+
+    #[test]
+    fn reads_v4_playlists() {
+        let loaded = v4_load_result(json!({
+            "loadType": "playlist",
+            "data": {
+                "info": {
+                    "name": "Rick Astley",
+                    "selectedTrack": 1,
+                },
+                "pluginInfo": {},
+                "tracks": [track("first", "First"), track("second", "Second")],
+            },
+        })).unwrap();
+
+        assert_eq!(loaded.load_type, LoadType::PlaylistLoaded);
+        assert_eq!(loaded.playlist_info.name, Some("Rick Astley".to_owned()));
+        assert_eq!(loaded.playlist_info.selected_track, Some(1));
+        assert_eq!(loaded.tracks.iter().map(|track| &track.track[..]).collect::<Vec<_>>(), vec![
+            "first",
+            "second",
+        ]);
+    }
+
+    #[test]
+    fn reads_v4_searches_and_empty_results() {
+        let search = v4_load_result(json!({
+            "loadType": "search",
+            "data": [track("first", "First"), track("second", "Second")],
+        })).unwrap();
+
+        assert_eq!(search.load_type, LoadType::SearchResult);
+        assert_eq!(search.tracks.len(), 2);
+
+        let empty = v4_load_result(json!({
+            "loadType": "empty",
+            "data": {},
+        })).unwrap();
+
+        assert_eq!(empty.load_type, LoadType::NoMatches);
+        assert!(empty.tracks.is_empty());
+    }
+
+    #[test]
+    fn reads_v4_errors_as_failed_loads() {
+        let failed = v4_load_result(json!({
+            "loadType": "error",
+            "data": {
+                "message": "The uploader has not made this video available in your country.",
+                "severity": "common",
+                "cause": "com.sedmelluq.discord.lavaplayer.tools.FriendlyException",
+            },
+        })).unwrap();
+
+        assert_eq!(failed.load_type, LoadType::LoadFailed);
+        assert!(failed.tracks.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_v4_tracks() {
+        assert!(v4_load_result(json!({
+            "loadType": "track",
+            "data": {
+                "encoded": "encoded",
+            },
+        })).is_err());
+    }
+
+    fn ip_block() -> Value {
+        json!({
+            "type": "Inet6Address",
+            "size": "1208925819614629174706176",
+        })
+    }
+
+    fn failing(address: &str) -> Value {
+        json!({
+            "address": address,
+            "failingTimestamp": 1573520707545u64,
+            "failingTime": "Mon Nov 11 20:05:07 EET 2019",
+        })
+    }
+
+    fn route_planner(json: Value) -> Option<RoutePlannerStatus> {
+        let body = serde_json::to_vec(&json).unwrap();
+
+        route_planner_result(StatusCode::Ok, &body[..]).unwrap()
+    }
+
+    #[test]
+    fn reads_every_route_planner_class() {
+        let rotating = route_planner(json!({
+            "class": "RotatingIpRoutePlanner",
+            "details": {
+                "ipBlock": ip_block(),
+                "failingAddresses": [failing("/1.0.0.0")],
+                "rotateIndex": "0",
+                "ipIndex": "0",
+                "currentAddress": "/1.0.0.1",
+            },
+        }));
+
+        match rotating {
+            Some(RoutePlannerStatus::RotatingIp(ref details)) => {
+                assert_eq!(details.current_address, "/1.0.0.1");
+                assert_eq!(details.ip_block.kind, "Inet6Address");
+            },
+            other => panic!("expected a rotating route planner, got {:?}", other),
+        }
+
+        let nano = route_planner(json!({
+            "class": "NanoIpRoutePlanner",
+            "details": {
+                "ipBlock": ip_block(),
+                "failingAddresses": [],
+                "currentAddressIndex": "2",
+            },
+        }));
+
+        match nano {
+            Some(RoutePlannerStatus::NanoIp(ref details)) => {
+                assert_eq!(details.current_address_index, "2");
+            },
+            other => panic!("expected a nano route planner, got {:?}", other),
+        }
+
+        let rotating_nano = route_planner(json!({
+            "class": "RotatingNanoIpRoutePlanner",
+            "details": {
+                "ipBlock": ip_block(),
+                "failingAddresses": [],
+                "blockIndex": "1",
+                "currentAddressIndex": "3",
+            },
+        }));
+
+        match rotating_nano {
+            Some(RoutePlannerStatus::RotatingNanoIp(ref details)) => {
+                assert_eq!(details.block_index, "1");
+                assert_eq!(details.current_address_index, "3");
+            },
+            other => panic!("expected a rotating nano route planner, got {:?}", other),
+        }
+
+        let balancing = route_planner(json!({
+            "class": "BalancingIpRoutePlanner",
+            "details": {
+                "ipBlock": ip_block(),
+                "failingAddresses": [],
+            },
+        }));
+
+        match balancing {
+            Some(RoutePlannerStatus::BalancingIp(ref details)) => {
+                assert_eq!(details.ip_block.size, "1208925819614629174706176");
+            },
+            other => panic!("expected a balancing route planner, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nodes_without_a_route_planner_have_no_status() {
+        assert_eq!(route_planner_result(StatusCode::NoContent, &b""[..]).unwrap(), None);
+        assert_eq!(route_planner(json!({
+            "class": null,
+            "details": null,
+        })), None);
+    }
+
+    #[test]
+    fn failing_addresses_are_known() {
+        let status = route_planner(json!({
+            "class": "BalancingIpRoutePlanner",
+            "details": {
+                "ipBlock": ip_block(),
+                "failingAddresses": [failing("/1.0.0.0"), failing("/1.0.0.2")],
+            },
+        })).unwrap();
+
+        assert_eq!(status.failing_addresses().len(), 2);
+        assert!(status.is_failing("/1.0.0.0"));
+        assert!(status.is_failing("/1.0.0.2"));
+        assert!(!status.is_failing("/1.0.0.1"));
+        assert!(!status.is_failing("1.0.0.0"));
+    }
+}
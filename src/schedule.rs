@@ -0,0 +1,185 @@
+//! Timed actions on players, such as sleep timers.
+//!
+//! Actions are scheduled per guild through the [`AudioPlayerManager`], either
+//! after a delay or for when the current track ends.
+//!
+//! [`AudioPlayerManager`]: ../player/struct.AudioPlayerManager.html
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What to do to a guild's player when a scheduled task is due.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ScheduledAction {
+    /// Stop playing, keeping the queue.
+    Stop,
+    /// Pause the player. At the end of a track, the next track is started
+    /// paused.
+    Pause,
+    /// Destroy the player and leave its voice channel.
+    Leave,
+    /// Set the player's volume.
+    Volume(i32),
+}
+
+/// When a scheduled task is due.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ScheduleTrigger {
+    /// After a delay from when the task was scheduled.
+    After(Duration),
+    /// When the node ends the player's current track, before the next one is
+    /// started.
+    ///
+    /// Tracks replaced by another one, such as by skipping or crossfading,
+    /// don't count as ending.
+    TrackEnd,
+}
+
+/// An action scheduled for a guild's player.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ScheduledTask {
+    pub id: u64,
+    pub guild_id: u64,
+    pub action: ScheduledAction,
+    pub trigger: ScheduleTrigger,
+    /// When a delayed task is due.
+    pub due: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct TaskState {
+    next_id: u64,
+    /// The tasks still due, with the ID of the scheduler job of delayed ones.
+    tasks: HashMap<u64, (ScheduledTask, Option<u64>)>,
+}
+
+/// The tasks scheduled on the players of a manager.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScheduledTasks {
+    state: Arc<Mutex<TaskState>>,
+}
+
+impl ScheduledTasks {
+    /// Records a task, returning its ID.
+    pub fn insert(&self, guild_id: u64, action: ScheduledAction, trigger: ScheduleTrigger) -> u64 {
+        let mut state = self.state.lock();
+
+        state.next_id += 1;
+        let id = state.next_id;
+
+        let due = match trigger {
+            ScheduleTrigger::After(delay) => Some(Instant::now() + delay),
+            ScheduleTrigger::TrackEnd => None,
+        };
+
+        state.tasks.insert(id, (ScheduledTask {
+            id,
+            guild_id,
+            action,
+            trigger,
+            due,
+        }, None));
+
+        id
+    }
+
+    /// Records the scheduler job running a delayed task, so it can be
+    /// cancelled.
+    pub fn set_job(&self, id: u64, job: u64) {
+        if let Some(&mut (_, ref mut task_job)) = self.state.lock().tasks.get_mut(&id) {
+            *task_job = Some(job);
+        }
+    }
+
+    /// Removes a task, returning it and its scheduler job if it was still
+    /// due.
+    pub fn remove(&self, id: u64) -> Option<(ScheduledTask, Option<u64>)> {
+        self.state.lock().tasks.remove(&id)
+    }
+
+    /// Removes the tasks of a guild matching a filter.
+    pub fn remove_where<F>(&self, guild_id: u64, filter: F) -> Vec<(ScheduledTask, Option<u64>)>
+        where F: Fn(&ScheduledTask) -> bool {
+        let mut state = self.state.lock();
+
+        let ids = state.tasks
+            .values()
+            .filter(|&&(ref task, _)| task.guild_id == guild_id && filter(task))
+            .map(|&(ref task, _)| task.id)
+            .collect::<Vec<_>>();
+
+        let mut removed = ids
+            .iter()
+            .filter_map(|id| state.tasks.remove(id))
+            .collect::<Vec<_>>();
+
+        removed.sort_by_key(|&(ref task, _)| task.id);
+
+        removed
+    }
+
+    /// The tasks still due for a guild, in the order they were scheduled.
+    pub fn list(&self, guild_id: u64) -> Vec<ScheduledTask> {
+        let mut tasks = self.state.lock()
+            .tasks
+            .values()
+            .filter(|&&(ref task, _)| task.guild_id == guild_id)
+            .map(|&(task, _)| task)
+            .collect::<Vec<_>>();
+
+        tasks.sort_by_key(|task| task.id);
+
+        tasks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn ids(tasks: &[(ScheduledTask, Option<u64>)]) -> Vec<u64> {
+        tasks.iter().map(|&(ref task, _)| task.id).collect()
+    }
+
+    #[test]
+    fn tasks_are_listed_in_the_order_they_were_scheduled() {
+        let tasks = ScheduledTasks::default();
+
+        let first = tasks.insert(7, ScheduledAction::Pause, ScheduleTrigger::TrackEnd);
+        let other = tasks.insert(8, ScheduledAction::Stop, ScheduleTrigger::TrackEnd);
+        let delay = ScheduleTrigger::After(Duration::from_secs(60));
+        let second = tasks.insert(7, ScheduledAction::Volume(50), delay);
+
+        let listed = tasks.list(7);
+
+        assert_eq!(listed.iter().map(|task| task.id).collect::<Vec<_>>(), vec![first, second]);
+        assert!(listed[0].due.is_none());
+        assert!(listed[1].due.is_some());
+        assert_eq!(tasks.list(8)[0].id, other);
+        assert!(tasks.list(9).is_empty());
+    }
+
+    #[test]
+    fn removing_keeps_other_tasks() {
+        let tasks = ScheduledTasks::default();
+        let delay = ScheduleTrigger::After(Duration::from_secs(60));
+
+        let delayed = tasks.insert(7, ScheduledAction::Stop, delay);
+        let first = tasks.insert(7, ScheduledAction::Pause, ScheduleTrigger::TrackEnd);
+        let second = tasks.insert(7, ScheduledAction::Stop, ScheduleTrigger::TrackEnd);
+        tasks.insert(8, ScheduledAction::Stop, ScheduleTrigger::TrackEnd);
+
+        tasks.set_job(delayed, 3);
+
+        let removed = tasks.remove_where(7, |task| task.trigger == ScheduleTrigger::TrackEnd);
+        assert_eq!(ids(&removed), vec![first, second]);
+
+        assert_eq!(tasks.remove(delayed).map(|(_, job)| job), Some(Some(3)));
+        assert!(tasks.remove(delayed).is_none());
+        assert!(tasks.list(7).is_empty());
+        assert_eq!(tasks.list(8).len(), 1);
+    }
+}
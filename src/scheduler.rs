@@ -0,0 +1,200 @@
+//! A single thread running jobs after a delay.
+
+use parking_lot::{Condvar, Mutex};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Weak};
+use std::thread::Builder as ThreadBuilder;
+use std::time::{Duration, Instant};
+
+/// The longest the thread waits before checking whether the scheduler was
+/// dropped.
+const IDLE_WAIT_SECS: u64 = 1;
+
+pub(crate) type Job = Box<FnMut() + Send>;
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    /// The jobs ordered by when they are due.
+    queue: BTreeMap<(Instant, u64), Job>,
+    /// When each queued job is due, by ID.
+    due: HashMap<u64, Instant>,
+    running: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    jobs: Mutex<Jobs>,
+    condvar: Condvar,
+}
+
+/// Runs jobs after a delay on a thread spawned on first use, which exits once
+/// every handle to the scheduler is dropped.
+///
+/// Schedulers are cheap to clone and share their thread.
+#[derive(Clone, Default)]
+pub(crate) struct Scheduler {
+    shared: Arc<Shared>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules a job to run after a delay, returning its ID.
+    pub fn schedule<F: FnMut() + Send + 'static>(&self, delay: Duration, job: F) -> u64 {
+        let mut jobs = self.shared.jobs.lock();
+
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        let at = Instant::now() + delay;
+
+        jobs.queue.insert((at, id), Box::new(job));
+        jobs.due.insert(id, at);
+
+        if !jobs.running {
+            let shared = Arc::downgrade(&self.shared);
+            let builder = ThreadBuilder::new().name("scheduler".into());
+
+            match builder.spawn(move || run(&shared)) {
+                Ok(_) => jobs.running = true,
+                Err(why) => error!("Err spawning scheduler thread: {:?}", why),
+            }
+        }
+
+        self.shared.condvar.notify_one();
+
+        id
+    }
+
+    /// Cancels a job, returning whether it was still queued.
+    pub fn cancel(&self, id: u64) -> bool {
+        let mut jobs = self.shared.jobs.lock();
+
+        match jobs.due.remove(&id) {
+            Some(at) => jobs.queue.remove(&(at, id)).is_some(),
+            None => false,
+        }
+    }
+
+    /// When a queued job is due.
+    pub fn due(&self, id: u64) -> Option<Instant> {
+        self.shared.jobs.lock().due.get(&id).cloned()
+    }
+}
+
+fn run(shared: &Weak<Shared>) {
+    let idle = Duration::from_secs(IDLE_WAIT_SECS);
+
+    loop {
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        let job = {
+            let mut jobs = shared.jobs.lock();
+            let now = Instant::now();
+            let next = jobs.queue.keys().next().cloned();
+
+            match next {
+                Some((at, id)) if at <= now => {
+                    jobs.due.remove(&id);
+                    jobs.queue.remove(&(at, id))
+                },
+                Some((at, _)) => {
+                    shared.condvar.wait_for(&mut jobs, (at - now).min(idle));
+
+                    None
+                },
+                None => {
+                    shared.condvar.wait_for(&mut jobs, idle);
+
+                    None
+                },
+            }
+        };
+
+        // run outside the lock so jobs can schedule more jobs
+        if let Some(mut job) = job {
+            job();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use super::Scheduler;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn jobs_run_in_the_order_they_are_due() {
+        let scheduler = Scheduler::new();
+        let (tx, rx) = mpsc::channel();
+
+        for &delay in &[60, 20, 40] {
+            let tx = tx.clone();
+
+            scheduler.schedule(millis(delay), move || {
+                let _ = tx.send(delay);
+            });
+        }
+
+        let ran = (0..3)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ran, vec![20, 40, 60]);
+    }
+
+    #[test]
+    fn cancelled_jobs_never_run() {
+        let scheduler = Scheduler::new();
+        let ran = Arc::new(Mutex::new(Vec::new()));
+
+        let ids = (0..2).map(|job| {
+            let ran = Arc::clone(&ran);
+
+            scheduler.schedule(millis(50), move || ran.lock().push(job))
+        }).collect::<Vec<_>>();
+
+        assert!(scheduler.due(ids[0]).is_some());
+        assert!(scheduler.cancel(ids[0]));
+        assert!(!scheduler.cancel(ids[0]));
+        assert!(scheduler.due(ids[0]).is_none());
+
+        thread::sleep(millis(200));
+
+        assert_eq!(*ran.lock(), vec![1]);
+
+        // jobs that already ran can't be cancelled
+        assert!(!scheduler.cancel(ids[1]));
+    }
+
+    #[test]
+    fn jobs_can_schedule_more_jobs() {
+        let scheduler = Scheduler::new();
+        let (tx, rx) = mpsc::channel();
+
+        let inner = scheduler.clone();
+
+        scheduler.schedule(millis(10), move || {
+            let tx = tx.clone();
+
+            inner.schedule(millis(10), move || {
+                let _ = tx.send(());
+            });
+        });
+
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
@@ -0,0 +1,375 @@
+//! Searching for tracks and presenting the results as choices.
+//!
+//! [`NodeManager::search`] resolves a query on the least loaded node, using
+//! the query as is when it is a URL and otherwise prefixing it for the
+//! [`SearchSource`]. Search results are ranked by how well they match the
+//! query and limited, and [`paginate`] splits them into pages ready for a
+//! Discord select menu.
+//!
+//! [`NodeManager::search`]: ../nodes/struct.NodeManager.html#method.search
+//! [`SearchSource`]: enum.SearchSource.html
+//! [`paginate`]: fn.paginate.html
+
+use hyper::Url;
+use rest::{LoadType, LoadedTrack, LoadedTracks};
+
+/// The most tracks a search returns by default, which is also the most
+/// options a Discord select menu can have.
+pub const DEFAULT_SEARCH_LIMIT: usize = 25;
+
+/// The most options a Discord select menu can have.
+pub const MAX_SELECT_OPTIONS: usize = 25;
+
+/// The longest label, description or value of a select menu option, in
+/// characters.
+const MAX_OPTION_LENGTH: usize = 100;
+
+/// Where a query is searched for.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SearchSource {
+    YouTube,
+    YouTubeMusic,
+    SoundCloud,
+    /// The query is loaded as is, such as a URL or a source specific
+    /// identifier.
+    Url,
+}
+
+impl SearchSource {
+    /// The prefix the node searches the source with, if any.
+    pub fn prefix(&self) -> Option<&'static str> {
+        match *self {
+            SearchSource::YouTube => Some("ytsearch:"),
+            SearchSource::YouTubeMusic => Some("ytmsearch:"),
+            SearchSource::SoundCloud => Some("scsearch:"),
+            SearchSource::Url => None,
+        }
+    }
+
+    /// The identifier to load for a query, which is the query itself when it
+    /// is a URL.
+    pub fn identifier(&self, query: &str) -> String {
+        let query = query.trim();
+
+        match self.prefix() {
+            Some(prefix) if !is_url(query) => format!("{}{}", prefix, query),
+            _ => query.to_owned(),
+        }
+    }
+}
+
+impl Default for SearchSource {
+    fn default() -> Self {
+        SearchSource::YouTube
+    }
+}
+
+/// Whether a query is an HTTP or HTTPS URL.
+pub fn is_url(query: &str) -> bool {
+    match Url::parse(query.trim()) {
+        Ok(url) => (url.scheme() == "http" || url.scheme() == "https") && url.host().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Ranks search results by how well they match the query and keeps up to
+/// `limit` of them.
+///
+/// Only search results are ranked and limited, so that loaded tracks and
+/// playlists are kept whole and in order.
+pub fn rank(query: &str, loaded: &mut LoadedTracks, limit: usize) {
+    if loaded.load_type != LoadType::SearchResult {
+        return;
+    }
+
+    let words = query
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+
+    // the sort is stable, so ties keep the node's order
+    loaded.tracks.sort_by_key(|track| -score(&words, track));
+    loaded.tracks.truncate(limit);
+}
+
+/// Scores a track by the query words in its title and author, preferring
+/// tracks over streams.
+fn score(words: &[String], track: &LoadedTrack) -> i64 {
+    let title = track.info.title.to_lowercase();
+    let author = track.info.author.to_lowercase();
+
+    let mut score = 0;
+
+    for word in words {
+        if title.contains(word.as_str()) {
+            score += 2;
+        }
+
+        if author.contains(word.as_str()) {
+            score += 1;
+        }
+    }
+
+    if track.info.is_stream {
+        score -= 1;
+    }
+
+    score
+}
+
+/// An option of a Discord select menu.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SelectOption {
+    /// The track's title.
+    pub label: String,
+    /// The track's author and length.
+    pub description: String,
+    /// The index of the track in the results.
+    pub value: String,
+}
+
+/// A page of results as select menu options.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SelectPage {
+    /// The index of the page, from 0.
+    pub page: usize,
+    /// The number of pages.
+    pub pages: usize,
+    pub options: Vec<SelectOption>,
+}
+
+/// Splits tracks into pages of select menu options, with up to `per_page`
+/// options each.
+///
+/// `per_page` is kept between 1 and [`MAX_SELECT_OPTIONS`]. The value of each
+/// option is the index of its track, to look the track up once chosen.
+///
+/// [`MAX_SELECT_OPTIONS`]: constant.MAX_SELECT_OPTIONS.html
+pub fn paginate(tracks: &[LoadedTrack], per_page: usize) -> Vec<SelectPage> {
+    let per_page = per_page.max(1).min(MAX_SELECT_OPTIONS);
+    let pages = (tracks.len() + per_page - 1) / per_page;
+
+    tracks
+        .chunks(per_page)
+        .enumerate()
+        .map(|(page, chunk)| SelectPage {
+            page,
+            pages,
+            options: chunk
+                .iter()
+                .enumerate()
+                .map(|(i, track)| select_option(page * per_page + i, track))
+                .collect(),
+        })
+        .collect()
+}
+
+fn select_option(index: usize, track: &LoadedTrack) -> SelectOption {
+    let length = if track.info.is_stream {
+        "live".to_owned()
+    } else {
+        format_length(track.info.length)
+    };
+
+    SelectOption {
+        label: truncate(&track.info.title),
+        description: truncate(&format!("{} - {}", track.info.author, length)),
+        value: index.to_string(),
+    }
+}
+
+/// Formats a length in milliseconds as `h:mm:ss` or `m:ss`.
+pub fn format_length(length: u64) -> String {
+    let seconds = length / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Shortens text to the longest an option's fields can be, ending it with an
+/// ellipsis if it was cut.
+fn truncate(text: &str) -> String {
+    if text.chars().count() <= MAX_OPTION_LENGTH {
+        return text.to_owned();
+    }
+
+    let mut truncated = text.chars().take(MAX_OPTION_LENGTH - 1).collect::<String>();
+    truncated.push('…');
+
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use rest::{LoadType, LoadedTrack, LoadedTracks, PlaylistInfo, TrackInfo};
+    use super::*;
+
+    fn track(title: &str, author: &str, is_stream: bool) -> LoadedTrack {
+        LoadedTrack {
+            track: format!("encoded {}", title),
+            info: TrackInfo {
+                identifier: title.to_owned(),
+                is_seekable: !is_stream,
+                author: author.to_owned(),
+                length: 212_000,
+                is_stream,
+                position: 0,
+                title: title.to_owned(),
+                uri: None,
+            },
+        }
+    }
+
+    fn loaded(load_type: LoadType, tracks: Vec<LoadedTrack>) -> LoadedTracks {
+        LoadedTracks {
+            load_type,
+            playlist_info: PlaylistInfo::default(),
+            tracks,
+        }
+    }
+
+    fn titles(loaded: &LoadedTracks) -> Vec<&str> {
+        loaded.tracks.iter().map(|track| &track.info.title[..]).collect()
+    }
+
+    #[test]
+    fn detects_urls() {
+        assert!(is_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(is_url("  http://example.com/track.mp3 "));
+        assert!(!is_url("never gonna give you up"));
+        assert!(!is_url("ftp://example.com/track.mp3"));
+        assert!(!is_url("ytsearch:never gonna"));
+        assert!(!is_url(""));
+
+        assert_eq!(SearchSource::YouTube.identifier(" never gonna "), "ytsearch:never gonna");
+        assert_eq!(SearchSource::SoundCloud.identifier("never gonna"), "scsearch:never gonna");
+        assert_eq!(
+            SearchSource::YouTubeMusic.identifier("https://example.com/track.mp3"),
+            "https://example.com/track.mp3",
+        );
+        assert_eq!(SearchSource::Url.identifier("never gonna"), "never gonna");
+    }
+
+    #[test]
+    fn ranks_search_results_by_match() {
+        let mut results = loaded(LoadType::SearchResult, vec![
+            track("Something else", "Someone", false),
+            track("Never Gonna Give You Up (live)", "Rick Astley", true),
+            track("Together Forever", "Rick Astley", false),
+            track("Never Gonna Give You Up", "Rick Astley", false),
+        ]);
+
+        rank("never gonna rick", &mut results, 10);
+
+        assert_eq!(titles(&results), vec![
+            "Never Gonna Give You Up",
+            "Never Gonna Give You Up (live)",
+            "Together Forever",
+            "Something else",
+        ]);
+    }
+
+    #[test]
+    fn keeps_ties_in_order_and_limits_results() {
+        let mut results = loaded(LoadType::SearchResult, vec![
+            track("First", "Nobody", false),
+            track("Second", "Nobody", false),
+            track("Third match", "Nobody", false),
+            track("Fourth", "Nobody", false),
+        ]);
+
+        rank("match", &mut results, 3);
+
+        assert_eq!(titles(&results), vec!["Third match", "First", "Second"]);
+
+        rank("match", &mut results, 0);
+
+        assert!(results.tracks.is_empty());
+    }
+
+    #[test]
+    fn leaves_playlists_whole() {
+        let mut playlist = loaded(LoadType::PlaylistLoaded, vec![
+            track("First", "Nobody", false),
+            track("Second match", "Nobody", false),
+        ]);
+
+        rank("match", &mut playlist, 1);
+
+        assert_eq!(titles(&playlist), vec!["First", "Second match"]);
+    }
+
+    #[test]
+    fn paginates_at_page_boundaries() {
+        let tracks = (0..5)
+            .map(|i| track(&format!("Track {}", i), "Nobody", false))
+            .collect::<Vec<_>>();
+
+        let pages = paginate(&tracks, 2);
+
+        assert_eq!(pages.len(), 3);
+        assert!(pages.iter().all(|page| page.pages == 3));
+        assert_eq!(pages[0].options.len(), 2);
+        assert_eq!(pages[2].options.len(), 1);
+        assert_eq!(pages[2].page, 2);
+        assert_eq!(pages[2].options[0].value, "4");
+
+        let pages = paginate(&tracks[..4], 2);
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].options.len(), 2);
+        assert_eq!(pages[1].options[1].value, "3");
+    }
+
+    #[test]
+    fn paginates_an_empty_list() {
+        assert!(paginate(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn keeps_pages_within_select_menu_limits() {
+        let tracks = (0..30)
+            .map(|i| track(&format!("Track {}", i), "Nobody", false))
+            .collect::<Vec<_>>();
+
+        let pages = paginate(&tracks, 100);
+
+        assert_eq!(pages[0].options.len(), MAX_SELECT_OPTIONS);
+        assert_eq!(pages[1].options.len(), 5);
+
+        assert_eq!(paginate(&tracks[..3], 0).len(), 3);
+    }
+
+    #[test]
+    fn describes_options() {
+        let pages = paginate(&[
+            track("Never Gonna Give You Up", "Rick Astley", false),
+            track("Lofi radio", "Lofi Girl", true),
+        ], 25);
+
+        let options = &pages[0].options;
+
+        assert_eq!(options[0].label, "Never Gonna Give You Up");
+        assert_eq!(options[0].description, "Rick Astley - 3:32");
+        assert_eq!(options[1].description, "Lofi Girl - live");
+        assert_eq!(format_length(3_723_000), "1:02:03");
+    }
+
+    #[test]
+    fn truncates_multibyte_titles_at_the_option_length() {
+        let exact = "é".repeat(100);
+        let long = "日本語".repeat(40);
+
+        assert_eq!(truncate(&exact), exact);
+
+        let truncated = truncate(&long);
+
+        assert_eq!(truncated.chars().count(), 100);
+        assert!(truncated.ends_with('…'));
+        assert!(truncated.starts_with(&long.chars().take(99).collect::<String>()[..]));
+    }
+}
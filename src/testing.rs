@@ -0,0 +1,616 @@
+//! An in-process mock Lavalink server for testing bots offline.
+//!
+//! The mock accepts websocket connections from [`Node::connect`], checking the
+//! `Authorization`, `User-Id`, and `Num-Shards` headers against its
+//! configuration, and records every op sent to it. Tests can then script the
+//! stats, `playerUpdate`, and event frames sent back to connected clients.
+//!
+//! A small HTTP server is started alongside the websocket server to serve
+//! scripted `/loadtracks`, `/decodetrack` and `/decodetracks` responses, or a
+//! scripted error status for the decoding endpoints. Updates of v4 sessions
+//! and their players are recorded and accepted, while the `ready` op opening
+//! a session is scripted like any other frame.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! use serenity_lavalink::testing::MockLavalink;
+//!
+//! let mock = MockLavalink::start("password", "1", 1)?;
+//! manager.add_node(&mock.node_config()?)?;
+//!
+//! mock.wait_for_connection(Duration::from_secs(5));
+//! mock.send_stats(0, 0, 0.1);
+//! ```
+//!
+//! [`Node::connect`]: ../nodes/struct.Node.html#method.connect
+
+use hyper::header::Headers;
+use hyper::server::{Listening, Request, Response, Server as HttpServer};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use nodes::NodeConfig;
+use parking_lot::{Condvar, Mutex};
+use percent_encoding::percent_decode;
+use serde_json;
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::Builder as ThreadBuilder;
+use std::time::{Duration, Instant};
+use websocket::sender::Writer as WebSocketWriter;
+use websocket::sync::Server as WebSocketServer;
+use websocket::OwnedMessage;
+use ::prelude::*;
+
+type ClientWriter = Arc<Mutex<WebSocketWriter<TcpStream>>>;
+type ClientWriters = Arc<Mutex<Vec<ClientWriter>>>;
+
+/// A websocket handshake received by the mock, whether accepted or not.
+#[derive(Clone, Debug)]
+pub struct RecordedHandshake {
+    pub path: String,
+    pub headers: Headers,
+}
+
+impl RecordedHandshake {
+    /// The value of a header sent with the handshake, if any.
+    pub fn header(&self, name: &str) -> Option<String> {
+        header_str(&self.headers, name)
+    }
+}
+
+/// An HTTP request received by the mock's REST server.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub authorization: Option<String>,
+    /// The JSON body of the request, if it had one.
+    pub body: Option<Value>,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    connections: usize,
+    rejected: usize,
+    handshakes: Vec<RecordedHandshake>,
+    ops: Vec<Value>,
+    requests: Vec<RecordedRequest>,
+}
+
+struct Shared {
+    password: String,
+    user_id: String,
+    num_shards: u64,
+    answer_pings: AtomicBool,
+    closing: AtomicBool,
+    recorded: Mutex<Recorded>,
+    changed: Condvar,
+    load_tracks: Mutex<HashMap<String, Value>>,
+    decoded_tracks: Mutex<HashMap<String, Value>>,
+    decode_status: Mutex<Option<StatusCode>>,
+}
+
+impl Shared {
+    fn record<F: FnOnce(&mut Recorded)>(&self, f: F) {
+        f(&mut self.recorded.lock());
+        self.changed.notify_all();
+    }
+
+    fn check_headers(&self, headers: &Headers) -> bool {
+        header_str(headers, "Authorization") == Some(self.password.clone())
+            && header_str(headers, "User-Id") == Some(self.user_id.clone())
+            && header_str(headers, "Num-Shards") == Some(self.num_shards.to_string())
+    }
+}
+
+/// A mock Lavalink server running on local ports.
+///
+/// The servers are shut down when the mock is dropped.
+pub struct MockLavalink {
+    websocket_addr: SocketAddr,
+    http_addr: SocketAddr,
+    http: Option<Listening>,
+    shared: Arc<Shared>,
+    writers: ClientWriters,
+}
+
+impl MockLavalink {
+    /// Starts a mock server expecting the given credentials, binding the
+    /// websocket and HTTP servers to free local ports.
+    pub fn start(password: &str, user_id: &str, num_shards: u64) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            password: password.to_owned(),
+            user_id: user_id.to_owned(),
+            num_shards,
+            answer_pings: AtomicBool::new(true),
+            closing: AtomicBool::new(false),
+            recorded: Mutex::new(Recorded::default()),
+            changed: Condvar::new(),
+            load_tracks: Mutex::new(HashMap::new()),
+            decoded_tracks: Mutex::new(HashMap::new()),
+            decode_status: Mutex::new(None),
+        });
+        let writers: ClientWriters = Arc::new(Mutex::new(Vec::new()));
+
+        let mut server = WebSocketServer::bind("127.0.0.1:0")?;
+        let websocket_addr = server.local_addr()?;
+
+        let accept_shared = Arc::clone(&shared);
+        let accept_writers = Arc::clone(&writers);
+        ThreadBuilder::new()
+            .name("mock lavalink accept loop".into())
+            .spawn(move || loop {
+                let upgrade = match server.accept() {
+                    Ok(upgrade) => upgrade,
+                    Err(_) => {
+                        if accept_shared.closing.load(Ordering::SeqCst) {
+                            return;
+                        }
+
+                        continue;
+                    },
+                };
+
+                if accept_shared.closing.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                let handshake = RecordedHandshake {
+                    path: match upgrade.request.subject.1 {
+                        RequestUri::AbsolutePath(ref path) => path.clone(),
+                        ref other => other.to_string(),
+                    },
+                    headers: upgrade.request.headers.clone(),
+                };
+                accept_shared.record(|recorded| recorded.handshakes.push(handshake));
+
+                if !accept_shared.check_headers(&upgrade.request.headers) {
+                    warn!("Mock lavalink rejecting connection with invalid headers");
+                    accept_shared.record(|recorded| recorded.rejected += 1);
+                    let _ = upgrade.reject();
+
+                    continue;
+                }
+
+                let client = match upgrade.accept() {
+                    Ok(client) => client,
+                    Err((_, why)) => {
+                        error!("Mock lavalink err accepting connection: {:?}", why);
+
+                        continue;
+                    },
+                };
+
+                let (mut receiver, sender) = match client.split() {
+                    Ok(split) => split,
+                    Err(why) => {
+                        error!("Mock lavalink err splitting client: {:?}", why);
+
+                        continue;
+                    },
+                };
+
+                let sender = Arc::new(Mutex::new(sender));
+                accept_writers.lock().push(Arc::clone(&sender));
+                accept_shared.record(|recorded| recorded.connections += 1);
+
+                let recv_shared = Arc::clone(&accept_shared);
+                let _ = ThreadBuilder::new()
+                    .name("mock lavalink recv loop".into())
+                    .spawn(move || loop {
+                        let data = match receiver.recv_message() {
+                            Ok(OwnedMessage::Text(data)) => data.into_bytes(),
+                            Ok(OwnedMessage::Binary(data)) => data,
+                            Ok(OwnedMessage::Ping(data)) => {
+                                if recv_shared.answer_pings.load(Ordering::SeqCst) {
+                                    let _ = sender.lock().send_message(&OwnedMessage::Pong(data));
+                                }
+
+                                continue;
+                            },
+                            Ok(OwnedMessage::Close(_)) | Err(_) => return,
+                            Ok(_) => continue,
+                        };
+
+                        match serde_json::from_slice::<Value>(&data) {
+                            Ok(op) => recv_shared.record(|recorded| recorded.ops.push(op)),
+                            Err(why) => warn!("Mock lavalink received invalid JSON: {:?}", why),
+                        }
+                    });
+            })?;
+
+        let http_shared = Arc::clone(&shared);
+        let mut http_server = HttpServer::http("127.0.0.1:0")?;
+        let http_addr = http_server.local_addr()?;
+        let http = http_server.handle(move |req: Request, res: Response| {
+            handle_http(&http_shared, req, res);
+        })?;
+
+        Ok(Self {
+            websocket_addr,
+            http_addr,
+            http: Some(http),
+            shared,
+            writers,
+        })
+    }
+
+    /// A node config that connects to this mock with valid credentials.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidConfig`] if the mock was started with
+    /// credentials that would not pass config validation.
+    ///
+    /// [`Error::InvalidConfig`]: ../enum.Error.html#variant.InvalidConfig
+    pub fn node_config(&self) -> Result<NodeConfig> {
+        NodeConfig::builder()
+            .http_host(self.http_host())
+            .websocket_host(self.websocket_host())
+            .user_id(self.shared.user_id.clone())
+            .password(self.shared.password.clone())
+            .num_shards(self.shared.num_shards)
+            .build()
+    }
+
+    /// The `ws://` URL of the websocket server.
+    pub fn websocket_host(&self) -> String {
+        format!("ws://{}", self.websocket_addr)
+    }
+
+    /// The `http://` URL of the REST server.
+    pub fn http_host(&self) -> String {
+        format!("http://{}", self.http_addr)
+    }
+
+    /// The number of accepted websocket connections.
+    pub fn connections(&self) -> usize {
+        self.shared.recorded.lock().connections
+    }
+
+    /// The number of websocket connections rejected due to invalid headers.
+    pub fn rejected(&self) -> usize {
+        self.shared.recorded.lock().rejected
+    }
+
+    /// All websocket handshakes received so far, including rejected ones, in
+    /// order.
+    pub fn handshakes(&self) -> Vec<RecordedHandshake> {
+        self.shared.recorded.lock().handshakes.clone()
+    }
+
+    /// All ops received from clients so far, in order.
+    pub fn received_ops(&self) -> Vec<Value> {
+        self.shared.recorded.lock().ops.clone()
+    }
+
+    /// All requests made to the REST server so far, in order.
+    pub fn received_requests(&self) -> Vec<RecordedRequest> {
+        self.shared.recorded.lock().requests.clone()
+    }
+
+    /// Clears the recorded handshakes, ops and requests.
+    pub fn clear(&self) {
+        let mut recorded = self.shared.recorded.lock();
+        recorded.handshakes.clear();
+        recorded.ops.clear();
+        recorded.requests.clear();
+    }
+
+    /// Waits until at least one client has connected.
+    ///
+    /// Returns whether a client connected before the timeout elapsed.
+    pub fn wait_for_connection(&self, timeout: Duration) -> bool {
+        self.wait_until(timeout, |recorded| recorded.connections > 0)
+    }
+
+    /// Waits until an op with the given name has been received, returning the
+    /// first such op.
+    pub fn wait_for_op(&self, op: &str, timeout: Duration) -> Option<Value> {
+        let mut found = None;
+
+        self.wait_until(timeout, |recorded| {
+            found = recorded.ops.iter().find(|value| value["op"] == op).cloned();
+
+            found.is_some()
+        });
+
+        found
+    }
+
+    fn wait_until<F>(&self, timeout: Duration, mut done: F) -> bool
+        where F: FnMut(&Recorded) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut recorded = self.shared.recorded.lock();
+
+        loop {
+            if done(&recorded) {
+                return true;
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                return false;
+            }
+
+            let _ = self.shared.changed.wait_for(&mut recorded, deadline - now);
+        }
+    }
+
+    /// Sets the response served for `/loadtracks` with the given identifier.
+    ///
+    /// Unknown identifiers are answered with an empty `NO_MATCHES` result.
+    pub fn set_load_tracks(&self, identifier: &str, response: Value) {
+        self.shared.load_tracks.lock().insert(identifier.to_owned(), response);
+    }
+
+    /// Sets the info `/decodetrack` and `/decodetracks` decode a track into.
+    ///
+    /// Unknown tracks are answered with `400 Bad Request`, like Lavalink does
+    /// for tracks it can't decode.
+    pub fn set_decoded_track(&self, track: &str, info: Value) {
+        self.shared.decoded_tracks.lock().insert(track.to_owned(), info);
+    }
+
+    /// Sets the status `/decodetrack` and `/decodetracks` are answered with
+    /// instead of decoding, such as `404 Not Found` for a version without the
+    /// endpoints. `None` decodes tracks again.
+    pub fn set_decode_status(&self, status: Option<StatusCode>) {
+        *self.shared.decode_status.lock() = status;
+    }
+
+    /// Sends a raw frame to every connected client.
+    pub fn send_frame(&self, frame: &Value) -> Result<()> {
+        let message = OwnedMessage::Text(serde_json::to_string(frame)?);
+        let writers = self.writers.lock();
+
+        for writer in writers.iter() {
+            writer.lock().send_message(&message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a stats frame to every connected client.
+    pub fn send_stats(&self, players: i32, playing_players: i32, system_load: f64) -> Result<()> {
+        self.send_frame(&json!({
+            "op": "stats",
+            "players": players,
+            "playingPlayers": playing_players,
+            "uptime": 0,
+            "memory": {
+                "free": 0,
+                "used": 0,
+                "allocated": 0,
+                "reservable": 0,
+            },
+            "cpu": {
+                "cores": 1,
+                "systemLoad": system_load,
+                "lavalinkLoad": system_load,
+            },
+        }))
+    }
+
+    /// Sends a `playerUpdate` frame to every connected client.
+    pub fn send_player_update(&self, guild_id: u64, time: i64, position: i64) -> Result<()> {
+        self.send_frame(&json!({
+            "op": "playerUpdate",
+            "guildId": guild_id.to_string(),
+            "state": {
+                "time": time,
+                "position": position,
+            },
+        }))
+    }
+
+    /// Sends a `TrackEndEvent` to every connected client.
+    pub fn send_track_end(&self, guild_id: u64, track: &str, reason: &str) -> Result<()> {
+        self.send_frame(&json!({
+            "op": "event",
+            "type": "TrackEndEvent",
+            "guildId": guild_id.to_string(),
+            "track": track,
+            "reason": reason,
+        }))
+    }
+
+    /// Sends a `TrackExceptionEvent` to every connected client.
+    pub fn send_track_exception(
+        &self,
+        guild_id: u64,
+        track: &str,
+        message: &str,
+        severity: &str,
+    ) -> Result<()> {
+        self.send_frame(&json!({
+            "op": "event",
+            "type": "TrackExceptionEvent",
+            "guildId": guild_id.to_string(),
+            "track": track,
+            "error": message,
+            "exception": {
+                "message": message,
+                "severity": severity,
+                "cause": message,
+            },
+        }))
+    }
+
+    /// Sends a `TrackStuckEvent` to every connected client.
+    pub fn send_track_stuck(&self, guild_id: u64, track: &str, threshold_ms: i64) -> Result<()> {
+        self.send_frame(&json!({
+            "op": "event",
+            "type": "TrackStuckEvent",
+            "guildId": guild_id.to_string(),
+            "track": track,
+            "thresholdMs": threshold_ms,
+        }))
+    }
+
+    /// Closes the connections to every connected client.
+    pub fn disconnect_all(&self) {
+        let mut writers = self.writers.lock();
+
+        for writer in writers.iter() {
+            let _ = writer.lock().send_message(&OwnedMessage::Close(None));
+        }
+
+        writers.clear();
+    }
+
+    /// Sets whether the mock answers websocket pings, which is useful for
+    /// testing dead node detection.
+    ///
+    /// Pings are answered by default.
+    pub fn set_answer_pings(&self, answer: bool) {
+        self.shared.answer_pings.store(answer, Ordering::SeqCst);
+    }
+}
+
+impl Drop for MockLavalink {
+    fn drop(&mut self) {
+        self.shared.closing.store(true, Ordering::SeqCst);
+        self.disconnect_all();
+
+        // wake the accept loop so that it notices the mock is closing
+        let _ = TcpStream::connect(self.websocket_addr);
+
+        if let Some(mut http) = self.http.take() {
+            let _ = http.close();
+        }
+    }
+}
+
+fn handle_http(shared: &Shared, mut req: Request, res: Response) {
+    let path = match req.uri {
+        RequestUri::AbsolutePath(ref path) => path.clone(),
+        _ => String::new(),
+    };
+    let method = req.method.to_string();
+    let authorization = header_str(&req.headers, "Authorization");
+
+    let mut raw = String::new();
+    let body = match req.read_to_string(&mut raw) {
+        Ok(_) if !raw.is_empty() => serde_json::from_str::<Value>(&raw).ok(),
+        _ => None,
+    };
+
+    shared.record(|recorded| recorded.requests.push(RecordedRequest {
+        method: method.clone(),
+        path: path.clone(),
+        authorization: authorization.clone(),
+        body: body.clone(),
+    }));
+
+    if authorization.as_ref() != Some(&shared.password) {
+        respond(res, StatusCode::Unauthorized, None);
+
+        return;
+    }
+
+    let mut parts = path.splitn(2, '?');
+    let route = parts.next().unwrap_or("");
+    let query = parts.next().unwrap_or("");
+
+    match route {
+        "/loadtracks" => {
+            let identifier = query_param(query, "identifier").unwrap_or_default();
+            let body = shared.load_tracks
+                .lock()
+                .get(&identifier)
+                .cloned()
+                .unwrap_or_else(|| json!({
+                    "loadType": "NO_MATCHES",
+                    "playlistInfo": {},
+                    "tracks": [],
+                }));
+
+            respond(res, StatusCode::Ok, Some(&body));
+        },
+        "/decodetrack" | "/decodetracks" => {
+            let status = *shared.decode_status.lock();
+
+            if let Some(status) = status {
+                respond(res, status, None);
+
+                return;
+            }
+
+            let tracks = if route == "/decodetrack" {
+                query_param(query, "track")
+                    .or_else(|| query_param(query, "encodedTrack"))
+                    .map(|track| vec![track])
+            } else {
+                body.and_then(|body| serde_json::from_value::<Vec<String>>(body).ok())
+            };
+
+            let decoded = {
+                let infos = shared.decoded_tracks.lock();
+
+                tracks.and_then(|tracks| {
+                    tracks
+                        .into_iter()
+                        .map(|track| infos.get(&track).map(|info| json!({
+                            "track": track,
+                            "info": info,
+                        })))
+                        .collect::<Option<Vec<_>>>()
+                })
+            };
+
+            match decoded {
+                Some(ref decoded) if route == "/decodetrack" => {
+                    respond(res, StatusCode::Ok, Some(&decoded[0]["info"]));
+                },
+                Some(decoded) => respond(res, StatusCode::Ok, Some(&json!(decoded))),
+                None => respond(res, StatusCode::BadRequest, None),
+            }
+        },
+        _ if route.starts_with("/v4/sessions/") => respond(res, StatusCode::Ok, Some(&json!({}))),
+        _ => respond(res, StatusCode::NotFound, None),
+    }
+}
+
+fn respond(mut res: Response, status: StatusCode, body: Option<&Value>) {
+    *res.status_mut() = status;
+
+    match body {
+        Some(body) => {
+            res.headers_mut().set_raw("Content-Type", vec![b"application/json".to_vec()]);
+            let _ = res.send(body.to_string().as_bytes());
+        },
+        None => {
+            let _ = res.send(b"");
+        },
+    }
+}
+
+fn header_str(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key == name => Some(value.to_owned()),
+                _ => None,
+            }
+        })
+        .next()
+        .map(|value| {
+            let value = value.replace('+', " ");
+
+            percent_decode(value.as_bytes()).decode_utf8_lossy().into_owned()
+        })
+}
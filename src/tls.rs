@@ -0,0 +1,117 @@
+//! Helpers for connecting to nodes over TLS.
+
+use native_tls::{Certificate, TlsConnector, TlsStream};
+use nodes::TlsConfig;
+use parking_lot::{Mutex, MutexGuard};
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ::prelude::*;
+
+/// How long a read on a shared TLS stream may hold the lock before giving
+/// writers a turn.
+const POLL_INTERVAL_MS: u64 = 50;
+
+/// Builds a TLS connector trusting the configured root certificates.
+pub(crate) fn connector(config: &TlsConfig) -> Result<TlsConnector> {
+    let mut builder = TlsConnector::builder();
+
+    for path in &config.ca_certificates {
+        let mut pem = Vec::new();
+        File::open(path)?.read_to_end(&mut pem)?;
+
+        builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+
+    if config.accept_invalid_certs {
+        warn!("Accepting invalid TLS certificates and hostnames");
+
+        builder.danger_accept_invalid_certs(true);
+        builder.danger_accept_invalid_hostnames(true);
+    }
+
+    builder.build().map_err(From::from)
+}
+
+/// A TLS stream that can be shared between a reading and a writing thread.
+///
+/// A TLS session can't be split like a plain `TcpStream`, so both halves share
+/// the session behind a lock. Reads poll the socket with a short timeout and
+/// release the lock between polls so that writes aren't blocked until the
+/// node next sends something.
+///
+/// The lock is always released fairly, handing it to the longest waiting
+/// thread, so a reader polling in a loop can't starve a writer.
+///
+/// Until the connection is established, reads give up once the handshake
+/// deadline passes, as polling would otherwise wait on a stalled node
+/// forever.
+#[derive(Clone)]
+pub(crate) struct SharedTlsStream {
+    stream: Arc<Mutex<TlsStream<TcpStream>>>,
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+impl SharedTlsStream {
+    /// Wraps a stream whose websocket handshake must complete within the
+    /// timeout, if any.
+    pub(crate) fn new(stream: TlsStream<TcpStream>, timeout: Option<Duration>) -> Result<Self> {
+        stream.get_ref().set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS)))?;
+
+        Ok(SharedTlsStream {
+            stream: Arc::new(Mutex::new(stream)),
+            deadline: Arc::new(Mutex::new(timeout.map(|timeout| Instant::now() + timeout))),
+        })
+    }
+
+    /// Marks the connection as established, after which reads wait for data
+    /// for as long as it takes.
+    pub(crate) fn set_established(&self) {
+        *self.deadline.lock() = None;
+    }
+}
+
+impl Read for SharedTlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            let mut stream = self.stream.lock();
+            let result = stream.read(buf);
+            MutexGuard::unlock_fair(stream);
+
+            match result {
+                Err(why) => {
+                    if why.kind() != ErrorKind::WouldBlock && why.kind() != ErrorKind::TimedOut {
+                        return Err(why);
+                    }
+
+                    let deadline = *self.deadline.lock();
+
+                    if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                        return Err(IoError::new(ErrorKind::TimedOut, "the handshake timed out"));
+                    }
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Write for SharedTlsStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut stream = self.stream.lock();
+        let result = stream.write(buf);
+        MutexGuard::unlock_fair(stream);
+
+        result
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        let mut stream = self.stream.lock();
+        let result = stream.flush();
+        MutexGuard::unlock_fair(stream);
+
+        result
+    }
+}
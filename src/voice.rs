@@ -0,0 +1,275 @@
+//! Tracking who is in which voice channel from Serenity voice state updates.
+
+use parking_lot::RwLock;
+use serenity::model::guild::{Guild, Member};
+use serenity::model::voice::VoiceState;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    /// The channel each user in a voice channel is in, per guild.
+    guilds: HashMap<u64, HashMap<u64, u64>>,
+    bots: HashSet<u64>,
+}
+
+/// Tracks the voice channel of each user, to know who is listening to the
+/// bot.
+///
+/// Trackers are cheap to clone and share their state.
+#[derive(Clone, Debug)]
+pub struct VoiceTracker {
+    user_id: u64,
+    state: Arc<RwLock<TrackerState>>,
+}
+
+impl VoiceTracker {
+    /// Creates a tracker for the bot with the given user ID.
+    pub fn new(user_id: u64) -> Self {
+        Self {
+            user_id,
+            state: Arc::new(RwLock::new(TrackerState::default())),
+        }
+    }
+
+    /// The bot's user ID.
+    #[inline]
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    /// Records a voice state update received from Discord.
+    ///
+    /// Call this from `EventHandler::voice_state_update`. The voice states of
+    /// a guild received in `EventHandler::guild_create` are recorded by
+    /// [`update_guild`].
+    ///
+    /// [`update_guild`]: #method.update_guild
+    pub fn update(&self, guild_id: u64, state: &VoiceState) {
+        let mut tracker = self.state.write();
+        let guild = tracker.guilds.entry(guild_id).or_insert_with(HashMap::new);
+
+        match state.channel_id {
+            Some(channel_id) => {
+                guild.insert(state.user_id.0, channel_id.0);
+            },
+            None => {
+                guild.remove(&state.user_id.0);
+            },
+        }
+    }
+
+    /// Records whether a member is a bot, so bots don't count as listeners.
+    ///
+    /// Call this from `EventHandler::guild_member_addition`. The members of a
+    /// guild received in `EventHandler::guild_create` are recorded by
+    /// [`update_guild`].
+    ///
+    /// [`update_guild`]: #method.update_guild
+    pub fn update_member(&self, member: &Member) {
+        let (user_id, is_bot) = {
+            let user = member.user.read();
+
+            (user.id.0, user.bot)
+        };
+
+        let mut tracker = self.state.write();
+
+        if is_bot {
+            tracker.bots.insert(user_id);
+        } else {
+            tracker.bots.remove(&user_id);
+        }
+    }
+
+    /// Records the members and voice states of a guild.
+    ///
+    /// Call this from `EventHandler::guild_create`.
+    pub fn update_guild(&self, guild: &Guild) {
+        for member in guild.members.values() {
+            self.update_member(member);
+        }
+
+        for state in guild.voice_states.values() {
+            self.update(guild.id.0, state);
+        }
+    }
+
+    /// Forgets the voice states of a guild, such as when the bot is removed
+    /// from it.
+    pub fn remove_guild(&self, guild_id: u64) {
+        self.state.write().guilds.remove(&guild_id);
+    }
+
+    /// The voice channel a user is in.
+    pub fn channel_of(&self, guild_id: u64, user_id: u64) -> Option<u64> {
+        self.state.read().guilds.get(&guild_id)?.get(&user_id).cloned()
+    }
+
+    /// The voice channel the bot is in.
+    pub fn bot_channel(&self, guild_id: u64) -> Option<u64> {
+        self.channel_of(guild_id, self.user_id)
+    }
+
+    /// The users other than bots in the bot's voice channel, if the bot is
+    /// known to be in one.
+    pub fn listeners(&self, guild_id: u64) -> Option<Vec<u64>> {
+        let tracker = self.state.read();
+        let guild = tracker.guilds.get(&guild_id)?;
+        let channel_id = *guild.get(&self.user_id)?;
+
+        Some(guild
+            .iter()
+            .filter(|&(user, &channel)| {
+                channel == channel_id && *user != self.user_id && !tracker.bots.contains(user)
+            })
+            .map(|(user, _)| *user)
+            .collect())
+    }
+
+    /// Whether a user other than a bot is listening in the bot's voice
+    /// channel.
+    pub fn is_listening(&self, guild_id: u64, user_id: u64) -> bool {
+        self.listeners(guild_id).map_or(false, |listeners| listeners.contains(&user_id))
+    }
+
+    /// Whether the bot is known to be in a voice channel that no one else but
+    /// bots is in.
+    pub fn is_empty(&self, guild_id: u64) -> bool {
+        self.listeners(guild_id).map_or(false, |listeners| listeners.is_empty())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json;
+    use serenity::model::guild::Member;
+    use serenity::model::voice::VoiceState;
+    use super::VoiceTracker;
+
+    pub(crate) const BOT: u64 = 1;
+    pub(crate) const GUILD: u64 = 7;
+
+    /// The voice state of a user in a channel, or in none.
+    pub(crate) fn voice_state(user_id: u64, channel_id: Option<u64>) -> VoiceState {
+        serde_json::from_value(json!({
+            "channel_id": channel_id.map(|id| id.to_string()),
+            "deaf": false,
+            "mute": false,
+            "self_deaf": false,
+            "self_mute": false,
+            "session_id": "session",
+            "suppress": false,
+            "token": null,
+            "user_id": user_id.to_string(),
+        })).unwrap()
+    }
+
+    pub(crate) fn member(user_id: u64, bot: bool) -> Member {
+        serde_json::from_value(json!({
+            "deaf": false,
+            "guild_id": GUILD.to_string(),
+            "joined_at": null,
+            "mute": false,
+            "nick": null,
+            "roles": [],
+            "user": {
+                "id": user_id.to_string(),
+                "avatar": null,
+                "bot": bot,
+                "discriminator": "0001",
+                "username": "user",
+            },
+        })).unwrap()
+    }
+
+    /// A tracker with the bot in channel 10 along with the given users.
+    pub(crate) fn tracker(listeners: &[u64]) -> VoiceTracker {
+        let tracker = VoiceTracker::new(BOT);
+        tracker.update(GUILD, &voice_state(BOT, Some(10)));
+
+        for &user_id in listeners {
+            tracker.update(GUILD, &voice_state(user_id, Some(10)));
+        }
+
+        tracker
+    }
+
+    fn sorted_listeners(tracker: &VoiceTracker) -> Option<Vec<u64>> {
+        tracker.listeners(GUILD).map(|mut listeners| {
+            listeners.sort();
+
+            listeners
+        })
+    }
+
+    #[test]
+    fn joining_the_bot_makes_a_listener() {
+        let tracker = tracker(&[]);
+
+        assert!(tracker.is_empty(GUILD));
+
+        tracker.update(GUILD, &voice_state(2, Some(10)));
+
+        assert_eq!(tracker.channel_of(GUILD, 2), Some(10));
+        assert_eq!(sorted_listeners(&tracker), Some(vec![2]));
+        assert!(tracker.is_listening(GUILD, 2));
+        assert!(!tracker.is_empty(GUILD));
+    }
+
+    #[test]
+    fn moving_away_from_the_bot_stops_listening() {
+        let tracker = tracker(&[2, 3]);
+
+        tracker.update(GUILD, &voice_state(2, Some(11)));
+
+        assert_eq!(tracker.channel_of(GUILD, 2), Some(11));
+        assert!(!tracker.is_listening(GUILD, 2));
+        assert_eq!(sorted_listeners(&tracker), Some(vec![3]));
+
+        // the bot following them makes them listeners again
+        tracker.update(GUILD, &voice_state(BOT, Some(11)));
+
+        assert_eq!(tracker.bot_channel(GUILD), Some(11));
+        assert_eq!(sorted_listeners(&tracker), Some(vec![2]));
+    }
+
+    #[test]
+    fn leaving_forgets_the_channel() {
+        let tracker = tracker(&[2]);
+
+        tracker.update(GUILD, &voice_state(2, None));
+
+        assert_eq!(tracker.channel_of(GUILD, 2), None);
+        assert!(tracker.is_empty(GUILD));
+
+        tracker.update(GUILD, &voice_state(BOT, None));
+
+        assert_eq!(tracker.listeners(GUILD), None);
+        assert!(!tracker.is_empty(GUILD));
+    }
+
+    #[test]
+    fn bots_are_not_listeners() {
+        let tracker = tracker(&[2, 3]);
+
+        tracker.update_member(&member(3, true));
+
+        assert_eq!(sorted_listeners(&tracker), Some(vec![2]));
+        assert!(!tracker.is_listening(GUILD, 3));
+
+        tracker.update_member(&member(3, false));
+
+        assert_eq!(sorted_listeners(&tracker), Some(vec![2, 3]));
+    }
+
+    #[test]
+    fn removed_guilds_are_forgotten() {
+        let tracker = tracker(&[2]);
+
+        tracker.remove_guild(GUILD);
+
+        assert_eq!(tracker.bot_channel(GUILD), None);
+        assert_eq!(tracker.listeners(GUILD), None);
+    }
+}
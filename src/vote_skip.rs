@@ -0,0 +1,380 @@
+//! Skipping the current track once enough listeners vote for it.
+//!
+//! Votes are kept per guild for the current track, and are reset when a
+//! track starts if [`VoteSkip`] is added to the manager's [`ListenerChain`].
+//! Who is listening comes from a [`VoiceTracker`], which can be shared with
+//! the [`InactivityManager`].
+//!
+//! [`InactivityManager`]: ../inactivity/struct.InactivityManager.html
+//! [`ListenerChain`]: ../struct.ListenerChain.html
+//! [`VoiceTracker`]: ../voice/struct.VoiceTracker.html
+//! [`VoteSkip`]: struct.VoteSkip.html
+
+use parking_lot::Mutex;
+use player::AudioPlayer;
+use serenity::model::id::{RoleId, UserId};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use voice::VoiceTracker;
+use ::listener::AudioPlayerListener;
+use ::prelude::*;
+
+/// How many votes skip a track, and who can skip without voting.
+#[derive(Clone, Debug)]
+pub struct VoteSkipConfig {
+    /// The fraction of listeners that must vote to skip, above 0 and at
+    /// most 1.
+    pub threshold: f64,
+    /// The role whose members skip without voting.
+    pub dj_role: Option<RoleId>,
+}
+
+impl Default for VoteSkipConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            dj_role: None,
+        }
+    }
+}
+
+/// How far a vote to skip the current track is.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct VoteProgress {
+    /// The votes of users still listening.
+    pub votes: usize,
+    /// The votes needed to skip.
+    pub required: usize,
+    /// The users other than bots listening.
+    pub listeners: usize,
+}
+
+impl VoteProgress {
+    pub fn is_passed(&self) -> bool {
+        self.votes >= self.required
+    }
+}
+
+/// The result of voting to skip.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum VoteOutcome {
+    /// The vote was counted, but more are needed.
+    Voted(VoteProgress),
+    /// The user already voted to skip the current track.
+    AlreadyVoted(VoteProgress),
+    /// The track was skipped, either by enough votes or by a DJ.
+    Skipped {
+        by_dj: bool,
+    },
+    /// The user isn't listening in the bot's voice channel.
+    NotListening,
+    /// The player isn't playing a track.
+    NothingPlaying,
+}
+
+#[derive(Debug, Default)]
+struct GuildVotes {
+    /// The track the votes are for.
+    track: String,
+    voters: HashSet<u64>,
+}
+
+/// Counts votes to skip the current track of each guild.
+///
+/// Vote skips are cheap to clone and share their votes.
+#[derive(Clone, Debug)]
+pub struct VoteSkip {
+    tracker: VoiceTracker,
+    config: VoteSkipConfig,
+    votes: Arc<Mutex<HashMap<u64, GuildVotes>>>,
+}
+
+impl VoteSkip {
+    /// Creates a vote skip counting the listeners known to the tracker.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidThreshold`] if the config's threshold is not
+    /// above 0 and at most 1.
+    ///
+    /// [`Error::InvalidThreshold`]: ../enum.Error.html#variant.InvalidThreshold
+    pub fn new(tracker: VoiceTracker, config: VoteSkipConfig) -> Result<Self> {
+        let threshold = config.threshold;
+
+        if threshold.is_nan() || threshold <= 0f64 || threshold > 1f64 {
+            return Err(Error::InvalidThreshold(config.threshold));
+        }
+
+        Ok(Self {
+            tracker,
+            config,
+            votes: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    #[inline]
+    pub fn config(&self) -> &VoteSkipConfig {
+        &self.config
+    }
+
+    /// Votes to skip the player's current track, skipping it if the vote
+    /// passes or the user has the DJ role.
+    ///
+    /// `roles` are the roles of the voting member.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vote passed but the player could not skip.
+    pub fn vote(
+        &self,
+        player: &mut AudioPlayer,
+        user_id: UserId,
+        roles: &[RoleId],
+    ) -> Result<VoteOutcome> {
+        let track = match player.track.clone() {
+            Some(track) => track,
+            None => return Ok(VoteOutcome::NothingPlaying),
+        };
+
+        let guild_id = player.guild_id;
+        let is_dj = self.config.dj_role.map_or(false, |role| roles.contains(&role));
+
+        if is_dj {
+            self.reset(guild_id);
+            player.skip()?;
+
+            return Ok(VoteOutcome::Skipped {
+                by_dj: true,
+            });
+        }
+
+        if !self.tracker.is_listening(guild_id, user_id.0) {
+            return Ok(VoteOutcome::NotListening);
+        }
+
+        let is_new = {
+            let mut votes = self.votes.lock();
+            let guild = votes.entry(guild_id).or_insert_with(GuildVotes::default);
+
+            if guild.track != track {
+                guild.track = track.clone();
+                guild.voters.clear();
+            }
+
+            guild.voters.insert(user_id.0)
+        };
+
+        let progress = match self.progress(guild_id, &track) {
+            Some(progress) => progress,
+            None => return Ok(VoteOutcome::NotListening),
+        };
+
+        if !is_new {
+            return Ok(VoteOutcome::AlreadyVoted(progress));
+        }
+
+        if !progress.is_passed() {
+            return Ok(VoteOutcome::Voted(progress));
+        }
+
+        self.reset(guild_id);
+        player.skip()?;
+
+        Ok(VoteOutcome::Skipped {
+            by_dj: false,
+        })
+    }
+
+    /// Withdraws a user's vote to skip the track, returning whether they had
+    /// voted.
+    pub fn unvote(&self, guild_id: u64, track: &str, user_id: UserId) -> bool {
+        let mut votes = self.votes.lock();
+
+        let guild = match votes.get_mut(&guild_id) {
+            Some(guild) => guild,
+            None => return false,
+        };
+
+        guild.track == track && guild.voters.remove(&user_id.0)
+    }
+
+    /// How far the vote to skip a guild's track is, if the bot is known to
+    /// be in a voice channel.
+    ///
+    /// Only votes of users still listening count.
+    pub fn progress(&self, guild_id: u64, track: &str) -> Option<VoteProgress> {
+        let listeners = self.tracker.listeners(guild_id)?;
+
+        let votes = self.votes.lock();
+        let votes = match votes.get(&guild_id) {
+            Some(guild) if guild.track == track => listeners
+                .iter()
+                .filter(|listener| guild.voters.contains(listener))
+                .count(),
+            _ => 0,
+        };
+
+        let required = (listeners.len() as f64 * self.config.threshold).ceil() as usize;
+
+        Some(VoteProgress {
+            votes,
+            required: required.max(1),
+            listeners: listeners.len(),
+        })
+    }
+
+    /// Clears the votes of a guild.
+    pub fn reset(&self, guild_id: u64) {
+        self.votes.lock().remove(&guild_id);
+    }
+}
+
+impl AudioPlayerListener for VoteSkip {
+    fn track_start(&self, player: &mut AudioPlayer, _: &str) {
+        self.reset(player.guild_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use player::tests::player;
+    use serenity::model::id::{RoleId, UserId};
+    use voice::tests::{member, tracker, GUILD};
+    use super::*;
+
+    fn vote_skip(listeners: &[u64], threshold: f64) -> VoteSkip {
+        let config = VoteSkipConfig {
+            threshold,
+            dj_role: Some(RoleId(9)),
+        };
+
+        VoteSkip::new(tracker(listeners), config).unwrap()
+    }
+
+    fn required(listeners: &[u64], threshold: f64) -> usize {
+        vote_skip(listeners, threshold).progress(GUILD, "a").unwrap().required
+    }
+
+    #[test]
+    fn thresholds_must_be_a_fraction() {
+        for &threshold in &[0f64, -0.5, 1.5, ::std::f64::NAN] {
+            let config = VoteSkipConfig {
+                threshold,
+                dj_role: None,
+            };
+
+            match VoteSkip::new(tracker(&[]), config) {
+                Err(Error::InvalidThreshold(_)) => {},
+                other => panic!("expected an invalid threshold, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn required_votes_round_up() {
+        assert_eq!(required(&[2, 3, 4], 0.5), 2);
+        assert_eq!(required(&[2, 3, 4], 0.34), 2);
+        assert_eq!(required(&[2, 3, 4, 5], 0.5), 2);
+        assert_eq!(required(&[2, 3, 4], 1f64), 3);
+        assert_eq!(required(&[2], 0.1), 1);
+
+        // a lone bot still needs a vote
+        assert_eq!(required(&[], 0.5), 1);
+    }
+
+    #[test]
+    fn bots_neither_count_nor_vote() {
+        let vote_skip = vote_skip(&[2, 3, 4], 0.5);
+        vote_skip.tracker.update_member(&member(4, true));
+
+        let (player, _ops) = player();
+        let mut player = player.lock();
+        player.play("a", None, None).unwrap();
+
+        let progress = vote_skip.progress(GUILD, "a").unwrap();
+        assert_eq!(progress.listeners, 2);
+        assert_eq!(progress.required, 1);
+
+        assert_eq!(vote_skip.vote(&mut player, UserId(4), &[]).unwrap(), VoteOutcome::NotListening);
+    }
+
+    #[test]
+    fn enough_votes_skip_the_track() {
+        let vote_skip = vote_skip(&[2, 3, 4], 0.5);
+
+        let (player, _ops) = player();
+        let mut player = player.lock();
+        player.play("a", None, None).unwrap();
+        player.enqueue("b");
+
+        let progress = VoteProgress {
+            votes: 1,
+            required: 2,
+            listeners: 3,
+        };
+
+        assert_eq!(
+            vote_skip.vote(&mut player, UserId(2), &[]).unwrap(),
+            VoteOutcome::Voted(progress),
+        );
+        assert_eq!(
+            vote_skip.vote(&mut player, UserId(2), &[]).unwrap(),
+            VoteOutcome::AlreadyVoted(progress),
+        );
+        assert_eq!(vote_skip.vote(&mut player, UserId(5), &[]).unwrap(), VoteOutcome::NotListening);
+        assert_eq!(
+            vote_skip.vote(&mut player, UserId(3), &[]).unwrap(),
+            VoteOutcome::Skipped {
+                by_dj: false,
+            },
+        );
+
+        assert_eq!(player.track, Some("b".to_owned()));
+        assert_eq!(vote_skip.progress(GUILD, "b").unwrap().votes, 0);
+    }
+
+    #[test]
+    fn djs_skip_without_voting() {
+        let vote_skip = vote_skip(&[2, 3, 4], 1f64);
+
+        let (player, _ops) = player();
+        let mut player = player.lock();
+        player.play("a", None, None).unwrap();
+        player.enqueue("b");
+
+        vote_skip.vote(&mut player, UserId(2), &[]).unwrap();
+
+        // DJs needn't even be listening
+        assert_eq!(
+            vote_skip.vote(&mut player, UserId(5), &[RoleId(8), RoleId(9)]).unwrap(),
+            VoteOutcome::Skipped {
+                by_dj: true,
+            },
+        );
+
+        assert_eq!(player.track, Some("b".to_owned()));
+        assert_eq!(vote_skip.progress(GUILD, "a").unwrap().votes, 0);
+    }
+
+    #[test]
+    fn starting_a_track_resets_the_votes() {
+        let vote_skip = vote_skip(&[2, 3, 4], 0.5);
+
+        let (player, _ops) = player();
+        let mut player = player.lock();
+
+        assert_eq!(
+            vote_skip.vote(&mut player, UserId(2), &[]).unwrap(),
+            VoteOutcome::NothingPlaying,
+        );
+
+        player.play("a", None, None).unwrap();
+        vote_skip.vote(&mut player, UserId(2), &[]).unwrap();
+        assert_eq!(vote_skip.progress(GUILD, "a").unwrap().votes, 1);
+
+        vote_skip.track_start(&mut player, "a");
+
+        assert_eq!(vote_skip.progress(GUILD, "a").unwrap().votes, 0);
+        assert!(!vote_skip.unvote(GUILD, "a", UserId(2)));
+    }
+}
@@ -0,0 +1,112 @@
+//! Helpers shared by the tests driving nodes against the mock server.
+
+#![allow(dead_code)]
+
+use parking_lot::Mutex;
+use serenity_lavalink::inactivity::InactivityReason;
+use serenity_lavalink::lavalink::stats::RemoteStats;
+use serenity_lavalink::nodes::{Node, NodeConfig, NodeHandle, NodeManager};
+use serenity_lavalink::player::AudioPlayer;
+use serenity_lavalink::testing::MockLavalink;
+use serenity_lavalink::AudioPlayerListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long tests wait for something to happen before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A listener recording the hooks called on it, in order.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    events: Mutex<Vec<String>>,
+}
+
+impl Recorder {
+    pub fn events(&self) -> Vec<String> {
+        self.events.lock().clone()
+    }
+
+    /// Waits until a hook was recorded.
+    pub fn wait_for(&self, event: &str) -> bool {
+        wait_until(|| self.events.lock().iter().any(|recorded| recorded == event))
+    }
+
+    fn push(&self, event: String) {
+        self.events.lock().push(event);
+    }
+}
+
+impl AudioPlayerListener for Recorder {
+    fn track_start(&self, player: &mut AudioPlayer, track: &str) {
+        self.push(format!("track_start {} {}", player.guild_id, track));
+    }
+
+    fn track_end(&self, player: &mut AudioPlayer, track: &str, reason: &str) {
+        self.push(format!("track_end {} {} {}", player.guild_id, track, reason));
+    }
+
+    fn track_exception(&self, player: &mut AudioPlayer, track: &str, exception: &str) {
+        self.push(format!("track_exception {} {} {}", player.guild_id, track, exception));
+    }
+
+    fn track_stuck(&self, player: &mut AudioPlayer, track: &str, threshold: i64) {
+        self.push(format!("track_stuck {} {} {}", player.guild_id, track, threshold));
+    }
+
+    fn node_connected(&self, node: &NodeHandle) {
+        self.push(format!("node_connected {}", node.websocket_host()));
+    }
+
+    fn player_inactive(&self, player: &mut AudioPlayer, reason: InactivityReason) {
+        self.push(format!("player_inactive {} {:?}", player.guild_id, reason));
+    }
+
+    fn node_disconnected(&self, node: &NodeHandle) {
+        self.push(format!("node_disconnected {}", node.websocket_host()));
+    }
+
+    fn stats_received(&self, _node: &NodeHandle, stats: &RemoteStats) {
+        self.push(format!("stats_received {}", stats.players));
+    }
+}
+
+/// Starts a mock and a manager connected to it.
+pub fn connect() -> (MockLavalink, NodeManager, Arc<Recorder>) {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+
+    connect_to(mock, |config| config)
+}
+
+/// Connects a manager to a mock with a config adjusted for the test.
+pub fn connect_to<F>(mock: MockLavalink, adjust: F) -> (MockLavalink, NodeManager, Arc<Recorder>)
+    where F: FnOnce(NodeConfig) -> NodeConfig {
+    let recorder = Arc::new(Recorder::default());
+    let mut manager = NodeManager::new(Arc::clone(&recorder) as Arc<AudioPlayerListener>);
+
+    manager.add_node(&adjust(mock.node_config().unwrap())).unwrap();
+    assert!(mock.wait_for_connection(TIMEOUT));
+
+    (mock, manager, recorder)
+}
+
+/// The only node of a manager.
+pub fn node(manager: &NodeManager) -> Arc<Node> {
+    Arc::clone(&manager.nodes.read()[0])
+}
+
+/// Polls a condition until it holds or the timeout elapses, returning whether
+/// it held.
+pub fn wait_until<F: FnMut() -> bool>(mut done: F) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    done()
+}
@@ -0,0 +1,202 @@
+#![cfg(feature = "testing")]
+
+extern crate parking_lot;
+#[macro_use] extern crate serde_json;
+extern crate serenity_lavalink;
+
+mod common;
+
+use common::{connect, wait_until, Recorder, TIMEOUT};
+use parking_lot::Mutex;
+use serenity_lavalink::crossfade::{Crossfade, CrossfadeConfig};
+use serenity_lavalink::nodes::NodeManager;
+use serenity_lavalink::player::{AudioPlayer, QueuedTrack};
+use serenity_lavalink::testing::MockLavalink;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the current track ends, in milliseconds.
+const END: u64 = 10_000;
+
+struct Playing {
+    mock: MockLavalink,
+    manager: NodeManager,
+    recorder: Arc<Recorder>,
+    player: Arc<Mutex<AudioPlayer>>,
+    crossfade: Crossfade,
+}
+
+/// Connects to a mock and starts playing `track` on guild 7 until `END`, with
+/// a search queued after it, then preloads the search.
+fn playing() -> Playing {
+    let (mock, manager, recorder) = connect();
+
+    mock.set_load_tracks("ytsearch:next", json!({
+        "loadType": "SEARCH_RESULT",
+        "playlistInfo": {},
+        "tracks": [{
+            "track": "next",
+            "info": {
+                "identifier": "dQw4w9WgXcQ",
+                "isSeekable": true,
+                "author": "RickAstleyVEVO",
+                "length": 212000,
+                "isStream": false,
+                "position": 0,
+                "title": "Rick Astley - Never Gonna Give You Up",
+                "uri": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            },
+        }],
+    }));
+
+    let crossfade = Crossfade::new(&manager, CrossfadeConfig {
+        duration: Some(Duration::from_millis(1000)),
+        preload: Duration::from_millis(2000),
+        tick: Duration::from_millis(50),
+    });
+    crossfade.set_enabled(7, true);
+
+    let player = manager.create_player(7).unwrap();
+
+    {
+        let mut player = player.lock();
+        player.enqueue(QueuedTrack::new("ytsearch:next"));
+        player.play("track", None, Some(END)).unwrap();
+    }
+
+    assert!(mock.wait_for_op("play", TIMEOUT).is_some());
+
+    let playing = Playing { mock, manager, recorder, player, crossfade };
+
+    // close enough to the end to preload, but not to fade
+    playing.seek_to(END - 1500);
+    playing.crossfade.tick();
+
+    playing
+}
+
+impl Playing {
+    /// Has the node report the player at a position.
+    fn seek_to(&self, position: u64) {
+        let position = position as i64;
+
+        self.mock.send_player_update(7, 1_500_000_000_000, position).unwrap();
+        assert!(wait_until(|| self.player.lock().position == position));
+    }
+
+    /// The volumes sent so far.
+    fn volumes(&self) -> Vec<i64> {
+        self.mock
+            .received_ops()
+            .into_iter()
+            .filter(|op| op["op"] == "volume")
+            .filter_map(|op| op["volume"].as_i64())
+            .collect()
+    }
+
+    /// Waits until the last volume sent is the given one.
+    fn wait_for_volume(&self, volume: i64) -> bool {
+        wait_until(|| self.volumes().last() == Some(&volume))
+    }
+}
+
+#[test]
+fn searches_are_loaded_ahead_of_time() {
+    let playing = playing();
+
+    assert_eq!(playing.player.lock().queue[0].track, "next");
+    assert_eq!(
+        playing.crossfade.preloaded(7).map(|info| info.title),
+        Some("Rick Astley - Never Gonna Give You Up".to_owned()),
+    );
+    assert!(playing.volumes().is_empty());
+}
+
+#[test]
+fn auto_advance_is_on_while_enabled() {
+    let playing = playing();
+
+    assert!(playing.player.lock().auto_advance);
+
+    playing.crossfade.set_enabled(7, false);
+
+    assert!(!playing.player.lock().auto_advance);
+}
+
+#[test]
+fn fades_out_over_the_rest_of_the_track() {
+    let playing = playing();
+
+    playing.seek_to(END - 800);
+    let started = Instant::now();
+    playing.crossfade.tick();
+
+    assert!(playing.wait_for_volume(0));
+
+    let elapsed = started.elapsed();
+    let volumes = playing.volumes();
+
+    assert!(elapsed >= Duration::from_millis(500), "faded out in {:?}", elapsed);
+    assert!(volumes.len() > 1);
+    assert!(volumes.windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", volumes);
+}
+
+#[test]
+fn fades_the_next_track_in() {
+    let playing = playing();
+
+    playing.seek_to(END - 300);
+    playing.crossfade.tick();
+    assert!(playing.wait_for_volume(0));
+
+    playing.mock.clear();
+    playing.mock.send_track_end(7, "track", "FINISHED").unwrap();
+    assert!(playing.recorder.wait_for("track_end 7 track FINISHED"));
+
+    let play = playing.mock.wait_for_op("play", TIMEOUT).expect("no play op");
+    assert_eq!(play["track"], "next");
+
+    let started = Instant::now();
+    playing.crossfade.tick();
+
+    assert!(playing.wait_for_volume(100));
+
+    let volumes = playing.volumes();
+
+    assert!(started.elapsed() >= Duration::from_millis(700));
+    assert!(volumes.len() > 1);
+    assert!(volumes.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", volumes);
+}
+
+#[test]
+fn skipping_cancels_the_fade_out() {
+    let playing = playing();
+
+    playing.seek_to(END - 800);
+    playing.crossfade.tick();
+    assert!(wait_until(|| !playing.volumes().is_empty()));
+
+    playing.player.lock().skip().unwrap();
+    playing.crossfade.tick();
+
+    assert!(playing.wait_for_volume(100));
+
+    // past when the fade out would have ended
+    thread::sleep(Duration::from_millis(1000));
+
+    assert_eq!(playing.volumes().last(), Some(&100));
+    assert_eq!(playing.player.lock().volume, 100);
+    assert_eq!(playing.player.lock().track, Some("next".to_owned()));
+}
+
+#[test]
+fn does_not_keep_the_node_manager_alive() {
+    let playing = playing();
+    let Playing { manager, crossfade, .. } = playing;
+
+    assert!(manager.close());
+
+    // the ticking thread stops as soon as it finds the manager closed
+    crossfade.start().unwrap().join().unwrap();
+}
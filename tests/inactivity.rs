@@ -0,0 +1,151 @@
+#![cfg(feature = "testing")]
+
+extern crate parking_lot;
+#[macro_use] extern crate serde_json;
+extern crate serenity;
+extern crate serenity_lavalink;
+
+mod common;
+
+use common::{connect, wait_until, TIMEOUT};
+use parking_lot::Mutex;
+use serenity::model::voice::VoiceState;
+use serenity_lavalink::inactivity::{InactivityConfig, InactivityManager};
+use serenity_lavalink::voice::VoiceTracker;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// The bot's user ID, as configured on the mock.
+const BOT: u64 = 1;
+const USER: u64 = 2;
+const GUILD: u64 = 7;
+const CHANNEL: u64 = 10;
+
+fn voice_state(user_id: u64, channel_id: Option<u64>) -> VoiceState {
+    serde_json::from_value(json!({
+        "channel_id": channel_id.map(|id| id.to_string()),
+        "deaf": false,
+        "mute": false,
+        "self_deaf": false,
+        "self_mute": false,
+        "session_id": "session",
+        "suppress": false,
+        "token": null,
+        "user_id": user_id.to_string(),
+    })).unwrap()
+}
+
+fn config() -> InactivityConfig {
+    InactivityConfig {
+        timeout: Duration::from_millis(300),
+        leave_after: Duration::from_millis(300),
+        check_interval: Duration::from_millis(50),
+        ..InactivityConfig::default()
+    }
+}
+
+/// A tracker with the bot and a user in the same channel.
+fn tracker() -> VoiceTracker {
+    let tracker = VoiceTracker::new(BOT);
+    tracker.update(GUILD, &voice_state(BOT, Some(CHANNEL)));
+    tracker.update(GUILD, &voice_state(USER, Some(CHANNEL)));
+
+    tracker
+}
+
+fn pauses(ops: &[serde_json::Value]) -> Vec<bool> {
+    ops.iter()
+        .filter(|op| op["op"] == "pause")
+        .filter_map(|op| op["pause"].as_bool())
+        .collect()
+}
+
+#[test]
+fn pauses_then_leaves_empty_channels() {
+    let (mock, manager, recorder) = connect();
+    let tracker = tracker();
+    let left = Arc::new(Mutex::new(Vec::new()));
+
+    let inactivity = {
+        let left = Arc::clone(&left);
+
+        InactivityManager::new(&manager, tracker.clone(), config(), move |guild_id: u64| {
+            left.lock().push(guild_id);
+        })
+    };
+
+    let player = manager.create_player(GUILD).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    inactivity.check();
+    assert!(!player.lock().paused);
+
+    tracker.update(GUILD, &voice_state(USER, None));
+    inactivity.check();
+
+    thread::sleep(Duration::from_millis(400));
+    inactivity.check();
+
+    assert!(player.lock().paused);
+    assert!(mock.wait_for_op("pause", TIMEOUT).is_some());
+    assert!(left.lock().is_empty());
+    assert!(manager.player_manager.read().has_player(&GUILD));
+
+    thread::sleep(Duration::from_millis(400));
+    inactivity.check();
+
+    assert_eq!(*left.lock(), vec![GUILD]);
+    assert!(!manager.player_manager.read().has_player(&GUILD));
+    assert!(recorder.wait_for("player_inactive 7 EmptyChannel"));
+}
+
+#[test]
+fn resumes_and_stays_when_someone_rejoins() {
+    let (mock, manager, _recorder) = connect();
+    let tracker = tracker();
+    let left = Arc::new(Mutex::new(Vec::new()));
+
+    let inactivity = {
+        let left = Arc::clone(&left);
+
+        InactivityManager::new(&manager, tracker.clone(), config(), move |guild_id: u64| {
+            left.lock().push(guild_id);
+        })
+    };
+
+    let player = manager.create_player(GUILD).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    tracker.update(GUILD, &voice_state(USER, None));
+    inactivity.check();
+
+    thread::sleep(Duration::from_millis(400));
+    inactivity.check();
+    assert!(player.lock().paused);
+
+    tracker.update(GUILD, &voice_state(USER, Some(CHANNEL)));
+    inactivity.check();
+    assert!(!player.lock().paused);
+
+    // well past when the player would have been left
+    thread::sleep(Duration::from_millis(700));
+    inactivity.check();
+
+    assert!(left.lock().is_empty());
+    assert!(manager.player_manager.read().has_player(&GUILD));
+    assert!(!player.lock().paused);
+    assert!(wait_until(|| pauses(&mock.received_ops()) == vec![true, false]));
+}
+
+#[test]
+fn does_not_keep_the_node_manager_alive() {
+    let (_mock, manager, _recorder) = connect();
+
+    let inactivity = InactivityManager::new(&manager, tracker(), config(), |_: u64| {});
+
+    assert!(manager.close());
+
+    // the checking thread stops as soon as it finds the manager closed
+    inactivity.start().unwrap().join().unwrap();
+}
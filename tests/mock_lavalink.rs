@@ -0,0 +1,548 @@
+#![cfg(feature = "testing")]
+
+extern crate hyper;
+extern crate parking_lot;
+#[macro_use] extern crate serde_json;
+extern crate serenity_lavalink;
+
+mod common;
+
+use common::{connect, connect_to, node, wait_until, TIMEOUT};
+use hyper::status::StatusCode;
+use serenity_lavalink::autoplay::{Autoplay, SameAuthor};
+use serenity_lavalink::decoder;
+use serenity_lavalink::nodes::{Node, NodeStatus, OutgoingOp, Protocol, ReconnectPolicy};
+use serenity_lavalink::player::AudioPlayerManager;
+use serenity_lavalink::rest::{LoadType, RestClient};
+use serenity_lavalink::testing::MockLavalink;
+use serenity_lavalink::{AudioPlayerListener, Error, ListenerChain};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A track encoded by Lavaplayer, as returned by `/loadtracks`.
+const TRACK: &str = "QAAAjQIAJVJpY2sgQXN0bGV5IC0gTmV2ZXIgR29ubmEgR2l2ZSBZb3UgVXAADlJpY2tBc3RsZXlWRVZPAAAAAAADPCAAC2RRdzR3OVdnWGNRAAEAK2h0dHBzOi8vd3d3LnlvdXR1YmUuY29tL3dhdGNoP3Y9ZFF3NHc5V2dYY1EAB3lvdXR1YmUAAAAAAAAAAA==";
+
+/// The info Lavalink decodes `TRACK` into.
+fn track_info() -> serde_json::Value {
+    json!({
+        "identifier": "dQw4w9WgXcQ",
+        "isSeekable": true,
+        "author": "RickAstleyVEVO",
+        "length": 212000,
+        "isStream": false,
+        "position": 0,
+        "title": "Rick Astley - Never Gonna Give You Up",
+        "uri": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+    })
+}
+
+#[test]
+fn handshake_sends_credentials() {
+    let (mock, _manager, recorder) = connect();
+
+    let handshakes = mock.handshakes();
+
+    assert_eq!(handshakes.len(), 1);
+    assert_eq!(handshakes[0].header("Authorization"), Some("password".to_owned()));
+    assert_eq!(handshakes[0].header("User-Id"), Some("1".to_owned()));
+    assert_eq!(handshakes[0].header("Num-Shards"), Some("1".to_owned()));
+    assert_eq!(mock.rejected(), 0);
+    assert!(recorder.wait_for(&format!("node_connected {}", mock.websocket_host())));
+}
+
+#[test]
+fn handshake_with_wrong_password_is_rejected() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+
+    let mut config = mock.node_config().unwrap();
+    config.password = "wrong".to_owned();
+
+    let player_manager = Arc::new(RwLock::new(AudioPlayerManager::new(
+        Arc::new(common::Recorder::default()) as Arc<AudioPlayerListener>,
+    )));
+
+    assert!(Node::connect(&config, player_manager).is_err());
+    assert_eq!(mock.rejected(), 1);
+    assert_eq!(mock.connections(), 0);
+}
+
+#[test]
+fn resume_key_configures_resuming() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, _manager, _recorder) = connect_to(mock, |mut config| {
+        config.resume_key = Some("key".to_owned());
+        config.resume_timeout = Duration::from_secs(30);
+
+        config
+    });
+
+    assert_eq!(mock.handshakes()[0].header("Resume-Key"), Some("key".to_owned()));
+
+    let op = mock.wait_for_op("configureResuming", TIMEOUT).expect("no configureResuming op");
+
+    assert_eq!(op["key"], "key");
+    assert_eq!(op["timeout"], 30);
+}
+
+#[test]
+fn stats_update_node_state() {
+    let (mock, manager, recorder) = connect();
+    let node = node(&manager);
+
+    assert!(node.stats().is_none());
+
+    mock.send_stats(3, 2, 0.5).unwrap();
+
+    assert!(wait_until(|| node.players() == Some(3)));
+    assert_eq!(node.playing_players(), Some(2));
+    assert_eq!(node.cpu().map(|cpu| cpu.system_load), Some(0.5));
+    assert!(recorder.wait_for("stats_received 3"));
+    assert!(manager.determine_best_node().is_some());
+}
+
+#[test]
+fn player_ops_are_sent_to_the_node() {
+    let (mock, manager, _recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", Some(1000), None).unwrap();
+
+    let play = mock.wait_for_op("play", TIMEOUT).expect("no play op");
+
+    assert_eq!(play["guildId"], "7");
+    assert_eq!(play["track"], "track");
+    assert_eq!(play["startTime"], 1000);
+
+    player.lock().pause(true).unwrap();
+
+    let pause = mock.wait_for_op("pause", TIMEOUT).expect("no pause op");
+
+    assert_eq!(pause["pause"], true);
+}
+
+#[test]
+fn player_updates_set_the_position() {
+    let (mock, manager, _recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    mock.send_player_update(7, 1_500_000_000_000, 42_000).unwrap();
+
+    assert!(wait_until(|| player.lock().position == 42_000));
+    assert_eq!(player.lock().time, 1_500_000_000_000);
+}
+
+#[test]
+fn events_reach_the_player_and_listener() {
+    let (mock, manager, recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", None, None).unwrap();
+    assert!(mock.wait_for_op("play", TIMEOUT).is_some());
+
+    mock.send_track_exception(7, "track", "it broke", "COMMON").unwrap();
+    assert!(recorder.wait_for("track_exception 7 track it broke"));
+
+    mock.send_track_end(7, "track", "FINISHED").unwrap();
+    assert!(recorder.wait_for("track_end 7 track FINISHED"));
+    assert!(wait_until(|| player.lock().track.is_none()));
+
+    // stuck events for tracks that already ended still reach the listener
+    mock.send_track_stuck(7, "track", 10_000).unwrap();
+    assert!(recorder.wait_for("track_stuck 7 track 10000"));
+
+    // events for guilds without players are ignored
+    mock.send_track_end(8, "track", "FINISHED").unwrap();
+    mock.send_stats(1, 1, 0.0).unwrap();
+    assert!(recorder.wait_for("stats_received 1"));
+    assert!(!recorder.events().iter().any(|event| event.starts_with("track_end 8")));
+}
+
+#[test]
+fn load_tracks_uses_the_rest_api() {
+    let (mock, manager, _recorder) = connect();
+
+    mock.set_load_tracks("ytsearch:never gonna", json!({
+        "loadType": "SEARCH_RESULT",
+        "playlistInfo": {},
+        "tracks": [{
+            "track": "encoded",
+            "info": {
+                "identifier": "dQw4w9WgXcQ",
+                "isSeekable": true,
+                "author": "RickAstleyVEVO",
+                "length": 212000,
+                "isStream": false,
+                "position": 0,
+                "title": "Rick Astley - Never Gonna Give You Up",
+                "uri": "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            },
+        }],
+    }));
+
+    let loaded = node(&manager).load_tracks("ytsearch:never gonna").unwrap();
+
+    assert_eq!(loaded.load_type, LoadType::SearchResult);
+    assert_eq!(loaded.tracks.len(), 1);
+    assert_eq!(loaded.tracks[0].track, "encoded");
+    assert_eq!(loaded.tracks[0].info.identifier, "dQw4w9WgXcQ");
+
+    let missing = node(&manager).load_tracks("ytsearch:nothing").unwrap();
+
+    assert_eq!(missing.load_type, LoadType::NoMatches);
+    assert!(missing.tracks.is_empty());
+
+    let requests = mock.received_requests();
+
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].path, "/loadtracks?identifier=ytsearch%3Anever+gonna");
+    assert_eq!(requests[0].authorization, Some("password".to_owned()));
+}
+
+#[test]
+fn load_tracks_with_wrong_password_fails() {
+    let (mock, manager, _recorder) = connect();
+
+    let rest = {
+        let mut config = mock.node_config().unwrap();
+        config.password = "wrong".to_owned();
+
+        RestClient::new(&config).unwrap()
+    };
+
+    match rest.load_tracks("ytsearch:anything") {
+        Err(Error::UnsuccessfulRequest(_)) => {},
+        other => panic!("expected an unsuccessful request, got {:?}", other),
+    }
+
+    assert!(node(&manager).load_tracks("ytsearch:anything").is_ok());
+}
+
+#[test]
+fn node_decodes_tracks_like_the_local_decoder() {
+    let (mock, manager, _recorder) = connect();
+    mock.set_decoded_track(TRACK, track_info());
+
+    let local = decoder::decode_track(TRACK).unwrap();
+
+    assert_eq!(node(&manager).decode_track(TRACK).unwrap(), local);
+
+    let decoded = node(&manager).decode_tracks(&[TRACK, TRACK]).unwrap();
+
+    assert_eq!(decoded.len(), 2);
+    assert!(decoded.iter().all(|track| track.track == TRACK && track.info == local));
+
+    let requests = mock.received_requests();
+
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].method, "GET");
+    assert!(requests[0].path.starts_with("/decodetrack?track=QAAAjQIAJVJp"));
+    assert_eq!(requests[1].method, "POST");
+    assert_eq!(requests[1].path, "/decodetracks");
+    assert_eq!(requests[1].body, Some(json!([TRACK, TRACK])));
+}
+
+#[test]
+fn decoding_falls_back_to_the_local_decoder() {
+    let (mock, manager, _recorder) = connect();
+    let local = decoder::decode_track(TRACK).unwrap();
+
+    let statuses = [
+        StatusCode::NotFound,
+        StatusCode::MethodNotAllowed,
+        StatusCode::BadRequest,
+        StatusCode::InternalServerError,
+    ];
+
+    for &status in &statuses {
+        mock.set_decode_status(Some(status));
+        mock.clear();
+
+        assert_eq!(node(&manager).decode_track(TRACK).unwrap(), local);
+
+        let decoded = node(&manager).decode_tracks(&[TRACK]).unwrap();
+
+        assert_eq!(decoded[0].info, local);
+        assert_eq!(mock.received_requests().len(), 2);
+    }
+
+    // tracks neither the node nor the local decoder can decode still fail
+    mock.set_decode_status(None);
+
+    assert!(node(&manager).decode_track("not a track").is_err());
+}
+
+#[test]
+fn decoding_does_not_fall_back_on_other_errors() {
+    let (mock, manager, _recorder) = connect();
+    mock.set_decode_status(Some(StatusCode::ServiceUnavailable));
+
+    match node(&manager).decode_track(TRACK) {
+        Err(Error::UnsuccessfulRequest(StatusCode::ServiceUnavailable)) => {},
+        other => panic!("expected an unsuccessful request, got {:?}", other),
+    }
+
+    match node(&manager).decode_tracks(&[TRACK]) {
+        Err(Error::UnsuccessfulRequest(StatusCode::ServiceUnavailable)) => {},
+        other => panic!("expected an unsuccessful request, got {:?}", other),
+    }
+}
+
+#[test]
+fn v4_nodes_open_a_session_and_send_ops_as_requests() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, manager, recorder) = connect_to(mock, |mut config| {
+        config.protocol = Protocol::V4;
+        config.resume_key = Some("key".to_owned());
+
+        config
+    });
+
+    assert_eq!(mock.handshakes()[0].path, "/v4/websocket");
+
+    let _player = manager.create_player(7).unwrap();
+    let handle = node(&manager).handle();
+
+    match handle.send(OutgoingOp::Pause { guild_id: 7, pause: true }) {
+        Err(Error::NoSession) => {},
+        other => panic!("expected no session, got {:?}", other),
+    }
+
+    mock.send_frame(&json!({
+        "op": "ready",
+        "resumed": false,
+        "sessionId": "session",
+    })).unwrap();
+
+    assert!(wait_until(|| node(&manager).state.read().session_id == Some("session".to_owned())));
+
+    handle.send(OutgoingOp::Pause { guild_id: 7, pause: true }).unwrap();
+
+    assert!(wait_until(|| mock.received_requests().len() == 2));
+
+    let requests = mock.received_requests();
+
+    assert_eq!(requests[0].method, "PATCH");
+    assert_eq!(requests[0].path, "/v4/sessions/session");
+    assert_eq!(requests[0].body, Some(json!({ "resuming": true, "timeout": 60 })));
+    assert_eq!(requests[1].method, "PATCH");
+    assert_eq!(requests[1].path, "/v4/sessions/session/players/7");
+    assert_eq!(requests[1].body, Some(json!({ "paused": true })));
+
+    // v4 events are handled in their v3 form
+    mock.send_frame(&json!({
+        "op": "event",
+        "type": "TrackEndEvent",
+        "guildId": "7",
+        "track": {
+            "encoded": "track",
+            "info": {},
+        },
+        "reason": "loadFailed",
+    })).unwrap();
+
+    assert!(recorder.wait_for("track_end 7 track LOAD_FAILED"));
+    assert!(mock.received_ops().is_empty());
+}
+
+#[test]
+fn closing_v4_nodes_waits_for_queued_requests() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, manager, _recorder) = connect_to(mock, |mut config| {
+        config.protocol = Protocol::V4;
+
+        config
+    });
+
+    mock.send_frame(&json!({
+        "op": "ready",
+        "resumed": false,
+        "sessionId": "session",
+    })).unwrap();
+
+    assert!(wait_until(|| node(&manager).state.read().session_id == Some("session".to_owned())));
+
+    let handle = node(&manager).handle();
+    let node = manager.nodes.write().remove(0);
+    let node = match Arc::try_unwrap(node) {
+        Ok(node) => node,
+        Err(_) => panic!("the node is still shared"),
+    };
+
+    handle.send(OutgoingOp::Pause { guild_id: 7, pause: true }).unwrap();
+    node.close();
+
+    // the rest loop made the queued request before it was joined
+    let requests = mock.received_requests();
+
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].path, "/v4/sessions/session/players/7");
+}
+
+#[test]
+fn exceptions_without_a_message_keep_the_node_running() {
+    let (mock, manager, recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    mock.send_frame(&json!({
+        "op": "event",
+        "type": "TrackExceptionEvent",
+        "guildId": "7",
+        "track": "track",
+        "exception": {
+            "message": null,
+            "severity": "FAULT",
+            "cause": "java.lang.NullPointerException",
+        },
+    })).unwrap();
+
+    assert!(recorder.wait_for("track_exception 7 track unknown error"));
+
+    mock.send_stats(1, 1, 0.0).unwrap();
+    assert!(recorder.wait_for("stats_received 1"));
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_server_lets_the_manager_close() {
+    use serenity_lavalink::metrics;
+    use std::io::Read;
+
+    let (_mock, manager, _recorder) = connect();
+
+    let mut server = metrics::serve("127.0.0.1:0", &manager).unwrap();
+    let url = format!("http://{}/metrics", server.addr());
+
+    let mut body = String::new();
+    let mut response = hyper::Client::new().get(&url).send().unwrap();
+    response.read_to_string(&mut body).unwrap();
+
+    assert_eq!(response.status, StatusCode::Ok);
+    assert!(body.contains("lavalink_websocket_reconnects_total"));
+
+    assert!(manager.close());
+    server.close().unwrap();
+}
+
+#[test]
+fn autoplay_lets_the_manager_close() {
+    let (_mock, manager, recorder) = connect();
+
+    let autoplay = Autoplay::new(&manager, SameAuthor);
+    autoplay.set_enabled(7, true);
+
+    manager.player_manager.write().listener = Arc::new(
+        ListenerChain::new()
+            .with(recorder)
+            .with(Arc::new(autoplay)),
+    );
+
+    assert!(manager.close());
+}
+
+/// A reconnect policy retrying quickly, for tests.
+fn quick_reconnect(delay_ms: u64, max_attempts: Option<u32>) -> ReconnectPolicy {
+    ReconnectPolicy {
+        max_attempts,
+        initial_delay: Duration::from_millis(delay_ms),
+        max_delay: Duration::from_millis(delay_ms),
+        ..ReconnectPolicy::default()
+    }
+}
+
+#[test]
+fn reconnects_after_the_connection_is_lost() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, manager, recorder) = connect_to(mock, |mut config| {
+        config.reconnect = quick_reconnect(500, None);
+
+        config
+    });
+    let handle = node(&manager).handle();
+    let disconnected = format!("node_disconnected {}", mock.websocket_host());
+
+    mock.disconnect_all();
+
+    assert!(recorder.wait_for(&disconnected));
+    assert!(wait_until(|| handle.status() == NodeStatus::Reconnecting));
+
+    match handle.send(OutgoingOp::Pause { guild_id: 7, pause: true }) {
+        Err(Error::NodeReconnecting) => {},
+        other => panic!("expected the node to be reconnecting, got {:?}", other),
+    }
+
+    assert!(wait_until(|| mock.connections() == 2));
+    assert!(wait_until(|| handle.status() == NodeStatus::Connected));
+    assert_eq!(node(&manager).state.read().reconnects, 1);
+
+    let connected = format!("node_connected {}", mock.websocket_host());
+
+    assert!(wait_until(|| recorder.events().iter().filter(|event| **event == connected).count() == 2));
+    assert!(handle.send(OutgoingOp::Pause { guild_id: 7, pause: true }).is_ok());
+}
+
+#[test]
+fn answered_pings_keep_the_node_alive() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, manager, _recorder) = connect_to(mock, |mut config| {
+        config.heartbeat_interval = Duration::from_millis(100);
+        config.read_timeout = Some(Duration::from_millis(400));
+
+        config
+    });
+
+    thread::sleep(Duration::from_millis(1000));
+
+    assert_eq!(mock.connections(), 1);
+    assert_eq!(node(&manager).status(), NodeStatus::Connected);
+    assert!(node(&manager).last_ping_latency().is_some());
+}
+
+#[test]
+fn silent_nodes_are_considered_dead_and_reconnected_to() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, manager, recorder) = connect_to(mock, |mut config| {
+        config.heartbeat_interval = Duration::from_millis(100);
+        config.read_timeout = Some(Duration::from_millis(400));
+        config.reconnect = quick_reconnect(100, None);
+
+        config
+    });
+
+    mock.set_answer_pings(false);
+
+    assert!(recorder.wait_for(&format!("node_disconnected {}", mock.websocket_host())));
+
+    mock.set_answer_pings(true);
+
+    assert!(wait_until(|| mock.connections() >= 2));
+    assert!(wait_until(|| node(&manager).status() == NodeStatus::Connected));
+    assert!(node(&manager).state.read().reconnects >= 1);
+}
+
+#[test]
+fn gives_up_reconnecting_after_the_last_attempt() {
+    let mock = MockLavalink::start("password", "1", 1).unwrap();
+    let (mock, manager, _recorder) = connect_to(mock, |mut config| {
+        config.reconnect = quick_reconnect(50, Some(2));
+
+        config
+    });
+    let handle = node(&manager).handle();
+
+    // the mock stops accepting connections once dropped
+    drop(mock);
+
+    assert!(wait_until(|| handle.status() == NodeStatus::Dead));
+    assert!(handle.is_closed());
+
+    match handle.send(OutgoingOp::Pause { guild_id: 7, pause: true }) {
+        Err(Error::NodeClosed) => {},
+        other => panic!("expected the node to be closed, got {:?}", other),
+    }
+}
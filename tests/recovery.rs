@@ -0,0 +1,176 @@
+#![cfg(feature = "testing")]
+
+extern crate parking_lot;
+extern crate serde_json;
+extern crate serenity_lavalink;
+
+mod common;
+
+use common::{connect, wait_until, Recorder, TIMEOUT};
+use parking_lot::Mutex;
+use serenity_lavalink::nodes::NodeManager;
+use serenity_lavalink::player::{AudioPlayer, QueuedTrack};
+use serenity_lavalink::recovery::{RecoveryAction, RecoveryPolicy};
+use serenity_lavalink::testing::MockLavalink;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Connects to a mock and starts playing `track` on guild 7, with `next`
+/// queued after it.
+fn playing(
+    recovery: RecoveryPolicy,
+) -> (MockLavalink, NodeManager, Arc<Recorder>, Arc<Mutex<AudioPlayer>>) {
+    let (mock, manager, recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+
+    {
+        let mut player = player.lock();
+        player.recovery = recovery;
+        player.auto_advance = true;
+        player.enqueue(QueuedTrack::new("next"));
+        player.play("track", None, None).unwrap();
+    }
+
+    assert!(mock.wait_for_op("play", TIMEOUT).is_some());
+
+    mock.send_player_update(7, 1_500_000_000_000, 42_000).unwrap();
+    assert!(wait_until(|| player.lock().position == 42_000));
+
+    mock.clear();
+
+    (mock, manager, recorder, player)
+}
+
+fn policy(exceptions: RecoveryAction, stuck: RecoveryAction) -> RecoveryPolicy {
+    RecoveryPolicy {
+        common: exceptions,
+        suspicious: exceptions,
+        fault: exceptions,
+        stuck,
+        min_stuck_threshold: 0,
+    }
+}
+
+/// The ops received by the mock after giving in-flight ones time to arrive.
+fn ops_named(mock: &MockLavalink, op: &str) -> Vec<serde_json::Value> {
+    thread::sleep(Duration::from_millis(100));
+
+    mock.received_ops().into_iter().filter(|value| value["op"] == op).collect()
+}
+
+#[test]
+fn stuck_tracks_are_retried_from_their_position() {
+    let recovery = policy(RecoveryAction::Ignore, RecoveryAction::Retry(2));
+    let (mock, _manager, recorder, player) = playing(recovery);
+
+    mock.send_track_stuck(7, "track", 10_000).unwrap();
+    assert!(recorder.wait_for("track_stuck 7 track 10000"));
+
+    let play = mock.wait_for_op("play", TIMEOUT).expect("no play op");
+
+    assert_eq!(play["track"], "track");
+    assert_eq!(play["startTime"], 42_000);
+    assert_eq!(player.lock().queue.len(), 1);
+}
+
+#[test]
+fn retries_skip_the_track_once_the_limit_is_reached() {
+    let recovery = policy(RecoveryAction::Ignore, RecoveryAction::Retry(1));
+    let (mock, _manager, recorder, player) = playing(recovery);
+
+    mock.send_track_stuck(7, "track", 10_000).unwrap();
+    assert!(mock.wait_for_op("play", TIMEOUT).is_some());
+    mock.clear();
+
+    mock.send_track_stuck(7, "track", 10_000).unwrap();
+    assert!(wait_until(|| {
+        recorder.events().iter().filter(|event| event.starts_with("track_stuck")).count() == 2
+    }));
+
+    let play = mock.wait_for_op("play", TIMEOUT).expect("no play op");
+
+    assert_eq!(play["track"], "next");
+    assert!(play["startTime"].is_null());
+    assert!(player.lock().queue.is_empty());
+}
+
+#[test]
+fn exceptions_skip_to_the_next_track_once_ended() {
+    let recovery = policy(RecoveryAction::Skip, RecoveryAction::Ignore);
+    let (mock, _manager, recorder, player) = playing(recovery);
+
+    mock.send_track_exception(7, "track", "it broke", "COMMON").unwrap();
+    assert!(recorder.wait_for("track_exception 7 track it broke"));
+
+    // nothing happens until the node ends the track
+    assert!(ops_named(&mock, "play").is_empty());
+
+    mock.send_track_end(7, "track", "LOAD_FAILED").unwrap();
+
+    let play = mock.wait_for_op("play", TIMEOUT).expect("no play op");
+
+    assert_eq!(play["track"], "next");
+    assert_eq!(player.lock().track, Some("next".to_owned()));
+}
+
+#[test]
+fn exceptions_stop_the_player_instead_of_advancing() {
+    let recovery = policy(RecoveryAction::Stop, RecoveryAction::Ignore);
+    let (mock, _manager, recorder, player) = playing(recovery);
+
+    mock.send_track_exception(7, "track", "it broke", "FAULT").unwrap();
+    mock.send_track_end(7, "track", "LOAD_FAILED").unwrap();
+    assert!(recorder.wait_for("track_end 7 track LOAD_FAILED"));
+
+    {
+        let player = player.lock();
+
+        assert!(player.track.is_none());
+        assert_eq!(player.queue.len(), 1);
+    }
+
+    assert!(ops_named(&mock, "play").is_empty());
+}
+
+#[test]
+fn stuck_tracks_are_stopped() {
+    let recovery = policy(RecoveryAction::Ignore, RecoveryAction::Stop);
+    let (mock, _manager, _recorder, player) = playing(recovery);
+
+    mock.send_track_stuck(7, "track", 10_000).unwrap();
+
+    assert!(mock.wait_for_op("stop", TIMEOUT).is_some());
+    assert!(player.lock().track.is_none());
+    assert_eq!(player.lock().queue.len(), 1);
+}
+
+#[test]
+fn actions_depend_on_the_severity() {
+    let recovery = RecoveryPolicy {
+        common: RecoveryAction::Stop,
+        suspicious: RecoveryAction::Skip,
+        ..RecoveryPolicy::disabled()
+    };
+    let (mock, _manager, _recorder, _player) = playing(recovery);
+
+    mock.send_track_exception(7, "track", "it broke", "SUSPICIOUS").unwrap();
+    mock.send_track_end(7, "track", "LOAD_FAILED").unwrap();
+
+    let play = mock.wait_for_op("play", TIMEOUT).expect("no play op");
+
+    assert_eq!(play["track"], "next");
+}
+
+#[test]
+fn failures_are_left_to_the_listener_by_default() {
+    let (mock, _manager, recorder, player) = playing(RecoveryPolicy::default());
+
+    mock.send_track_stuck(7, "track", 10_000).unwrap();
+    assert!(recorder.wait_for("track_stuck 7 track 10000"));
+
+    assert!(ops_named(&mock, "play").is_empty());
+    assert!(ops_named(&mock, "stop").is_empty());
+    assert_eq!(player.lock().track, Some("track".to_owned()));
+}
@@ -0,0 +1,197 @@
+#![cfg(feature = "testing")]
+
+extern crate parking_lot;
+extern crate serde_json;
+extern crate serenity_lavalink;
+
+mod common;
+
+use common::{connect, wait_until, TIMEOUT};
+use serenity_lavalink::schedule::{ScheduledAction, ScheduleTrigger};
+use serenity_lavalink::Error;
+use std::thread;
+use std::time::Duration;
+
+fn after(millis: u64) -> ScheduleTrigger {
+    ScheduleTrigger::After(Duration::from_millis(millis))
+}
+
+/// The names of the ops received other than `play`.
+fn op_names(ops: &[serde_json::Value]) -> Vec<String> {
+    ops.iter()
+        .filter_map(|op| op["op"].as_str())
+        .filter(|&name| name != "play")
+        .map(|name| name.to_owned())
+        .collect()
+}
+
+#[test]
+fn delayed_actions_run_in_the_order_they_are_due() {
+    let (mock, manager, _recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    {
+        let player_manager = manager.player_manager.read();
+
+        player_manager.schedule(7, after(150), ScheduledAction::Volume(50)).unwrap();
+        player_manager.schedule(7, after(50), ScheduledAction::Pause).unwrap();
+        player_manager.schedule(7, after(250), ScheduledAction::Stop).unwrap();
+    }
+
+    assert!(mock.wait_for_op("stop", TIMEOUT).is_some());
+    assert_eq!(op_names(&mock.received_ops()), vec!["pause", "volume", "stop"]);
+
+    let player = player.lock();
+
+    assert!(player.paused);
+    assert_eq!(player.volume, 50);
+    assert!(player.track.is_none());
+    assert!(manager.player_manager.read().scheduled(7).is_empty());
+}
+
+#[test]
+fn cancelled_actions_never_run() {
+    let (mock, manager, _recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    let player_manager = manager.player_manager.read();
+    let id = player_manager.schedule(7, after(100), ScheduledAction::Stop).unwrap();
+
+    assert_eq!(player_manager.scheduled(7).len(), 1);
+    assert!(player_manager.cancel_scheduled(id));
+    assert!(!player_manager.cancel_scheduled(id));
+    assert!(player_manager.scheduled(7).is_empty());
+
+    thread::sleep(Duration::from_millis(250));
+
+    assert!(op_names(&mock.received_ops()).is_empty());
+    assert_eq!(player.lock().track, Some("track".to_owned()));
+}
+
+#[test]
+fn cancelling_all_only_cancels_the_guild() {
+    let (mock, manager, _recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+    player.lock().play("track", None, None).unwrap();
+
+    let player_manager = manager.player_manager.read();
+
+    player_manager.schedule(7, after(100), ScheduledAction::Stop).unwrap();
+    player_manager.schedule(7, after(100), ScheduledAction::Pause).unwrap();
+    player_manager.schedule(7, ScheduleTrigger::TrackEnd, ScheduledAction::Stop).unwrap();
+    let other = player_manager
+        .schedule(8, ScheduleTrigger::TrackEnd, ScheduledAction::Stop)
+        .unwrap();
+
+    assert_eq!(player_manager.cancel_all_scheduled(7), 3);
+    assert_eq!(player_manager.cancel_all_scheduled(7), 0);
+
+    let scheduled = player_manager.scheduled(8);
+    assert_eq!(scheduled.iter().map(|task| task.id).collect::<Vec<_>>(), vec![other]);
+
+    thread::sleep(Duration::from_millis(250));
+
+    assert!(op_names(&mock.received_ops()).is_empty());
+}
+
+#[test]
+fn stopping_at_the_end_of_a_track_keeps_the_queue() {
+    let (mock, manager, recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+
+    {
+        let mut player = player.lock();
+        player.auto_advance = true;
+        player.enqueue("next");
+        player.play("track", None, None).unwrap();
+    }
+
+    manager.player_manager
+        .read()
+        .schedule(7, ScheduleTrigger::TrackEnd, ScheduledAction::Stop)
+        .unwrap();
+
+    assert!(mock.wait_for_op("play", TIMEOUT).is_some());
+    mock.clear();
+
+    mock.send_track_end(7, "track", "FINISHED").unwrap();
+    assert!(recorder.wait_for("track_end 7 track FINISHED"));
+
+    {
+        let player = player.lock();
+
+        assert!(player.track.is_none());
+        assert_eq!(player.queue.len(), 1);
+    }
+
+    thread::sleep(Duration::from_millis(100));
+
+    assert!(mock.received_ops().is_empty());
+    assert!(manager.player_manager.read().scheduled(7).is_empty());
+}
+
+#[test]
+fn pausing_at_the_end_of_a_track_starts_the_next_one_paused() {
+    let (mock, manager, recorder) = connect();
+
+    let player = manager.create_player(7).unwrap();
+
+    {
+        let mut player = player.lock();
+        player.auto_advance = true;
+        player.enqueue("next");
+        player.play("track", None, None).unwrap();
+    }
+
+    manager.player_manager
+        .read()
+        .schedule(7, ScheduleTrigger::TrackEnd, ScheduledAction::Pause)
+        .unwrap();
+
+    assert!(mock.wait_for_op("play", TIMEOUT).is_some());
+    mock.clear();
+
+    mock.send_track_end(7, "track", "FINISHED").unwrap();
+    assert!(recorder.wait_for("track_end 7 track FINISHED"));
+
+    let play = mock.wait_for_op("play", TIMEOUT).expect("no play op");
+    assert_eq!(play["track"], "next");
+
+    assert!(wait_until(|| {
+        let names = mock.received_ops()
+            .iter()
+            .filter_map(|op| op["op"].as_str().map(|name| name.to_owned()))
+            .collect::<Vec<_>>();
+
+        names == vec!["pause", "play"]
+    }));
+
+    let player = player.lock();
+
+    assert!(player.paused);
+    assert_eq!(player.track, Some("next".to_owned()));
+}
+
+#[test]
+fn invalid_actions_are_not_scheduled() {
+    let (_mock, manager, _recorder) = connect();
+    let player_manager = manager.player_manager.read();
+
+    match player_manager.schedule(7, after(100), ScheduledAction::Volume(1001)) {
+        Err(Error::InvalidVolume(1001)) => {},
+        other => panic!("expected an invalid volume, got {:?}", other),
+    }
+
+    match player_manager.schedule(7, ScheduleTrigger::TrackEnd, ScheduledAction::Leave) {
+        Err(Error::NoVoiceLeaver) => {},
+        other => panic!("expected no voice leaver, got {:?}", other),
+    }
+
+    assert!(player_manager.scheduled(7).is_empty());
+}